@@ -73,6 +73,7 @@ sudo cp share/tiny-dfr/* /usr/share/tiny-dfr/
 sudo cp etc/systemd/system/suspend-fix-t2.service /etc/systemd/system/
 sudo cp etc/systemd/system/tiny-dfr.service /etc/systemd/system/
 sudo install -Dm755 bin/tiny-dfr-terminal-exec /usr/bin/tiny-dfr-terminal-exec
+sudo /usr/bin/tiny-dfr schema --out /usr/share/tiny-dfr/schema >/dev/null
 
 # Install udev rules
 sudo cp etc/udev/rules.d/99-touchbar-seat.rules /etc/udev/rules.d/
@@ -0,0 +1,424 @@
+use crate::shared_state::{keep_running, SharedState};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::fd::BorrowedFd;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Just enough of the PulseAudio native protocol, which pipewire-pulse speaks too, to
+// follow the default sink and source and set the sink volume
+const COMMAND_ERROR: u32 = 0;
+const COMMAND_REPLY: u32 = 2;
+const COMMAND_AUTH: u32 = 8;
+const COMMAND_SET_CLIENT_NAME: u32 = 9;
+const COMMAND_GET_SINK_INFO: u32 = 21;
+const COMMAND_GET_SOURCE_INFO: u32 = 22;
+const COMMAND_SUBSCRIBE: u32 = 35;
+const COMMAND_SET_SINK_VOLUME: u32 = 36;
+const COMMAND_SUBSCRIBE_EVENT: u32 = 66;
+const PROTOCOL_VERSION: u32 = 32;
+// Sinks, sources and the server (default device changes)
+const SUBSCRIPTION_MASK: u32 = 0x0001 | 0x0002 | 0x0080;
+const INVALID_INDEX: u32 = u32::MAX;
+const VOLUME_NORM: u32 = 0x10000;
+const CONTROL_CHANNEL: u32 = u32::MAX;
+
+const TAG_STRING: u8 = b't';
+const TAG_STRING_NULL: u8 = b'N';
+const TAG_U32: u8 = b'L';
+const TAG_SAMPLE_SPEC: u8 = b'a';
+const TAG_ARBITRARY: u8 = b'x';
+const TAG_BOOLEAN_TRUE: u8 = b'1';
+const TAG_BOOLEAN_FALSE: u8 = b'0';
+const TAG_CHANNEL_MAP: u8 = b'm';
+const TAG_CVOLUME: u8 = b'v';
+const TAG_PROPLIST: u8 = b'P';
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AudioState {
+    pub connected: bool,
+    /// Default sink volume in percent, the loudest channel
+    pub volume: u32,
+    pub muted: bool,
+    pub mic_muted: bool,
+    sink_index: u32,
+    channels: u8,
+}
+
+// Global audio state
+static AUDIO_STATE: std::sync::LazyLock<SharedState<AudioState>> = std::sync::LazyLock::new(SharedState::default);
+
+// Volume the slider asked for, not yet sent
+static REQUESTED_VOLUME: Mutex<Option<u32>> = Mutex::new(None);
+
+// Started by the first button that shows the audio state; nobody connects before that
+static MONITOR: OnceLock<AudioMonitor> = OnceLock::new();
+
+/// Follows the desktop user's sound server and reconnects when it restarts.
+pub struct AudioMonitor {
+    _handle: thread::JoinHandle<()>,
+}
+
+impl AudioMonitor {
+    pub fn new() -> Self {
+        let handle = thread::spawn(move || {
+            Self::monitor_loop();
+        });
+
+        AudioMonitor { _handle: handle }
+    }
+
+    fn monitor_loop() {
+        keep_running("Sound server", Duration::from_secs(5), session, || {
+            AUDIO_STATE.update(|state| state.connected = false)
+        });
+    }
+}
+
+// Public API
+pub fn watch() {
+    MONITOR.get_or_init(AudioMonitor::new);
+}
+
+pub fn current() -> AudioState {
+    AUDIO_STATE.current()
+}
+
+pub fn wake_fd() -> BorrowedFd<'static> {
+    AUDIO_STATE.wake_fd()
+}
+
+/// Clears the wake fd; true if the state changed since the last call.
+pub fn take_changed() -> bool {
+    AUDIO_STATE.take_changed()
+}
+
+/// Sets the default sink volume in percent, showing it right away.
+pub fn set_volume(percent: u32) {
+    if let Ok(mut requested) = REQUESTED_VOLUME.lock() {
+        *requested = Some(percent);
+    }
+    AUDIO_STATE.update(|state| state.volume = percent);
+}
+
+fn session() -> io::Result<()> {
+    let env = crate::user_cache::get_cached_user_environment().ok_or_else(|| io::Error::other("no desktop user"))?;
+    let mut conn = Connection::connect(&Path::new(&env.runtime_dir).join("pulse/native"))?;
+    // pipewire-pulse ignores the cookie, PulseAudio wants the user's
+    let mut cookie = fs::read(format!("{}/.config/pulse/cookie", env.home_dir)).unwrap_or_default();
+    cookie.resize(256, 0);
+    conn.request(COMMAND_AUTH, |t| {
+        t.u32(PROTOCOL_VERSION);
+        t.arbitrary(&cookie);
+    })?;
+    conn.request(COMMAND_SET_CLIENT_NAME, |t| t.proplist(&[("application.name", "tiny-dfr")]))?;
+    conn.request(COMMAND_SUBSCRIBE, |t| t.u32(SUBSCRIPTION_MASK))?;
+
+    let mut stale = true;
+    loop {
+        if std::mem::take(&mut stale) {
+            let sink = conn.request(COMMAND_GET_SINK_INFO, |t| {
+                t.u32(INVALID_INDEX);
+                t.string(Some("@DEFAULT_SINK@"));
+            })?;
+            let sink = DeviceInfo::parse(sink)?;
+            let source = conn.request(COMMAND_GET_SOURCE_INFO, |t| {
+                t.u32(INVALID_INDEX);
+                t.string(Some("@DEFAULT_SOURCE@"));
+            });
+            // A machine without a microphone has no default source
+            let mic_muted = source.and_then(DeviceInfo::parse).is_ok_and(|source| source.muted);
+            AUDIO_STATE.update(|state| {
+                state.connected = true;
+                state.volume = sink.volume_percent();
+                state.muted = sink.muted;
+                state.mic_muted = mic_muted;
+                state.sink_index = sink.index;
+                state.channels = sink.channels;
+            });
+        }
+
+        let requested = REQUESTED_VOLUME.lock().ok().and_then(|mut requested| requested.take());
+        if let Some(percent) = requested {
+            let state = current();
+            let volume = (percent.min(150) as u64 * VOLUME_NORM as u64 / 100) as u32;
+            conn.request(COMMAND_SET_SINK_VOLUME, |t| {
+                t.u32(state.sink_index);
+                t.string(None);
+                t.cvolume(&vec![volume; state.channels.max(1) as usize]);
+            })?;
+        }
+
+        conn.poll()?;
+        stale |= std::mem::take(&mut conn.event);
+    }
+}
+
+struct DeviceInfo {
+    index: u32,
+    channels: u8,
+    volumes: Vec<u32>,
+    muted: bool,
+}
+
+impl DeviceInfo {
+    // Sink and source info replies start alike: index, name, description, sample spec,
+    // channel map, owner module, volume, mute
+    fn parse(mut reply: TagReader) -> io::Result<DeviceInfo> {
+        let index = reply.u32()?;
+        reply.string()?;
+        reply.string()?;
+        let channels = reply.sample_spec()?;
+        reply.channel_map()?;
+        reply.u32()?;
+        let volumes = reply.cvolume()?;
+        let muted = reply.boolean()?;
+        Ok(DeviceInfo { index, channels, volumes, muted })
+    }
+
+    fn volume_percent(&self) -> u32 {
+        let loudest = self.volumes.iter().copied().max().unwrap_or(0) as u64;
+        ((loudest * 100 + VOLUME_NORM as u64 / 2) / VOLUME_NORM as u64) as u32
+    }
+}
+
+struct Connection {
+    stream: UnixStream,
+    buf: Vec<u8>,
+    tag: u32,
+    // A subscription event arrived
+    event: bool,
+}
+
+impl Connection {
+    fn connect(path: &Path) -> io::Result<Connection> {
+        let stream = UnixStream::connect(path)?;
+        stream.set_read_timeout(Some(Duration::from_millis(100)))?;
+        Ok(Connection { stream, buf: Vec::new(), tag: 0, event: false })
+    }
+
+    // Sends a command and waits for its reply, noting events that come first
+    fn request(&mut self, command: u32, build: impl FnOnce(&mut TagWriter)) -> io::Result<TagReader> {
+        self.tag += 1;
+        let mut t = TagWriter::default();
+        t.u32(command);
+        t.u32(self.tag);
+        build(&mut t);
+        self.stream.write_all(&frame(&t.0))?;
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            let Some(mut reply) = self.next_packet()? else {
+                continue;
+            };
+            match (reply.u32()?, reply.u32()?) {
+                (COMMAND_REPLY, tag) if tag == self.tag => return Ok(reply),
+                (COMMAND_ERROR, tag) if tag == self.tag => {
+                    return Err(io::Error::other(format!("command {} failed with error {}", command, reply.u32()?)));
+                }
+                (COMMAND_SUBSCRIBE_EVENT, _) => self.event = true,
+                _ => {}
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::TimedOut, format!("no reply to command {}", command)))
+    }
+
+    // Waits briefly for events
+    fn poll(&mut self) -> io::Result<()> {
+        while let Some(mut packet) = self.next_packet()? {
+            if packet.u32()? == COMMAND_SUBSCRIBE_EVENT {
+                self.event = true;
+            }
+        }
+        Ok(())
+    }
+
+    // The next control packet, or None once the read times out
+    fn next_packet(&mut self) -> io::Result<Option<TagReader>> {
+        loop {
+            if self.buf.len() >= 20 {
+                let len = u32::from_be_bytes(self.buf[0..4].try_into().unwrap()) as usize;
+                let channel = u32::from_be_bytes(self.buf[4..8].try_into().unwrap());
+                if self.buf.len() >= 20 + len {
+                    let packet: Vec<u8> = self.buf.drain(..20 + len).skip(20).collect();
+                    // Audio data for streams we never open
+                    if channel != CONTROL_CHANNEL {
+                        continue;
+                    }
+                    return Ok(Some(TagReader { data: packet, pos: 0 }));
+                }
+            }
+            let mut chunk = [0u8; 4096];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+// Packet descriptor: length, channel, offset high and low, flags
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(20 + payload.len());
+    for word in [payload.len() as u32, CONTROL_CHANNEL, 0, 0, 0] {
+        packet.extend_from_slice(&word.to_be_bytes());
+    }
+    packet.extend_from_slice(payload);
+    packet
+}
+
+#[derive(Default)]
+struct TagWriter(Vec<u8>);
+
+impl TagWriter {
+    fn u32(&mut self, v: u32) {
+        self.0.push(TAG_U32);
+        self.0.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn string(&mut self, s: Option<&str>) {
+        match s {
+            Some(s) => {
+                self.0.push(TAG_STRING);
+                self.0.extend_from_slice(s.as_bytes());
+                self.0.push(0);
+            }
+            None => self.0.push(TAG_STRING_NULL),
+        }
+    }
+
+    fn arbitrary(&mut self, bytes: &[u8]) {
+        self.0.push(TAG_ARBITRARY);
+        self.0.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn proplist(&mut self, props: &[(&str, &str)]) {
+        self.0.push(TAG_PROPLIST);
+        for (key, value) in props {
+            self.string(Some(key));
+            let value = [value.as_bytes(), &[0]].concat();
+            self.u32(value.len() as u32);
+            self.arbitrary(&value);
+        }
+        self.string(None);
+    }
+
+    fn cvolume(&mut self, volumes: &[u32]) {
+        self.0.push(TAG_CVOLUME);
+        self.0.push(volumes.len() as u8);
+        for v in volumes {
+            self.0.extend_from_slice(&v.to_be_bytes());
+        }
+    }
+}
+
+struct TagReader {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed reply")
+}
+
+impl TagReader {
+    fn take(&mut self, n: usize) -> io::Result<&[u8]> {
+        let bytes = self.data.get(self.pos..self.pos + n).ok_or_else(malformed)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn expect(&mut self, tag: u8) -> io::Result<()> {
+        if self.take(1)?[0] == tag {
+            Ok(())
+        } else {
+            Err(malformed())
+        }
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        self.expect(TAG_U32)?;
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> io::Result<Option<String>> {
+        match self.take(1)?[0] {
+            TAG_STRING_NULL => Ok(None),
+            TAG_STRING => {
+                let len = self.data[self.pos..].iter().position(|&b| b == 0).ok_or_else(malformed)?;
+                let s = String::from_utf8_lossy(self.take(len)?).to_string();
+                self.pos += 1;
+                Ok(Some(s))
+            }
+            _ => Err(malformed()),
+        }
+    }
+
+    fn boolean(&mut self) -> io::Result<bool> {
+        match self.take(1)?[0] {
+            TAG_BOOLEAN_TRUE => Ok(true),
+            TAG_BOOLEAN_FALSE => Ok(false),
+            _ => Err(malformed()),
+        }
+    }
+
+    // Format, channels and rate; returns the channel count
+    fn sample_spec(&mut self) -> io::Result<u8> {
+        self.expect(TAG_SAMPLE_SPEC)?;
+        let spec = self.take(6)?;
+        Ok(spec[1])
+    }
+
+    fn channel_map(&mut self) -> io::Result<()> {
+        self.expect(TAG_CHANNEL_MAP)?;
+        let channels = self.take(1)?[0] as usize;
+        self.take(channels)?;
+        Ok(())
+    }
+
+    fn cvolume(&mut self) -> io::Result<Vec<u32>> {
+        self.expect(TAG_CVOLUME)?;
+        let channels = self.take(1)?[0] as usize;
+        let bytes = self.take(channels * 4)?;
+        Ok(bytes.chunks(4).map(|c| u32::from_be_bytes(c.try_into().unwrap())).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sink_info() {
+        // A sink info reply as the server would send it
+        let mut t = TagWriter::default();
+        t.u32(COMMAND_REPLY);
+        t.u32(7);
+        t.u32(42);
+        t.string(Some("alsa_output.pci"));
+        t.string(None);
+        t.0.extend_from_slice(&[TAG_SAMPLE_SPEC, 3, 2, 0, 0, 0xbb, 0x80]);
+        t.0.extend_from_slice(&[TAG_CHANNEL_MAP, 2, 1, 2]);
+        t.u32(3);
+        t.cvolume(&[VOLUME_NORM / 2, VOLUME_NORM * 3 / 4]);
+        t.0.push(TAG_BOOLEAN_TRUE);
+        t.u32(0);
+
+        let packet = frame(&t.0);
+        assert_eq!(u32::from_be_bytes(packet[0..4].try_into().unwrap()) as usize, t.0.len());
+        let mut reply = TagReader { data: packet[20..].to_vec(), pos: 0 };
+        assert_eq!((reply.u32().unwrap(), reply.u32().unwrap()), (COMMAND_REPLY, 7));
+        let sink = DeviceInfo::parse(reply).unwrap();
+        assert_eq!((sink.index, sink.channels, sink.muted), (42, 2, true));
+        assert_eq!(sink.volume_percent(), 75);
+
+        let mut bad = TagReader { data: vec![TAG_STRING, b'x'], pos: 0 };
+        assert!(bad.string().is_err());
+    }
+}
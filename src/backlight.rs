@@ -188,6 +188,10 @@ impl BacklightManager {
             }
         }
     }
+    /// Brings a dimmed or dark Touch Bar back up as if it had been touched.
+    pub fn wake(&mut self) {
+        self.last_active = Instant::now();
+    }
     pub fn current_bl(&self) -> u32 {
         self.current_bl
     }
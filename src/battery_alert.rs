@@ -0,0 +1,123 @@
+use crate::battery_monitor::{BatteryInfo, BatteryState};
+use std::time::{Duration, Instant};
+
+// The notice alternates between two shades this often
+pub const FLASH_INTERVAL: Duration = Duration::from_millis(700);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlertChange {
+    None,
+    Raised,
+    Cleared,
+}
+
+/// The full-width notice shown while the battery is critical, until tapped away.
+#[derive(Default)]
+pub struct BatteryAlert {
+    // When the battery went critical, while it still is
+    raised_at: Option<Instant>,
+    dismissed: bool,
+    drawn_phase: Option<bool>,
+}
+
+impl BatteryAlert {
+    /// Follows the battery state. An alert is raised once each time the battery turns
+    /// critical, and a dismissed one stays away until it has recovered.
+    pub fn update(&mut self, info: Option<&BatteryInfo>) -> AlertChange {
+        let critical = info.is_some_and(|info| info.state == BatteryState::Critical);
+        match (critical, self.raised_at.is_some()) {
+            (true, false) => {
+                self.raised_at = Some(Instant::now());
+                self.dismissed = false;
+                self.drawn_phase = None;
+                AlertChange::Raised
+            }
+            (false, true) => {
+                let was_shown = self.is_shown();
+                self.raised_at = None;
+                if was_shown {
+                    AlertChange::Cleared
+                } else {
+                    AlertChange::None
+                }
+            }
+            _ => AlertChange::None,
+        }
+    }
+
+    pub fn is_shown(&self) -> bool {
+        self.raised_at.is_some() && !self.dismissed
+    }
+
+    pub fn dismiss(&mut self) {
+        self.dismissed = true;
+    }
+
+    /// Which of the two flash shades to draw now.
+    pub fn phase(&self) -> bool {
+        self.raised_at
+            .is_some_and(|raised| (raised.elapsed().as_millis() / FLASH_INTERVAL.as_millis()).is_multiple_of(2))
+    }
+
+    /// True when the shade moved on since the last draw, which this records.
+    pub fn needs_redraw(&mut self) -> bool {
+        let phase = self.phase();
+        self.drawn_phase.replace(phase) != Some(phase)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::battery_monitor::{read_power_supply, BatteryThresholds};
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_battery_alert() {
+        let root = TempDir::new("alert");
+        root.write("BAT0/type", "Battery\n");
+        let set = |status: &str, capacity: &str| {
+            root.write("BAT0/status", status);
+            root.write("BAT0/capacity", capacity);
+            read_power_supply(&root, BatteryThresholds { low: 20, critical: 8 })
+        };
+
+        let mut alert = BatteryAlert::default();
+        assert_eq!(alert.update(set("Discharging", "9").as_ref()), AlertChange::None);
+        assert_eq!(alert.update(set("Discharging", "8").as_ref()), AlertChange::Raised);
+        assert!(alert.is_shown() && alert.needs_redraw() && !alert.needs_redraw());
+        assert_eq!(alert.update(set("Discharging", "7").as_ref()), AlertChange::None);
+
+        // Dismissed alerts stay away until the battery recovers and drops again
+        alert.dismiss();
+        assert!(!alert.is_shown());
+        assert_eq!(alert.update(set("Discharging", "6").as_ref()), AlertChange::None);
+        assert_eq!(alert.update(set("Charging", "6").as_ref()), AlertChange::None);
+        assert_eq!(alert.update(set("Discharging", "6").as_ref()), AlertChange::Raised);
+        assert_eq!(alert.update(set("Charging", "6").as_ref()), AlertChange::Cleared);
+    }
+
+    #[test]
+    fn test_battery_alert_without_readings() {
+        let root = TempDir::new("alert-errors");
+        root.write("BAT0/type", "Battery\n");
+        root.write("BAT0/status", "Discharging");
+        root.write("BAT0/capacity", "5");
+        let read = || read_power_supply(&root, BatteryThresholds { low: 20, critical: 8 });
+
+        // Dismissing with nothing raised is harmless
+        let mut alert = BatteryAlert::default();
+        alert.dismiss();
+        assert_eq!(alert.update(read().as_ref()), AlertChange::Raised);
+        assert!(alert.is_shown());
+
+        // A garbled reading is not critical, nor is a battery that vanished
+        root.write("BAT0/capacity", "??");
+        assert_eq!(alert.update(read().as_ref()), AlertChange::Cleared);
+        root.write("BAT0/capacity", "5");
+        assert_eq!(alert.update(read().as_ref()), AlertChange::Raised);
+        std::fs::remove_dir_all(root.join("BAT0")).unwrap();
+        assert_eq!(alert.update(read().as_ref()), AlertChange::Cleared);
+        assert!(!alert.is_shown() && !alert.phase());
+    }
+}
@@ -1,133 +1,409 @@
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Duration;
+
+const POWER_SUPPLY_PATH: &str = "/sys/class/power_supply";
+
+// The sysfs attributes the battery widget reads; udev events carry the same ones as
+// POWER_SUPPLY_<NAME> properties
+const ATTRIBUTES: &[&str] = &[
+    "type", "scope", "status", "online", "capacity", "capacity_level",
+    "energy_now", "energy_full", "power_now", "charge_now", "charge_full", "current_now",
+    "voltage_now",
+];
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BatteryState {
     NotCharging,
     Charging,
     Low,
+    Critical,
+}
+
+/// Charge levels, in percent, at or below which a discharging battery is low or critical.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatteryThresholds {
+    pub low: u32,
+    pub critical: u32,
+}
+
+impl Default for BatteryThresholds {
+    fn default() -> Self {
+        BatteryThresholds { low: 10, critical: 5 }
+    }
 }
 
-#[derive(Debug, Clone)]
+/// All system batteries taken together.
+#[derive(Debug, Clone, PartialEq)]
 pub struct BatteryInfo {
     pub capacity: u32,
     pub state: BatteryState,
-    pub last_updated: Instant,
+    /// Whether a mains supply is online; None on machines without one
+    pub on_ac: Option<bool>,
+    /// Time to empty while discharging, or to full while charging
+    pub time_remaining: Option<Duration>,
+    /// The kernel's coarse level ("Normal", "Low", "Critical", ...), lowest of all batteries
+    pub capacity_level: Option<String>,
+    /// No battery reports a charge, so `capacity` is only guessed from `capacity_level`
+    pub level_only: bool,
 }
 
 impl BatteryInfo {
-    fn new() -> Self {
-        BatteryInfo {
-            capacity: 100,
-            state: BatteryState::NotCharging,
-            last_updated: Instant::now(),
+    /// The charge as shown to the user: a percentage, or the kernel's level when that's all there is.
+    pub fn charge_text(&self) -> String {
+        match &self.capacity_level {
+            Some(level) if self.level_only => level.clone(),
+            _ => format!("{}%", self.capacity),
         }
     }
 }
 
-// Global battery state
-static BATTERY_STATE: std::sync::LazyLock<Arc<Mutex<Option<BatteryInfo>>>> =
-    std::sync::LazyLock::new(|| Arc::new(Mutex::new(None)));
+// Attributes of one supply by sysfs name, e.g. "status" => "Charging"
+type Attributes = HashMap<String, String>;
 
-pub struct BatteryMonitor {
-    _handle: thread::JoinHandle<()>,
+#[derive(Default)]
+struct PowerSupplies {
+    // By device name ("BAT0", "ADP1")
+    supplies: BTreeMap<String, Attributes>,
+    thresholds: BatteryThresholds,
+    info: Option<BatteryInfo>,
+    scanned: bool,
 }
 
-impl BatteryMonitor {
-    pub fn new(battery_name: String) -> Self {
-        let handle = thread::spawn(move || {
-            Self::monitor_loop(&battery_name);
-        });
+impl PowerSupplies {
+    fn scan(&mut self, power_supply: &Path) {
+        self.supplies = read_supplies(power_supply);
+        self.scanned = true;
+        self.update();
+    }
 
-        BatteryMonitor { _handle: handle }
+    // Re-aggregates; true if what the widget shows changed
+    fn update(&mut self) -> bool {
+        let info = aggregate(self.supplies.values(), self.thresholds);
+        let changed = info != self.info;
+        self.info = info;
+        changed
     }
 
-    fn monitor_loop(battery_name: &str) {
-        let mut last_update = Instant::now();
-        let update_interval = Duration::from_secs(30); // Update every 30 seconds
+    // Applies a uevent for supply `name`: its POWER_SUPPLY_* properties replace the
+    // attributes we had, and "remove" drops it
+    fn apply_event<'a>(&mut self, name: &str, action: &str, properties: impl Iterator<Item = (&'a str, &'a str)>) -> bool {
+        if action == "remove" {
+            self.supplies.remove(name);
+        } else {
+            let attributes: Attributes = properties
+                .filter_map(|(key, value)| Some((key.strip_prefix("POWER_SUPPLY_")?.to_ascii_lowercase(), value.to_string())))
+                .filter(|(key, _)| ATTRIBUTES.contains(&key.as_str()))
+                .collect();
+            let supply = self.supplies.entry(name.to_string()).or_default();
+            // Older kernels leave the type out of uevents
+            let kind = supply.remove("type");
+            *supply = attributes;
+            if let Some(kind) = kind {
+                supply.entry("type".into()).or_insert(kind);
+            }
+        }
+        self.update()
+    }
+}
 
-        loop {
-            if last_update.elapsed() >= update_interval {
-                let battery_info = Self::read_battery_state(battery_name);
+// Global battery state, kept current by power_supply uevents
+static BATTERY_STATE: std::sync::LazyLock<Arc<Mutex<PowerSupplies>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(PowerSupplies::default())));
 
-                if let Ok(mut state) = BATTERY_STATE.lock() {
-                    *state = Some(battery_info);
-                }
+// Public API
+pub fn get_cached_battery_state() -> Option<BatteryInfo> {
+    let mut state = BATTERY_STATE.lock().ok()?;
+    if !state.scanned {
+        state.scan(Path::new(POWER_SUPPLY_PATH));
+    }
+    state.info.clone()
+}
 
-                last_update = Instant::now();
-            }
+/// True on mains power, or when there is no mains supply to ask (desktops).
+pub fn on_ac() -> bool {
+    get_cached_battery_state().and_then(|info| info.on_ac).unwrap_or(true)
+}
 
-            thread::sleep(Duration::from_secs(1));
-        }
+/// Re-reads sysfs, for batteries whose charge drifts without uevents; true if anything changed.
+pub fn rescan() -> bool {
+    let Ok(mut state) = BATTERY_STATE.lock() else {
+        return false;
+    };
+    let before = state.info.clone();
+    state.scan(Path::new(POWER_SUPPLY_PATH));
+    state.info != before
+}
+
+/// Updates the state from a power_supply uevent; true if anything changed.
+pub fn handle_event(event: &udev::Event) -> bool {
+    let action = event.action().and_then(|a| a.to_str()).unwrap_or("change");
+    let name = event.sysname().to_string_lossy();
+    let properties: Vec<(String, String)> = event
+        .properties()
+        .map(|p| (p.name().to_string_lossy().into_owned(), p.value().to_string_lossy().into_owned()))
+        .collect();
+    let Ok(mut state) = BATTERY_STATE.lock() else {
+        return false;
+    };
+    if !state.scanned {
+        state.scan(Path::new(POWER_SUPPLY_PATH));
+        return true;
     }
+    state.apply_event(&name, action, properties.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+}
 
-    fn read_battery_state(battery: &str) -> BatteryInfo {
-        let status_path = format!("/sys/class/power_supply/{}/status", battery);
-        let status = fs::read_to_string(&status_path)
-            .unwrap_or_else(|_| "Unknown".to_string());
+pub fn set_thresholds(thresholds: BatteryThresholds) {
+    if let Ok(mut state) = BATTERY_STATE.lock() {
+        state.thresholds = thresholds;
+        state.update();
+    }
+}
 
-        let capacity = Self::read_capacity(battery);
+fn read_supplies(power_supply: &Path) -> BTreeMap<String, Attributes> {
+    let Ok(entries) = fs::read_dir(power_supply) else {
+        return BTreeMap::new();
+    };
+    entries
+        .flatten()
+        .map(|entry| {
+            let dir = entry.path();
+            let attributes = ATTRIBUTES
+                .iter()
+                .filter_map(|name| Some((name.to_string(), fs::read_to_string(dir.join(name)).ok()?.trim().to_string())))
+                .collect();
+            (entry.file_name().to_string_lossy().into_owned(), attributes)
+        })
+        .collect()
+}
 
-        let state = match status.trim() {
-            "Charging" | "Full" => BatteryState::Charging,
-            "Discharging" if capacity < 10 => BatteryState::Low,
-            _ => BatteryState::NotCharging,
-        };
+// Lower is more urgent
+fn level_rank(level: &str) -> u8 {
+    match level {
+        "Critical" => 0,
+        "Low" => 1,
+        "Normal" => 2,
+        "High" => 3,
+        "Full" => 4,
+        _ => 5,
+    }
+}
 
-        BatteryInfo {
-            capacity,
-            state,
-            last_updated: Instant::now(),
+/// Reads a fake sysfs power_supply tree the way the live state is built.
+#[cfg(test)]
+pub(crate) fn read_power_supply(power_supply: &Path, thresholds: BatteryThresholds) -> Option<BatteryInfo> {
+    aggregate(read_supplies(power_supply).values(), thresholds)
+}
+
+// All batteries and mains supplies taken together; None without batteries
+fn aggregate<'a>(supplies: impl Iterator<Item = &'a Attributes>, thresholds: BatteryThresholds) -> Option<BatteryInfo> {
+    let mut on_ac = None;
+    let mut statuses = Vec::new();
+    // Charge of each battery in percent, and its size in µWh where known
+    let mut batteries: Vec<(f64, Option<f64>)> = Vec::new();
+    let mut capacity_level: Option<String> = None;
+    // Summed energy in µWh and draw in µW of the batteries with a known size
+    let (mut now, mut full, mut rate) = (0.0, 0.0, 0.0);
+    for supply in supplies {
+        let text = |name: &str| supply.get(name).map(String::as_str);
+        let value = |name: &str| supply.get(name).and_then(|v| v.parse::<f64>().ok());
+        match text("type") {
+            Some("Mains") => {
+                let online = value("online").is_some_and(|o| o > 0.0);
+                on_ac = Some(on_ac.unwrap_or(false) || online);
+            }
+            // Peripheral batteries (mice, keyboards) report a Device scope
+            Some("Battery") if text("scope") != Some("Device") => {
+                statuses.push(text("status").unwrap_or_default().to_string());
+                if let Some(level) = text("capacity_level") {
+                    if capacity_level.as_deref().is_none_or(|l| level_rank(level) < level_rank(l)) {
+                        capacity_level = Some(level.to_string());
+                    }
+                }
+                // Drivers counting charge in µAh and µA are turned into µWh and µW
+                // through the present voltage (µV)
+                let volts = value("voltage_now").filter(|v| *v > 0.0).map(|v| v / 1e6);
+                let charge = value("charge_now").zip(value("charge_full")).filter(|(_, charge_full)| *charge_full > 0.0);
+                let energy = value("energy_now")
+                    .zip(value("energy_full"))
+                    .map(|e| (e, value("power_now")))
+                    .or_else(|| {
+                        let volts = volts?;
+                        let (charge_now, charge_full) = charge?;
+                        Some(((charge_now * volts, charge_full * volts), value("current_now").map(|c| c * volts)))
+                    })
+                    .filter(|((_, energy_full), _)| *energy_full > 0.0);
+                if let Some(((energy_now, energy_full), draw)) = energy {
+                    now += energy_now;
+                    full += energy_full;
+                    rate += draw.unwrap_or(0.0).abs();
+                    batteries.push((energy_now / energy_full * 100.0, Some(energy_full)));
+                } else if let Some(percent) = charge.map(|(charge_now, charge_full)| charge_now / charge_full * 100.0).or_else(|| value("capacity")) {
+                    batteries.push((percent, None));
+                }
+            }
+            _ => {}
         }
     }
+    if statuses.is_empty() {
+        return None;
+    }
 
-    #[cfg(target_arch = "x86_64")]
-    fn read_capacity(battery: &str) -> u32 {
-        let charge_now_path = format!("/sys/class/power_supply/{}/charge_now", battery);
-        let charge_full_path = format!("/sys/class/power_supply/{}/charge_full", battery);
+    // Each battery counts by its size; one of unknown size counts as the average known one
+    let sized = batteries.iter().filter(|(_, size)| size.is_some()).count();
+    let default_size = if sized > 0 { full / sized as f64 } else { 1.0 };
+    let level_only = batteries.is_empty();
+    let capacity = if !level_only {
+        let (sum, weight) = batteries.iter().fold((0.0, 0.0), |(sum, weight), (percent, size)| {
+            let size = size.unwrap_or(default_size);
+            (sum + percent * size, weight + size)
+        });
+        (sum / weight).round().clamp(0.0, 100.0) as u32
+    } else {
+        // Some firmware only reports a coarse level
+        match capacity_level.as_deref() {
+            Some("Critical") => 5,
+            Some("Low") => 15,
+            Some("Normal") => 50,
+            Some("High") => 80,
+            _ => 100,
+        }
+    };
+    let charging = statuses.iter().any(|s| s == "Charging");
+    let discharging = statuses.iter().any(|s| s == "Discharging");
+    // Energy and draw are only known for all batteries together if every one has a size
+    let time_remaining = match (charging, discharging) {
+        _ if rate <= 0.0 || sized < batteries.len() => None,
+        (true, false) => Some((full - now).max(0.0) / rate),
+        (false, true) => Some(now / rate),
+        _ => None,
+    }
+    .map(|hours| Duration::from_secs_f64(hours * 3600.0));
 
-        let charge_now = fs::read_to_string(&charge_now_path)
-            .ok()
-            .and_then(|s| s.trim().parse::<f64>().ok());
-        let charge_full = fs::read_to_string(&charge_full_path)
-            .ok()
-            .and_then(|s| s.trim().parse::<f64>().ok());
+    let level = capacity_level.as_deref();
+    let state = if charging || statuses.iter().all(|s| s == "Full") || on_ac == Some(true) {
+        BatteryState::Charging
+    } else if discharging && (capacity <= thresholds.critical || level == Some("Critical")) {
+        BatteryState::Critical
+    } else if discharging && (capacity <= thresholds.low || level == Some("Low")) {
+        BatteryState::Low
+    } else {
+        BatteryState::NotCharging
+    };
 
-        match (charge_now, charge_full) {
-            (Some(now), Some(full)) if full > 0.0 => ((now / full) * 100.0).round() as u32,
-            _ => 100,
+    Some(BatteryInfo {
+        capacity,
+        state,
+        on_ac,
+        time_remaining,
+        capacity_level,
+        level_only,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn supply(root: &Path, name: &str, files: &[(&str, &str)]) {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            fs::write(dir.join(file), format!("{}\n", contents)).unwrap();
         }
     }
 
-    #[cfg(target_arch = "aarch64")]
-    fn read_capacity(battery: &str) -> u32 {
-        let capacity_path = format!("/sys/class/power_supply/{}/capacity", battery);
-        fs::read_to_string(&capacity_path)
-            .ok()
-            .and_then(|s| s.trim().parse::<u32>().ok())
-            .unwrap_or(100)
+    #[test]
+    fn test_power_supply_aggregation() {
+        let root = TempDir::new("power");
+        let thresholds = BatteryThresholds::default();
+        supply(&root, "AC", &[("type", "Mains"), ("online", "0")]);
+        assert!(read_power_supply(&root, thresholds).is_none());
+
+        supply(&root, "BAT0", &[
+            ("type", "Battery"), ("status", "Discharging"), ("capacity_level", "Normal"),
+            ("energy_now", "30000000"), ("energy_full", "60000000"), ("power_now", "10000000"),
+        ]);
+        supply(&root, "BAT1", &[
+            ("type", "Battery"), ("status", "Discharging"), ("capacity_level", "Low"),
+            ("energy_now", "6000000"), ("energy_full", "40000000"), ("power_now", "2000000"),
+        ]);
+        supply(&root, "hid-mouse-battery", &[("type", "Battery"), ("scope", "Device"), ("capacity", "3")]);
+        let info = read_power_supply(&root, thresholds).unwrap();
+        assert_eq!(info.capacity, 36);
+        assert_eq!(info.on_ac, Some(false));
+        assert_eq!(info.time_remaining.map(|t| t.as_secs()), Some(3 * 3600));
+        assert_eq!(info.capacity_level.as_deref(), Some("Low"));
+        assert_eq!(info.state, BatteryState::Low);
+        assert_eq!(read_power_supply(&root, BatteryThresholds { low: 50, critical: 40 }).unwrap().state, BatteryState::Critical);
+
+        supply(&root, "AC", &[("online", "1")]);
+        supply(&root, "BAT0", &[("status", "Charging")]);
+        supply(&root, "BAT1", &[("status", "Not charging"), ("power_now", "0")]);
+        let info = read_power_supply(&root, thresholds).unwrap();
+        assert_eq!(info.state, BatteryState::Charging);
+        // 64 Wh to go at 10 W
+        assert_eq!(info.time_remaining.map(|t| t.as_secs()), Some(23040));
+
+        // Unplugging arrives as a uevent for the adapter alone
+        let mut state = PowerSupplies::default();
+        state.scan(&root);
+        assert!(state.apply_event("AC", "change", [("POWER_SUPPLY_NAME", "AC"), ("POWER_SUPPLY_ONLINE", "0")].into_iter()));
+        assert_eq!(state.supplies["AC"]["type"], "Mains");
+        assert_eq!(state.info.as_ref().unwrap().on_ac, Some(false));
+        assert!(!state.apply_event("AC", "change", [("POWER_SUPPLY_ONLINE", "0")].into_iter()));
+        assert!(state.apply_event("BAT1", "remove", std::iter::empty()));
+        assert_eq!(state.info.as_ref().unwrap().capacity, 50);
     }
-}
 
-// Public API
-pub fn get_cached_battery_state() -> Option<(u32, BatteryState)> {
-    if let Ok(state) = BATTERY_STATE.lock() {
-        state.as_ref().map(|info| (info.capacity, info.state))
-    } else {
-        None
+    #[test]
+    fn test_charge_units() {
+        let root = TempDir::new("power-units");
+        let thresholds = BatteryThresholds::default();
+        // 60 Wh at 10 W, and 40 Wh counted as 4 Ah at 10 V drawing 1 A
+        supply(&root, "BAT0", &[
+            ("type", "Battery"), ("status", "Discharging"),
+            ("energy_now", "30000000"), ("energy_full", "60000000"), ("power_now", "10000000"),
+        ]);
+        supply(&root, "BAT1", &[
+            ("type", "Battery"), ("status", "Discharging"), ("voltage_now", "10000000"),
+            ("charge_now", "1000000"), ("charge_full", "4000000"), ("current_now", "1000000"),
+        ]);
+        let info = read_power_supply(&root, thresholds).unwrap();
+        assert_eq!(info.capacity, 40);
+        assert_eq!(info.charge_text(), "40%");
+        assert_eq!(info.time_remaining.map(|t| t.as_secs()), Some(2 * 3600));
+
+        // Without a voltage, BAT1's 25% counts as big as BAT0 and no time is guessed
+        fs::remove_file(root.join("BAT1/voltage_now")).unwrap();
+        let info = read_power_supply(&root, thresholds).unwrap();
+        assert_eq!((info.capacity, info.time_remaining), (38, None));
     }
-}
 
-pub fn is_battery_data_fresh() -> bool {
-    if let Ok(state) = BATTERY_STATE.lock() {
-        if let Some(info) = state.as_ref() {
-            info.last_updated.elapsed() < Duration::from_secs(60)
-        } else {
-            false
-        }
-    } else {
-        false
+    #[test]
+    fn test_power_supply_errors() {
+        let root = TempDir::new("power-errors");
+        let thresholds = BatteryThresholds::default();
+        assert!(read_power_supply(&root.join("missing"), thresholds).is_none());
+
+        // Garbled and empty readings fall back to capacity, then to the coarse level
+        supply(&root, "BAT0", &[("type", "Battery"), ("status", "Discharging"), ("energy_now", "n/a"), ("energy_full", "0"), ("capacity", "40")]);
+        supply(&root, "BAT1", &[("type", "Battery"), ("status", "Discharging"), ("charge_now", "1"), ("charge_full", "0")]);
+        let info = read_power_supply(&root, thresholds).unwrap();
+        assert_eq!((info.capacity, info.time_remaining), (40, None));
+        supply(&root, "BAT0", &[("capacity", "many")]);
+        supply(&root, "BAT1", &[("capacity_level", "Critical")]);
+        let info = read_power_supply(&root, thresholds).unwrap();
+        assert_eq!((info.capacity, info.state), (5, BatteryState::Critical));
+        assert_eq!(info.charge_text(), "Critical");
+
+        // Events for unknown supplies or without properties don't invent a battery
+        let mut state = PowerSupplies::default();
+        assert!(!state.apply_event("ucsi-source-psy-1", "add", [("POWER_SUPPLY_ONLINE", "1")].into_iter()));
+        assert!(!state.apply_event("BAT9", "remove", std::iter::empty()));
+        assert!(state.info.is_none());
     }
-}
\ No newline at end of file
+}
@@ -1,7 +1,11 @@
+use crate::battery_monitor::BatteryThresholds;
 use crate::fonts::{FontConfig, Pattern};
+use crate::keymap::{self, TextFallback};
+use crate::macros::{self, MacroStep};
+use crate::upstream;
 use crate::user_cache; // For detecting the active desktop user's home dir
-use crate::FunctionLayer;
-use anyhow::Error;
+use crate::{Bar, FunctionLayer};
+use anyhow::{anyhow, Error};
 use cairo::FontFace;
 use freetype::Library as FtLibrary;
 use input_linux::Key;
@@ -9,12 +13,22 @@ use nix::{
     errno::Errno,
     sys::inotify::{AddWatchFlags, InitFlags, Inotify, InotifyEvent, WatchDescriptor},
 };
-use serde::{Deserialize, Deserializer};
-use serde::de::value;
-use std::{fs::read_to_string, os::fd::AsFd, collections::HashMap};
+use serde::{
+    de::{self, value, DeserializeOwned, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{read_dir, read_to_string},
+    os::fd::AsFd,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+};
+use toml::{Table, Value};
 
-#[derive(Deserialize, Debug, Clone)]
-#[serde(untagged)]
+#[derive(Debug, Clone)]
 pub enum ButtonColor {
     Grayscale(f64),
     Rgb([f64; 3]),
@@ -27,22 +41,77 @@ impl ButtonColor {
             ButtonColor::Rgb([r, g, b]) => c.set_source_rgb(*r, *g, *b),
         }
     }
+
+    pub fn json_schema() -> serde_json::Value {
+        let channel = json!({ "type": "number", "minimum": 0, "maximum": 1 });
+        json!({
+            "description": "A gray level or an [r, g, b] array, each channel from 0 to 1",
+            "anyOf": [
+                channel,
+                { "type": "array", "items": channel, "minItems": 3, "maxItems": 3 },
+            ],
+        })
+    }
+}
+
+struct ButtonColorVisitor;
+
+impl<'de> Visitor<'de> for ButtonColorVisitor {
+    type Value = ButtonColor;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a gray level or an [r, g, b] array")
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<ButtonColor, E> {
+        Ok(ButtonColor::Grayscale(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<ButtonColor, E> {
+        Ok(ButtonColor::Grayscale(v as f64))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<ButtonColor, E> {
+        Ok(ButtonColor::Grayscale(v as f64))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<ButtonColor, A::Error> {
+        <[f64; 3]>::deserialize(de::value::SeqAccessDeserializer::new(seq)).map(ButtonColor::Rgb)
+    }
 }
 
+impl<'de> Deserialize<'de> for ButtonColor {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(ButtonColorVisitor)
+    }
+}
+
+// Configuration tiers, lowest priority first. Every tier may contain
+// {config,commands,expandables,hyprland}.toml plus a config.d/ drop-in directory.
+const SHARE_DIR: &str = "/usr/share/tiny-dfr";
+const ETC_DIR: &str = "/etc/tiny-dfr";
+const DROPIN_DIR: &str = "config.d";
+
 // System-wide override locations
 const ETC_CFG_PATH: &str = "/etc/tiny-dfr/config.toml";
-const ETC_COMMANDS_PATH: &str = "/etc/tiny-dfr/commands.toml";
 const ETC_ENV_PATH: &str = "/etc/tiny-dfr/user-env.toml";
-const ETC_EXPANDABLES_PATH: &str = "/etc/tiny-dfr/expandables.toml";
+
+// Guards against include cycles (a.toml including b.toml including a.toml)
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+// Included files, and directories of globbed includes, seen since the last load, so
+// the config manager can watch them too
+static INCLUDE_WATCHES: LazyLock<Mutex<Vec<PathBuf>>> = LazyLock::new(|| Mutex::new(Vec::new()));
 
 // Per-user override (highest priority):
 // ~/.config/tiny-dfr/{config,commands,expandables,hyprland}.toml
 #[derive(Clone, Debug, Default)]
 struct UserConfigPaths {
+    dir: Option<String>,
     config: Option<String>,
-    commands: Option<String>,
-    expandables: Option<String>,
-    hyprland: Option<String>,
 }
 
 fn detect_user_config_paths() -> UserConfigPaths {
@@ -51,9 +120,7 @@ fn detect_user_config_paths() -> UserConfigPaths {
         let base = format!("{}/.config/tiny-dfr", env.home_dir);
         return UserConfigPaths {
             config: Some(format!("{}/config.toml", base)),
-            commands: Some(format!("{}/commands.toml", base)),
-            expandables: Some(format!("{}/expandables.toml", base)),
-            hyprland: Some(format!("{}/hyprland.toml", base)),
+            dir: Some(base),
         };
     }
 
@@ -61,6 +128,198 @@ fn detect_user_config_paths() -> UserConfigPaths {
     UserConfigPaths::default()
 }
 
+fn config_tiers() -> Vec<PathBuf> {
+    let mut tiers = vec![PathBuf::from(SHARE_DIR), PathBuf::from(ETC_DIR)];
+    if let Some(dir) = detect_user_config_paths().dir {
+        tiers.push(PathBuf::from(dir));
+    }
+    tiers
+}
+
+/// The four kinds of configuration file. Drop-ins in `config.d/` can carry any of
+/// them: plain top-level keys are `config.toml` settings, the others live in tables.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ConfigKind {
+    Config,
+    Commands,
+    Expandables,
+    Hyprland,
+}
+
+impl ConfigKind {
+    const DROPIN_SECTIONS: [&'static str; 3] = ["Commands", "Expandables", "Hyprland"];
+
+    fn file_name(self) -> &'static str {
+        match self {
+            ConfigKind::Config => "config.toml",
+            ConfigKind::Commands => "commands.toml",
+            ConfigKind::Expandables => "expandables.toml",
+            ConfigKind::Hyprland => "hyprland.toml",
+        }
+    }
+
+    fn dropin_section(self) -> Option<&'static str> {
+        match self {
+            ConfigKind::Config => None,
+            ConfigKind::Commands => Some("Commands"),
+            ConfigKind::Expandables => Some("Expandables"),
+            ConfigKind::Hyprland => Some("Hyprland"),
+        }
+    }
+}
+
+pub(crate) fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let (p, n): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut pi, mut ni) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ni));
+            pi += 1;
+        } else if let Some((sp, sn)) = star {
+            pi = sp + 1;
+            ni = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+/// Expands an `Include` entry into concrete paths. Relative entries are resolved
+/// against the including file's directory and `~/` against the desktop user's home;
+/// `*` and `?` are allowed in the final path component. Matches are sorted so
+/// globbed includes merge in lexical order.
+fn resolve_include(entry: &str, relative_to: &Path) -> Vec<PathBuf> {
+    let path = if let Some(rest) = entry.strip_prefix("~/") {
+        match user_cache::get_cached_user_environment() {
+            Some(env) => Path::new(&env.home_dir).join(rest),
+            None => return Vec::new(),
+        }
+    } else {
+        relative_to.join(entry)
+    };
+    let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    let globbed = file_name.contains(['*', '?']);
+    let dir = path.parent().unwrap_or(Path::new("/"));
+    if let Ok(mut watches) = INCLUDE_WATCHES.lock() {
+        watches.push(if globbed { dir.to_path_buf() } else { path.clone() });
+    }
+    if !globbed {
+        return vec![path];
+    }
+    let mut matches: Vec<PathBuf> = read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|e| wildcard_match(file_name, &e.file_name().to_string_lossy()))
+        .map(|e| e.path())
+        .collect();
+    matches.sort();
+    matches
+}
+
+/// Reads a TOML file and resolves its `Include = [...]` list. Included files are
+/// merged first, so keys set in the including file override them.
+fn read_table(path: &Path, depth: usize) -> Result<Table, Error> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(anyhow!("includes nested too deeply (cycle?)"));
+    }
+    let mut own = toml::from_str::<Table>(&read_to_string(path)?)?;
+    let includes = match own.remove("Include") {
+        Some(Value::Array(entries)) => entries,
+        Some(Value::String(entry)) => vec![Value::String(entry)],
+        Some(_) => return Err(anyhow!("Include must be a string or an array of strings")),
+        None => return Ok(own),
+    };
+    let dir = path.parent().unwrap_or(Path::new("/"));
+    let mut merged = Table::new();
+    for entry in includes {
+        let Value::String(entry) = entry else {
+            return Err(anyhow!("Include entries must be strings"));
+        };
+        for included in resolve_include(&entry, dir) {
+            let table = read_table(&included, depth + 1)
+                .map_err(|e| anyhow!("while including {}: {}", included.display(), e))?;
+            merged.extend(table);
+        }
+    }
+    merged.extend(own);
+    Ok(merged)
+}
+
+fn dropin_files(tier: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = read_dir(tier.join(DROPIN_DIR))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Collects every table that contributes to `kind`, lowest priority first: for each
+/// tier the main file, then its `config.d/*.toml` drop-ins in lexical order.
+fn layered_tables(kind: ConfigKind) -> Vec<(PathBuf, Table)> {
+    let mut layers = Vec::new();
+    for tier in config_tiers() {
+        let main = tier.join(kind.file_name());
+        if main.exists() {
+            match read_table(&main, 0) {
+                Ok(table) => layers.push((main, table)),
+                Err(e) => eprintln!("Ignoring {}: {}", main.display(), e),
+            }
+        }
+        for dropin in dropin_files(&tier) {
+            let mut table = match read_table(&dropin, 0) {
+                Ok(table) => table,
+                Err(e) => {
+                    eprintln!("Ignoring {}: {}", dropin.display(), e);
+                    continue;
+                }
+            };
+            let section = match kind.dropin_section() {
+                Some(name) => match table.remove(name) {
+                    Some(Value::Table(section)) => section,
+                    Some(_) => {
+                        eprintln!("Ignoring [{}] in {}: not a table", name, dropin.display());
+                        continue;
+                    }
+                    None => continue,
+                },
+                None => {
+                    table.retain(|k, _| !ConfigKind::DROPIN_SECTIONS.contains(&k));
+                    table
+                }
+            };
+            if !section.is_empty() {
+                layers.push((dropin, section));
+            }
+        }
+    }
+    layers
+}
+
+/// Merges keyed tables (commands, expandables, Hyprland layouts) across all layers;
+/// a later layer replaces entries with the same name. Layers that fail to parse are
+/// skipped as a whole.
+fn load_layered_map<T: DeserializeOwned>(kind: ConfigKind) -> HashMap<String, T> {
+    let mut merged = HashMap::new();
+    for (path, table) in layered_tables(kind) {
+        match Value::Table(table).try_into::<HashMap<String, T>>() {
+            Ok(entries) => merged.extend(entries),
+            Err(e) => eprintln!("Ignoring {}: {}", path.display(), e),
+        }
+    }
+    merged
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ButtonAction {
     Key(Key),
@@ -68,44 +327,239 @@ pub enum ButtonAction {
     Expand(String),  // Expand_Something
     HyprlandExpand(String), // Hyprland_Expand_ActiveWindow
     KeyCombos(Vec<Key>), // KeyCombos_CTRL_SHIFT_I
+    Run(String), // { type = "command", run = "..." }
+    Macro(Vec<MacroStep>), // { type = "macro", steps = [...] }
+    TypeText(String, TextFallback), // { type = "text", text = "..." }
+    Toggle(String), // a button with a Toggle table, by its name
+    Timer(String), // starts or pauses a timer, by its name
+    TimerReset(String),
+    NotificationsDnd, // toggles the notification daemon's do not disturb
+    NotificationInvoke(usize), // the default action of the nth most recent notification
+    NotificationDismiss(usize),
 }
 
-impl<'de> Deserialize<'de> for ButtonAction {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let s = String::deserialize(deserializer)?;
-
+impl ButtonAction {
+    /// Decodes the string shorthand, e.g. `F5`, `KeyCombos_CTRL_H` or `Command_Btop`.
+    /// Fails only for a `KeyCombos_` name containing an unknown key.
+    pub fn from_name(s: &str) -> Result<ButtonAction, Error> {
         // Check for Hyprland expand actions
         if s.starts_with("Hyprland_Expand_") {
-            return Ok(ButtonAction::HyprlandExpand(s));
+            return Ok(ButtonAction::HyprlandExpand(s.to_string()));
         }
 
         // Check for key combinations
         if s.starts_with("KeyCombos_") {
-            let keys = crate::hyprland::parse_key_combos(&s);
-            if !keys.is_empty() {
-                return Ok(ButtonAction::KeyCombos(keys));
-            }
+            return crate::hyprland::parse_key_combos(s).map(ButtonAction::KeyCombos);
         }
 
         // Check if it's an Expand action
         if s.starts_with("Expand_") {
-            return Ok(ButtonAction::Expand(s));
+            return Ok(ButtonAction::Expand(s.to_string()));
         }
 
         // Try to deserialize as Key using serde
-        let key_result: Result<Key, _> = serde::de::Deserialize::deserialize(
-            value::StringDeserializer::<serde::de::value::Error>::new(s.clone())
-        );
-
-        if let Ok(key) = key_result {
+        if let Some(key) = parse_key_name(s) {
             return Ok(ButtonAction::Key(key));
         }
 
         // Otherwise treat as Command
-        Ok(ButtonAction::Command(s))
+        Ok(ButtonAction::Command(s.to_string()))
+    }
+
+    /// Keys this action can send, which have to be registered with uinput.
+    pub fn keys(&self) -> Vec<Key> {
+        match self {
+            ButtonAction::Key(key) => vec![*key],
+            ButtonAction::KeyCombos(keys) => keys.clone(),
+            ButtonAction::Macro(steps) => macros::step_keys(steps),
+            ButtonAction::TypeText(..) => keymap::typing_keys(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl ButtonAction {
+    pub fn json_schema() -> serde_json::Value {
+        json!({
+            "anyOf": [
+                { "$ref": "#/definitions/Key" },
+                {
+                    "type": "string",
+                    "description": "Expand_<name>, Hyprland_Expand_<name>, KeyCombos_<KEY>_<KEY>, or a command from commands.toml",
+                },
+                {
+                    "type": "array",
+                    "description": "Keys pressed together",
+                    "items": { "$ref": "#/definitions/Key" },
+                    "minItems": 1,
+                },
+                action_table("key", "key", json!({ "$ref": "#/definitions/Key" })),
+                action_table(
+                    "combo",
+                    "keys",
+                    json!({ "type": "array", "items": { "$ref": "#/definitions/Key" }, "minItems": 1 }),
+                ),
+                action_table("command", "run", json!({ "type": "string", "description": "Shell command line" })),
+                action_table(
+                    "macro",
+                    "steps",
+                    json!({ "type": "array", "items": macro_step_schema(), "minItems": 1 }),
+                ),
+                {
+                    "type": "object",
+                    "properties": {
+                        "type": { "const": "text" },
+                        "text": { "type": "string", "description": "Typed with the active keyboard layout" },
+                        "fallback": {
+                            "enum": ["wtype", "unicode", "none"],
+                            "description": "For characters the layout lacks; default wtype",
+                        },
+                    },
+                    "required": ["type", "text"],
+                    "additionalProperties": false,
+                },
+                action_table(
+                    "expand",
+                    "name",
+                    json!({ "type": "string", "description": "Expand_<name> or Hyprland_Expand_<name>" }),
+                ),
+            ],
+        })
+    }
+}
+
+fn macro_step_schema() -> serde_json::Value {
+    let key = json!({ "type": "string", "description": "Key name, e.g. LeftCtrl or F5" });
+    let steps = [
+        ("press", key.clone()),
+        ("release", key.clone()),
+        ("tap", key.clone()),
+        ("chord", json!({ "type": "array", "items": key, "minItems": 1 })),
+        ("text", json!({ "type": "string" })),
+        ("sleep", json!({ "type": "integer", "minimum": 0, "description": "Milliseconds" })),
+        ("run", json!({ "type": "string", "description": "Shell command line" })),
+        ("dispatch", json!({ "type": "string", "description": "Hyprland dispatcher, e.g. `workspace 2`" })),
+    ];
+    let variants: Vec<serde_json::Value> = steps
+        .into_iter()
+        .map(|(step, schema)| {
+            json!({
+                "type": "object",
+                "properties": { step: schema },
+                "required": [step],
+                "additionalProperties": false,
+            })
+        })
+        .collect();
+    json!({ "oneOf": variants })
+}
+
+fn action_table(kind: &str, field: &str, schema: serde_json::Value) -> serde_json::Value {
+    json!({
+        "type": "object",
+        "properties": { "type": { "const": kind }, field: schema },
+        "required": ["type", field],
+        "additionalProperties": false,
+    })
+}
+
+/// Table form of an action, e.g. `{ type = "combo", keys = ["LeftCtrl", "C"] }`.
+/// Unlike the string shorthand nothing is guessed: unknown types, fields and key
+/// names are errors.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum ActionTable {
+    Key { key: String },
+    Combo { keys: Vec<String> },
+    Command { run: String },
+    Expand { name: String },
+    Macro { steps: Vec<MacroStep> },
+    Text {
+        text: String,
+        #[serde(default)]
+        fallback: TextFallback,
+    },
+}
+
+fn strict_key<E: de::Error>(name: &str) -> Result<Key, E> {
+    parse_key_name(name).ok_or_else(|| E::custom(format!("unknown key name `{}`", name)))
+}
+
+impl ActionTable {
+    fn into_action<E: de::Error>(self) -> Result<ButtonAction, E> {
+        Ok(match self {
+            ActionTable::Key { key } => ButtonAction::Key(strict_key(&key)?),
+            ActionTable::Combo { keys } => {
+                if keys.is_empty() {
+                    return Err(E::custom("combo needs at least one key"));
+                }
+                ButtonAction::KeyCombos(keys.iter().map(|k| strict_key(k)).collect::<Result<_, E>>()?)
+            }
+            ActionTable::Command { run } if run.trim().is_empty() => {
+                return Err(E::custom("command `run` is empty"))
+            }
+            ActionTable::Command { run } => ButtonAction::Run(run),
+            ActionTable::Macro { steps } if steps.is_empty() => {
+                return Err(E::custom("macro needs at least one step"))
+            }
+            ActionTable::Macro { steps } => ButtonAction::Macro(steps),
+            ActionTable::Text { text, fallback } => ButtonAction::TypeText(text, fallback),
+            ActionTable::Expand { name } if name.starts_with("Hyprland_Expand_") => {
+                ButtonAction::HyprlandExpand(name)
+            }
+            ActionTable::Expand { name } if name.starts_with("Expand_") => ButtonAction::Expand(name),
+            ActionTable::Expand { name } => {
+                return Err(E::custom(format!(
+                    "expandable `{}` must start with Expand_ or Hyprland_Expand_",
+                    name
+                )))
+            }
+        })
+    }
+}
+
+/// Looks up an `input_linux::Key` by its serde name (`F5`, `LeftCtrl`, `VolumeUp`, ...).
+pub fn parse_key_name(name: &str) -> Option<Key> {
+    Key::deserialize(value::StrDeserializer::<value::Error>::new(name)).ok()
+}
+
+struct ButtonActionVisitor;
+
+impl<'de> Visitor<'de> for ButtonActionVisitor {
+    type Value = ButtonAction;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an action name, an array of key names or an action table")
+    }
+
+    // A combo with an unknown key fails the config, so a typo keeps the last good one
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<ButtonAction, E> {
+        ButtonAction::from_name(s).map_err(E::custom)
+    }
+
+    // Upstream tiny-dfr form: `Action = ["LeftCtrl", "C"]` presses the keys together
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ButtonAction, A::Error> {
+        let mut keys = Vec::new();
+        while let Some(key) = seq.next_element::<Key>()? {
+            keys.push(key);
+        }
+        if keys.is_empty() {
+            return Err(de::Error::invalid_length(0, &self));
+        }
+        Ok(ButtonAction::KeyCombos(keys))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<ButtonAction, A::Error> {
+        ActionTable::deserialize(value::MapAccessDeserializer::new(map))?.into_action()
+    }
+}
+
+impl<'de> Deserialize<'de> for ButtonAction {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(ButtonActionVisitor)
     }
 }
 
@@ -137,6 +591,13 @@ pub struct Config {
     pub back_button_show_outlines: bool,
     pub back_button_outline_color: Option<ButtonColor>,
     pub expandable_timeout_seconds: u32,
+    pub long_press_ms: u64,
+    pub double_tap_ms: u64,
+    pub swipes: SwipeConfig,
+    pub bar: Bar,
+    pub battery_alert: bool,
+    pub battery_alert_wake: bool,
+    pub battery_alert_command: Option<String>,
     pub expandables: HashMap<String, Vec<ButtonConfig>>,
     pub hyprland_expandables: HashMap<String, Vec<HyprlandExpandConfig>>,
 }
@@ -151,9 +612,9 @@ pub struct HyprlandExpandConfig {
     pub layer_keys: Vec<ButtonConfig>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
 #[serde(rename_all = "PascalCase")]
-struct ConfigProxy {
+pub(crate) struct ConfigProxy {
     media_layer_default: Option<bool>,
     show_button_outlines: Option<bool>,
     enable_pixel_shift: Option<bool>,
@@ -167,123 +628,610 @@ struct ConfigProxy {
     back_button_show_outlines: Option<bool>,
     back_button_outline_color: Option<ButtonColor>,
     expandable_timeout_seconds: Option<u32>,
+    long_press_ms: Option<u64>,
+    double_tap_ms: Option<u64>,
+    swipes: Option<SwipeConfig>,
+    min_button_width: Option<u32>,
+    battery_low_percent: Option<u32>,
+    battery_critical_percent: Option<u32>,
+    battery_alert: Option<bool>,
+    battery_alert_wake: Option<bool>,
+    battery_alert_command: Option<String>,
+    probe_interval_seconds: Option<u32>,
+    auto_esc: Option<bool>,
+    profiles: Option<Vec<ProfileConfig>>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// Criteria selecting a profile. Every criterion that is set must match; string
+/// criteria accept `*` and `?` wildcards.
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct ProfileMatch {
+    product_name: Option<String>,
+    width: Option<u16>,
+    hostname: Option<String>,
+}
+
+/// A `[[Profiles]]` entry: a `Match` table plus any `config.toml` keys to override
+/// when it matches.
+#[derive(Deserialize)]
 #[serde(rename_all = "PascalCase")]
+struct ProfileConfig {
+    #[serde(default, rename = "Match")]
+    matches: ProfileMatch,
+    #[serde(flatten)]
+    overrides: ConfigProxy,
+}
+
+/// The machine facts profiles are matched against.
+#[derive(Debug, Clone)]
+struct MachineInfo {
+    product_name: String,
+    width: u16,
+    hostname: String,
+}
+
+fn read_trimmed(path: &str) -> Option<String> {
+    read_to_string(path)
+        .ok()
+        .map(|s| s.trim_matches(|c: char| c.is_whitespace() || c == '\0').to_string())
+        .filter(|s| !s.is_empty())
+}
+
+impl MachineInfo {
+    fn detect(width: u16) -> MachineInfo {
+        // T2 Macs expose DMI; Apple Silicon machines only have a device tree model
+        let product_name = read_trimmed("/sys/class/dmi/id/product_name")
+            .or_else(|| read_trimmed("/sys/firmware/devicetree/base/model"))
+            .unwrap_or_default();
+        let hostname = read_trimmed("/proc/sys/kernel/hostname")
+            .or_else(|| read_trimmed("/etc/hostname"))
+            .unwrap_or_default();
+        MachineInfo {
+            product_name,
+            width,
+            hostname,
+        }
+    }
+}
+
+impl ProfileMatch {
+    fn matches(&self, machine: &MachineInfo) -> bool {
+        self.product_name
+            .as_deref()
+            .is_none_or(|p| wildcard_match(p, &machine.product_name))
+            && self.width.is_none_or(|w| w == machine.width)
+            && self
+                .hostname
+                .as_deref()
+                .is_none_or(|h| wildcard_match(h, &machine.hostname))
+    }
+}
+
+impl ConfigProxy {
+    /// Applies every matching profile in order, so later profiles win.
+    fn apply_profiles(&mut self, machine: &MachineInfo) {
+        for (i, profile) in self.profiles.take().unwrap_or_default().into_iter().enumerate() {
+            if !profile.matches.matches(machine) {
+                continue;
+            }
+            println!("Applying config profile #{} ({:?})", i + 1, profile.matches);
+            let mut overrides = profile.overrides;
+            if overrides.profiles.take().is_some() {
+                eprintln!("Ignoring nested Profiles in profile #{}", i + 1);
+            }
+            self.merge(overrides);
+        }
+    }
+
+    fn merge(&mut self, user: ConfigProxy) {
+        self.media_layer_default = user.media_layer_default.or(self.media_layer_default);
+        self.show_button_outlines = user.show_button_outlines.or(self.show_button_outlines);
+        self.enable_pixel_shift = user.enable_pixel_shift.or(self.enable_pixel_shift);
+        self.font_template = user.font_template.or(self.font_template.take());
+        self.adaptive_brightness = user.adaptive_brightness.or(self.adaptive_brightness);
+        self.media_layer_keys = user.media_layer_keys.or(self.media_layer_keys.take());
+        self.primary_layer_keys = user.primary_layer_keys.or(self.primary_layer_keys.take());
+        self.active_brightness = user.active_brightness.or(self.active_brightness);
+        self.keyboard_brightness_step = user.keyboard_brightness_step.or(self.keyboard_brightness_step);
+        self.keyboard_brightness_enabled = user.keyboard_brightness_enabled.or(self.keyboard_brightness_enabled);
+        self.back_button_show_outlines = user.back_button_show_outlines.or(self.back_button_show_outlines);
+        self.back_button_outline_color = user.back_button_outline_color.or(self.back_button_outline_color.take());
+        self.expandable_timeout_seconds = user.expandable_timeout_seconds.or(self.expandable_timeout_seconds);
+        self.long_press_ms = user.long_press_ms.or(self.long_press_ms);
+        self.double_tap_ms = user.double_tap_ms.or(self.double_tap_ms);
+        self.swipes = user.swipes.or(self.swipes.take());
+        self.min_button_width = user.min_button_width.or(self.min_button_width);
+        self.battery_low_percent = user.battery_low_percent.or(self.battery_low_percent);
+        self.battery_critical_percent = user.battery_critical_percent.or(self.battery_critical_percent);
+        self.battery_alert = user.battery_alert.or(self.battery_alert);
+        self.battery_alert_wake = user.battery_alert_wake.or(self.battery_alert_wake);
+        self.battery_alert_command = user.battery_alert_command.or(self.battery_alert_command.take());
+        self.probe_interval_seconds = user.probe_interval_seconds.or(self.probe_interval_seconds);
+        self.auto_esc = user.auto_esc.or(self.auto_esc);
+        // Profiles accumulate so drop-ins can add machine profiles alongside the user's
+        if let Some(profiles) = user.profiles {
+            self.profiles.get_or_insert_with(Vec::new).extend(profiles);
+        }
+    }
+}
+
+/// A layer entry. Each kind of widget has its own key in the button table and an
+/// entry sets at most one; without any it shows its Icon and Text.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(try_from = "ButtonTable")]
 pub struct ButtonConfig {
-    #[serde(alias = "Svg")]
     pub icon: Option<String>,
     pub text: Option<String>,
     pub theme: Option<String>,
-    pub time: Option<String>,
-    pub battery: Option<String>,
-    pub locale: Option<String>,
-    pub action: ButtonAction,
+    pub action: Option<ButtonAction>,
+    pub long_press_action: Option<ButtonAction>,
+    pub double_tap_action: Option<ButtonAction>,
     pub stretch: Option<usize>,
     pub show_button_outlines: Option<bool>,
     pub button_outlines_color: Option<ButtonColor>,
     pub show_app_icon_alongside_text: Option<bool>,
     pub app_icon: Option<String>,
+    pub repeat: Option<RepeatConfig>,
+    pub width: Option<u32>,
+    pub align: Option<Align>,
+    pub visible_when: Option<String>,
+    pub widget: Widget,
 }
 
-fn load_commands() -> HashMap<String, String> {
-    let mut commands = HashMap::new();
+/// What a button shows besides its Icon and Text.
+#[derive(Debug, Clone, Default)]
+pub enum Widget {
+    #[default]
+    Label,
+    Spacer,
+    Time { format: String, locale: Option<String>, time_zone: Option<String> },
+    Battery { mode: GaugeMode, show_time: bool },
+    Resource { kind: ResourceKind, style: ResourceStyle, sensor: Option<String> },
+    Network(NetworkMode),
+    Volume { mode: GaugeMode, slider: bool },
+    Timer(TimerConfig),
+    Indicator(Indicator),
+    Notifications,
+    Notification(usize),
+    Toggle(ToggleConfig),
+}
 
-    // Load base commands from /usr/share/tiny-dfr/commands.toml
-    if let Ok(content) = read_to_string("/usr/share/tiny-dfr/commands.toml") {
-        if let Ok(base_commands) = toml::from_str::<HashMap<String, String>>(&content) {
-            commands.extend(base_commands);
+// A button table as written; ButtonConfig checks that it names one widget
+#[derive(Deserialize)]
+#[serde(rename = "ButtonConfig", rename_all = "PascalCase")]
+struct ButtonTable {
+    #[serde(alias = "Svg")]
+    icon: Option<String>,
+    text: Option<String>,
+    theme: Option<String>,
+    time: Option<String>,
+    battery: Option<GaugeMode>,
+    /// Follow the battery percentage with the estimated time to empty or full
+    battery_time: Option<bool>,
+    locale: Option<String>,
+    /// IANA zone for a Time button, e.g. "America/New_York"; the local zone by default
+    time_zone: Option<String>,
+    /// Required unless the entry is a spacer
+    action: Option<ButtonAction>,
+    long_press_action: Option<ButtonAction>,
+    double_tap_action: Option<ButtonAction>,
+    stretch: Option<usize>,
+    show_button_outlines: Option<bool>,
+    button_outlines_color: Option<ButtonColor>,
+    show_app_icon_alongside_text: Option<bool>,
+    app_icon: Option<String>,
+    repeat: Option<RepeatConfig>,
+    /// An empty gap instead of a button, sized by `Stretch` or `Width`
+    spacer: Option<bool>,
+    /// Fixed width in pixels instead of a share of the bar
+    width: Option<u32>,
+    align: Option<Align>,
+    /// Only show the button while this condition holds, e.g. `ac && class(firefox*)`
+    visible_when: Option<String>,
+    /// Makes the button an on/off switch; its Icon and Text show the off state
+    toggle: Option<ToggleConfig>,
+    /// Shows a live system reading, labelled with `Text` if given
+    resource: Option<ResourceKind>,
+    resource_style: Option<ResourceStyle>,
+    /// Temperature sensor as `<hwmon name>/<label>` or `thermal/<zone type>`, wildcards
+    /// allowed; the hottest sensor by default
+    sensor: Option<String>,
+    /// Wi-Fi, wired and VPN status; opens `Expand_Network` unless an Action is given
+    network: Option<NetworkMode>,
+    /// Output volume as "icon", "percentage" or "both"; mutes on tap unless an Action is given
+    volume: Option<GaugeMode>,
+    /// Dragging along a pressed Volume button sets the volume
+    volume_slider: Option<bool>,
+    /// Makes the button a timer, started and paused by a tap and reset by a long press
+    timer: Option<TimerConfig>,
+    /// Shows a keyboard state; the lock indicators press their key on tap
+    indicator: Option<Indicator>,
+    /// Unread notifications, lit while do not disturb is on; opens `Expand_Notifications`
+    /// on tap and toggles do not disturb on a long press unless actions are given
+    notifications: Option<bool>,
+    /// Summary of the nth most recent notification; a tap runs its default action and a
+    /// long press dismisses it
+    notification: Option<usize>,
+}
+
+impl TryFrom<ButtonTable> for ButtonConfig {
+    type Error = String;
+
+    fn try_from(t: ButtonTable) -> Result<ButtonConfig, String> {
+        let mut widgets = Vec::new();
+        if t.spacer == Some(true) {
+            widgets.push(("Spacer", Widget::Spacer));
+        }
+        if let Some(format) = t.time {
+            widgets.push(("Time", Widget::Time { format, locale: t.locale, time_zone: t.time_zone }));
+        } else if let Some(key) = [("Locale", t.locale.is_some()), ("TimeZone", t.time_zone.is_some())].iter().find(|(_, set)| *set) {
+            return Err(format!("{} only applies to Time buttons", key.0));
+        }
+        if let Some(mode) = t.battery {
+            widgets.push(("Battery", Widget::Battery { mode, show_time: t.battery_time.unwrap_or(false) }));
+        } else if t.battery_time.is_some() {
+            return Err("BatteryTime only applies to Battery buttons".into());
+        }
+        if let Some(kind) = t.resource {
+            widgets.push(("Resource", Widget::Resource { kind, style: t.resource_style.unwrap_or_default(), sensor: t.sensor }));
+        } else if let Some(key) = [("ResourceStyle", t.resource_style.is_some()), ("Sensor", t.sensor.is_some())].iter().find(|(_, set)| *set) {
+            return Err(format!("{} only applies to Resource buttons", key.0));
+        }
+        if let Some(mode) = t.network {
+            widgets.push(("Network", Widget::Network(mode)));
+        }
+        if let Some(mode) = t.volume {
+            widgets.push(("Volume", Widget::Volume { mode, slider: t.volume_slider.unwrap_or(false) }));
+        } else if t.volume_slider.is_some() {
+            return Err("VolumeSlider only applies to Volume buttons".into());
         }
+        if let Some(timer) = t.timer {
+            // A zero-length period would end again on every pass of the main loop
+            let lengths = [("Minutes", timer.minutes), ("BreakMinutes", timer.break_minutes)];
+            if let Some((key, _)) = lengths.iter().find(|(_, minutes)| minutes.is_some_and(|m| m <= 0.0 || !m.is_finite())) {
+                return Err(format!("Timer {} must be above 0", key));
+            }
+            widgets.push(("Timer", Widget::Timer(timer)));
+        }
+        if let Some(indicator) = t.indicator {
+            widgets.push(("Indicator", Widget::Indicator(indicator)));
+        }
+        if t.notifications == Some(true) {
+            widgets.push(("Notifications", Widget::Notifications));
+        }
+        if let Some(slot) = t.notification {
+            widgets.push(("Notification", Widget::Notification(slot)));
+        }
+        if let Some(toggle) = t.toggle {
+            // Its Icon and Text draw the off state
+            if t.icon.is_none() && t.text.is_none() {
+                return Err("a Toggle button needs an Icon or Text".into());
+            }
+            widgets.push(("Toggle", Widget::Toggle(toggle)));
+        }
+        if widgets.len() > 1 {
+            let names: Vec<&str> = widgets.iter().map(|(name, _)| *name).collect();
+            return Err(format!("a button can only be one of {}", names.join(", ")));
+        }
+        Ok(ButtonConfig {
+            icon: t.icon,
+            text: t.text,
+            theme: t.theme,
+            action: t.action,
+            long_press_action: t.long_press_action,
+            double_tap_action: t.double_tap_action,
+            stretch: t.stretch,
+            show_button_outlines: t.show_button_outlines,
+            button_outlines_color: t.button_outlines_color,
+            show_app_icon_alongside_text: t.show_app_icon_alongside_text,
+            app_icon: t.app_icon,
+            repeat: t.repeat,
+            width: t.width,
+            align: t.align,
+            visible_when: t.visible_when,
+            widget: widgets.pop().map(|(_, widget)| widget).unwrap_or_default(),
+        })
     }
+}
 
-    // Override with system-wide commands from /etc/tiny-dfr/commands.toml
-    if let Ok(content) = read_to_string(ETC_COMMANDS_PATH) {
-        if let Ok(user_commands) = toml::from_str::<HashMap<String, String>>(&content) {
-            commands.extend(user_commands);
-        }
+impl ButtonConfig {
+    /// Keys any of the button's actions can send.
+    pub fn keys(&self) -> Vec<Key> {
+        let actions = self.action.iter().chain(&self.long_press_action).chain(&self.double_tap_action);
+        actions.flat_map(ButtonAction::keys).collect()
     }
+}
 
-    // Highest priority: per-user commands from ~/.config/tiny-dfr/commands.toml
-    let user_paths = detect_user_config_paths();
-    if let Some(p) = user_paths.commands {
-        if let Ok(content) = read_to_string(p) {
-            if let Ok(user_commands) = toml::from_str::<HashMap<String, String>>(&content) {
-                commands.extend(user_commands);
-            }
-        }
+/// An on/off button, e.g.
+/// `Toggle = { Name = "dnd", OnCommand = "DndOn", OffCommand = "DndOff", OnIcon = "notifications_off" }`.
+/// Without a `Probe` the state is remembered by the daemon across restarts.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct ToggleConfig {
+    /// Buttons with the same name share their state; defaults to OnCommand
+    pub name: Option<String>,
+    /// Commands from commands.toml run when switching on and off
+    pub on_command: String,
+    pub off_command: String,
+    /// Shell command run as the desktop user; the toggle is on while it exits with status 0
+    pub probe: Option<String>,
+    pub on_icon: Option<String>,
+    pub on_text: Option<String>,
+    pub on_color: Option<ButtonColor>,
+    pub off_color: Option<ButtonColor>,
+}
+
+impl ToggleConfig {
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.on_command)
     }
+}
 
-    commands
+/// A stopwatch, countdown or Pomodoro timer, e.g.
+/// `Timer = { Kind = "Pomodoro", Minutes = 25, BreakMinutes = 5, Command = "Command_Chime" }`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct TimerConfig {
+    pub kind: TimerKind,
+    /// Buttons with the same name share one timer; defaults to the kind
+    pub name: Option<String>,
+    /// Countdown length, or the Pomodoro work period
+    pub minutes: Option<f64>,
+    pub break_minutes: Option<f64>,
+    /// Command from commands.toml run when a countdown or Pomodoro period ends
+    pub command: Option<String>,
 }
 
-fn load_user_environment() -> Option<UserEnvironment> {
-    if let Ok(content) = read_to_string(ETC_ENV_PATH) {
-        if let Ok(env_config) = toml::from_str::<UserEnvConfig>(&content) {
-            return Some(env_config.user_environment);
-        }
+impl TimerConfig {
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| format!("{:?}", self.kind).to_lowercase())
     }
-    None
 }
 
-fn load_expandables() -> HashMap<String, Vec<ButtonConfig>> {
-    let mut expandables = HashMap::new();
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerKind {
+    Stopwatch,
+    Countdown,
+    Pomodoro,
+}
 
-    // Load base expandables from /usr/share/tiny-dfr/expandables.toml
-    if let Ok(content) = read_to_string("/usr/share/tiny-dfr/expandables.toml") {
-        if let Ok(base_expandables) = toml::from_str::<HashMap<String, Vec<ButtonConfig>>>(&content) {
-            expandables.extend(base_expandables);
-        }
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResourceKind {
+    Cpu,
+    Memory,
+    Load,
+    Temperature,
+}
+
+/// How a resource reading is drawn: as text, over a bar filled to the reading, or
+/// over a graph of the last minute.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResourceStyle {
+    #[default]
+    Text,
+    Bar,
+    Graph,
+}
+
+/// Keyboard state shown by an `Indicator` button.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indicator {
+    CapsLock,
+    NumLock,
+    /// The active XKB layout name
+    Layout,
+    /// Symbols of the modifiers held down
+    Modifiers,
+}
+
+/// Like the battery modes: signal bars, a status line, or both.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkMode {
+    Icon,
+    Text,
+    Both,
+}
+
+/// How Battery and Volume buttons show their level: an icon, the percentage, or both.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GaugeMode {
+    Icon,
+    Percentage,
+    Both,
+}
+
+impl GaugeMode {
+    pub fn should_draw_icon(self) -> bool {
+        self != GaugeMode::Percentage
     }
 
-    // Override with system-wide expandables from /etc/tiny-dfr/expandables.toml
-    if let Ok(content) = read_to_string(ETC_EXPANDABLES_PATH) {
-        if let Ok(user_expandables) = toml::from_str::<HashMap<String, Vec<ButtonConfig>>>(&content) {
-            expandables.extend(user_expandables);
-        }
+    pub fn should_draw_text(self) -> bool {
+        self != GaugeMode::Icon
     }
+}
 
-    // Highest priority: per-user expandables from ~/.config/tiny-dfr/expandables.toml
-    let user_paths = detect_user_config_paths();
-    if let Some(p) = user_paths.expandables {
-        if let Ok(content) = read_to_string(p) {
-            if let Ok(user_expandables) = toml::from_str::<HashMap<String, Vec<ButtonConfig>>>(&content) {
-                expandables.extend(user_expandables);
-            }
+/// Which end of the bar an entry is packed against. Left entries come first, right
+/// entries end at the right edge, and center entries sit in the middle of the bar
+/// as far as the others leave room.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// Auto-repeat while a button is held, e.g. `Repeat = { Delay = 400, Rate = 20 }`.
+/// Repeating key buttons tap their keys instead of holding them down, so the
+/// compositor's own key repeat does not fire on top.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct RepeatConfig {
+    /// Milliseconds before the first repeat, 400 by default
+    pub delay: Option<u64>,
+    /// Repeats per second, 20 by default
+    pub rate: Option<u32>,
+}
+
+impl RepeatConfig {
+    pub fn delay(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.delay.unwrap_or(400))
+    }
+
+    pub fn interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(1000 / self.rate.unwrap_or(20).clamp(1, 1000) as u64)
+    }
+}
+
+/// Horizontal swipes across the bar, e.g. `Swipes = { Right = "Back", TwoFingerLeft = "NextPage" }`.
+/// Directions are the way the finger moves. Unbound swipes do nothing.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct SwipeConfig {
+    pub left: Option<SwipeAction>,
+    pub right: Option<SwipeAction>,
+    pub two_finger_left: Option<SwipeAction>,
+    pub two_finger_right: Option<SwipeAction>,
+    /// Pixels a finger travels before the touch counts as a swipe, 200 by default
+    pub distance: Option<u32>,
+}
+
+impl SwipeConfig {
+    pub fn enabled(&self) -> bool {
+        self.actions().next().is_some()
+    }
+
+    pub fn distance(&self) -> f64 {
+        self.distance.unwrap_or(200) as f64
+    }
+
+    pub fn binding(&self, left: bool, fingers: usize) -> Option<&SwipeAction> {
+        match (left, fingers >= 2) {
+            (true, false) => self.left.as_ref(),
+            (false, false) => self.right.as_ref(),
+            (true, true) => self.two_finger_left.as_ref(),
+            (false, true) => self.two_finger_right.as_ref(),
         }
     }
 
-    expandables
+    pub fn actions(&self) -> impl Iterator<Item = &SwipeAction> {
+        [&self.left, &self.right, &self.two_finger_left, &self.two_finger_right]
+            .into_iter()
+            .flatten()
+    }
 }
 
-fn load_hyprland_expandables() -> HashMap<String, Vec<HyprlandExpandConfig>> {
-    let mut hyprland_expandables = HashMap::new();
+/// What a swipe does: switch between the function and media layers, turn the page
+/// of a layer too long for the bar, leave the current expandable, or any button action.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SwipeAction {
+    NextLayer,
+    PreviousLayer,
+    NextPage,
+    PreviousPage,
+    Back,
+    Button(ButtonAction),
+}
+
+impl SwipeAction {
+    /// The actions only swipes have, by config name.
+    pub const NAMED: [(&'static str, SwipeAction); 5] = [
+        ("NextLayer", SwipeAction::NextLayer),
+        ("PreviousLayer", SwipeAction::PreviousLayer),
+        ("NextPage", SwipeAction::NextPage),
+        ("PreviousPage", SwipeAction::PreviousPage),
+        ("Back", SwipeAction::Back),
+    ];
 
-    // Load base hyprland expandables from /usr/share/tiny-dfr/hyprland.toml
-    if let Ok(content) = read_to_string("/usr/share/tiny-dfr/hyprland.toml") {
-        if let Ok(base_hyprland_expandables) = toml::from_str::<HashMap<String, Vec<HyprlandExpandConfig>>>(&content) {
-            hyprland_expandables.extend(base_hyprland_expandables);
+    pub fn json_schema() -> serde_json::Value {
+        let names: Vec<&str> = SwipeAction::NAMED.iter().map(|(name, _)| *name).collect();
+        json!({
+            "anyOf": [
+                { "type": "string", "enum": names },
+                { "$ref": "#/definitions/ButtonAction" },
+            ],
+        })
+    }
+}
+
+struct SwipeActionVisitor;
+
+impl<'de> Visitor<'de> for SwipeActionVisitor {
+    type Value = SwipeAction;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("NextLayer, PreviousLayer, NextPage, PreviousPage, Back or a button action")
+    }
+
+    // `Back` would otherwise be the kernel's Back key
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<SwipeAction, E> {
+        match SwipeAction::NAMED.into_iter().find(|(name, _)| *name == s) {
+            Some((_, action)) => Ok(action),
+            None => ButtonActionVisitor.visit_str(s).map(SwipeAction::Button),
         }
     }
 
-    // Override with system-wide hyprland expandables from /etc/tiny-dfr/hyprland.toml
-    if let Ok(content) = read_to_string("/etc/tiny-dfr/hyprland.toml") {
-        if let Ok(user_hyprland_expandables) = toml::from_str::<HashMap<String, Vec<HyprlandExpandConfig>>>(&content) {
-            hyprland_expandables.extend(user_hyprland_expandables);
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<SwipeAction, A::Error> {
+        ButtonActionVisitor.visit_seq(seq).map(SwipeAction::Button)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<SwipeAction, A::Error> {
+        ButtonActionVisitor.visit_map(map).map(SwipeAction::Button)
+    }
+}
+
+impl<'de> Deserialize<'de> for SwipeAction {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(SwipeActionVisitor)
+    }
+}
+
+fn load_commands() -> HashMap<String, String> {
+    load_layered_map(ConfigKind::Commands)
+}
+
+fn load_user_environment() -> Option<UserEnvironment> {
+    if let Ok(content) = read_to_string(ETC_ENV_PATH) {
+        if let Ok(env_config) = toml::from_str::<UserEnvConfig>(&content) {
+            return Some(env_config.user_environment);
         }
     }
+    None
+}
 
-    // Highest priority: per-user hyprland expandables from ~/.config/tiny-dfr/hyprland.toml
-    let user_paths = detect_user_config_paths();
-    if let Some(p) = user_paths.hyprland {
-        if let Ok(content) = read_to_string(p) {
-            if let Ok(user_hyprland_expandables) = toml::from_str::<HashMap<String, Vec<HyprlandExpandConfig>>>(&content) {
-                hyprland_expandables.extend(user_hyprland_expandables);
+fn load_expandables() -> HashMap<String, Vec<ButtonConfig>> {
+    load_layered_map(ConfigKind::Expandables)
+}
+
+// Hyprland layouts merge per window class rather than per expandable, so a drop-in
+// adding a layout for one app keeps the layouts other layers defined for the rest.
+fn load_hyprland_expandables() -> HashMap<String, Vec<HyprlandExpandConfig>> {
+    let mut merged: HashMap<String, Vec<HyprlandExpandConfig>> = HashMap::new();
+    for (path, table) in layered_tables(ConfigKind::Hyprland) {
+        let layer = match Value::Table(table).try_into::<HashMap<String, Vec<HyprlandExpandConfig>>>() {
+            Ok(layer) => layer,
+            Err(e) => {
+                eprintln!("Ignoring {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        for (name, layouts) in layer {
+            let existing = merged.entry(name).or_default();
+            for layout in layouts {
+                match existing.iter_mut().find(|l| l.class == layout.class) {
+                    Some(slot) => *slot = layout,
+                    None => existing.push(layout),
+                }
             }
         }
     }
-
-    hyprland_expandables
+    merged
 }
 
 fn load_font(name: &str) -> FontFace {
@@ -304,82 +1252,73 @@ fn load_font(name: &str) -> FontFace {
 fn load_config(width: u16) -> (Config, [FunctionLayer; 2]) {
     // Ensure the user environment cache is initialized so we can resolve per-user config paths
     user_cache::initialize_user_environment_cache();
+    if let Ok(mut watches) = INCLUDE_WATCHES.lock() {
+        watches.clear();
+    }
 
-    let mut base =
-        toml::from_str::<ConfigProxy>(&read_to_string("/usr/share/tiny-dfr/config.toml").unwrap())
-            .unwrap();
-    // Merge system-wide overrides from /etc
-    let user = read_to_string(ETC_CFG_PATH)
-        .map_err::<Error, _>(|e| e.into())
-        .and_then(|r| Ok(toml::from_str::<ConfigProxy>(&r)?));
-    if let Ok(user) = user {
-        base.media_layer_default = user.media_layer_default.or(base.media_layer_default);
-        base.show_button_outlines = user.show_button_outlines.or(base.show_button_outlines);
-        base.enable_pixel_shift = user.enable_pixel_shift.or(base.enable_pixel_shift);
-        base.font_template = user.font_template.or(base.font_template);
-        base.adaptive_brightness = user.adaptive_brightness.or(base.adaptive_brightness);
-        base.media_layer_keys = user.media_layer_keys.or(base.media_layer_keys);
-        base.primary_layer_keys = user.primary_layer_keys.or(base.primary_layer_keys);
-        base.active_brightness = user.active_brightness.or(base.active_brightness);
-        base.keyboard_brightness_step = user.keyboard_brightness_step.or(base.keyboard_brightness_step);
-        base.keyboard_brightness_enabled = user.keyboard_brightness_enabled.or(base.keyboard_brightness_enabled);
-        base.back_button_show_outlines = user.back_button_show_outlines.or(base.back_button_show_outlines);
-        base.back_button_outline_color = user.back_button_outline_color.or(base.back_button_outline_color);
-        base.expandable_timeout_seconds = user.expandable_timeout_seconds.or(base.expandable_timeout_seconds);
-    };
-
-    // Merge per-user overrides from ~/.config/tiny-dfr/config.toml (highest priority)
-    let user_paths = detect_user_config_paths();
-    if let Some(user_cfg_path) = user_paths.config {
-        let user_cfg = read_to_string(user_cfg_path)
-            .map_err::<Error, _>(|e| e.into())
-            .and_then(|r| Ok(toml::from_str::<ConfigProxy>(&r)?));
-        if let Ok(user) = user_cfg {
-            base.media_layer_default = user.media_layer_default.or(base.media_layer_default);
-            base.show_button_outlines = user.show_button_outlines.or(base.show_button_outlines);
-            base.enable_pixel_shift = user.enable_pixel_shift.or(base.enable_pixel_shift);
-            base.font_template = user.font_template.or(base.font_template);
-            base.adaptive_brightness = user.adaptive_brightness.or(base.adaptive_brightness);
-            base.media_layer_keys = user.media_layer_keys.or(base.media_layer_keys);
-            base.primary_layer_keys = user.primary_layer_keys.or(base.primary_layer_keys);
-            base.active_brightness = user.active_brightness.or(base.active_brightness);
-            base.keyboard_brightness_step = user.keyboard_brightness_step.or(base.keyboard_brightness_step);
-            base.keyboard_brightness_enabled = user.keyboard_brightness_enabled.or(base.keyboard_brightness_enabled);
-            base.back_button_show_outlines = user.back_button_show_outlines.or(base.back_button_show_outlines);
-            base.back_button_outline_color = user.back_button_outline_color.or(base.back_button_outline_color);
-            base.expandable_timeout_seconds = user.expandable_timeout_seconds.or(base.expandable_timeout_seconds);
+    // Merge every tier (defaults, /etc, per-user) and their drop-ins, later layers winning
+    let mut base = ConfigProxy::default();
+    for (path, table) in layered_tables(ConfigKind::Config) {
+        if upstream::is_upstream_format(&table) {
+            println!("Loading {} as an upstream tiny-dfr config", path.display());
+            for note in upstream::check(&table) {
+                eprintln!("  {}: {}", path.display(), note);
+            }
+        }
+        match Value::Table(table).try_into::<ConfigProxy>() {
+            Ok(layer) => base.merge(layer),
+            Err(e) => eprintln!("Ignoring {}: {}", path.display(), e),
         }
     }
+    base.apply_profiles(&MachineInfo::detect(width));
     let mut media_layer_keys = base.media_layer_keys.unwrap();
     let mut primary_layer_keys = base.primary_layer_keys.unwrap();
-    if width >= 2170 {
+    // Models with a 2170px bar (MacBookPro16,x) have no physical Esc key
+    if base.auto_esc.unwrap_or(width >= 2170) {
         for layer in [&mut media_layer_keys, &mut primary_layer_keys] {
             layer.insert(
                 0,
                 ButtonConfig {
-                    icon: None,
                     text: Some("esc".into()),
-                    theme: None,
-                    action: ButtonAction::Key(Key::Esc),
-                    stretch: None,
-                    time: None,
-                    locale: None,
-                    battery: None,
-                    show_button_outlines: None,
-                    button_outlines_color: None,
-                    show_app_icon_alongside_text: None,
-                    app_icon: None,
+                    action: Some(ButtonAction::Key(Key::Esc)),
+                    ..Default::default()
                 },
             );
         }
     }
-    let media_layer = FunctionLayer::with_config(media_layer_keys);
-    let fkey_layer = FunctionLayer::with_config(primary_layer_keys);
+    crate::visibility::clear_watches();
+    crate::toggle::clear_registered();
+    crate::timer::clear_registered();
+    let defaults = BatteryThresholds::default();
+    let thresholds = BatteryThresholds {
+        low: base.battery_low_percent.unwrap_or(defaults.low),
+        critical: base.battery_critical_percent.unwrap_or(defaults.critical),
+    };
+    if thresholds.critical > thresholds.low {
+        eprintln!("BatteryCriticalPercent is above BatteryLowPercent; the low state will never show");
+    }
+    crate::battery_monitor::set_thresholds(thresholds);
+    let probe_interval = base.probe_interval_seconds.unwrap_or(10);
+    if probe_interval == 0 {
+        eprintln!("ProbeIntervalSeconds must be at least 1; probing every second");
+    }
+    crate::toggle::set_probe_interval(std::time::Duration::from_secs(probe_interval.max(1) as u64));
+    let bar = Bar::new(width, base.enable_pixel_shift.unwrap(), base.min_button_width.unwrap_or(100));
+    let media_layer = FunctionLayer::with_config(media_layer_keys, bar);
+    let fkey_layer = FunctionLayer::with_config(primary_layer_keys, bar);
     let layers = if base.media_layer_default.unwrap() {
         [media_layer, fkey_layer]
     } else {
         [fkey_layer, media_layer]
     };
+    // Expandables build their buttons when opened; their timers keep running meanwhile
+    let expandables = load_expandables();
+    for button in expandables.values().flatten() {
+        if let Widget::Timer(timer) = &button.widget {
+            crate::timer::register(timer);
+        }
+    }
+    crate::timer::prune_unregistered();
     let cfg = Config {
         show_button_outlines: base.show_button_outlines.unwrap(),
         enable_pixel_shift: base.enable_pixel_shift.unwrap(),
@@ -393,7 +1332,14 @@ fn load_config(width: u16) -> (Config, [FunctionLayer; 2]) {
         back_button_show_outlines: base.back_button_show_outlines.unwrap_or(false),
         back_button_outline_color: base.back_button_outline_color,
         expandable_timeout_seconds: base.expandable_timeout_seconds.unwrap_or(5),
-        expandables: load_expandables(),
+        long_press_ms: base.long_press_ms.unwrap_or(500),
+        double_tap_ms: base.double_tap_ms.unwrap_or(250),
+        swipes: base.swipes.unwrap_or_default(),
+        bar,
+        battery_alert: base.battery_alert.unwrap_or(true),
+        battery_alert_wake: base.battery_alert_wake.unwrap_or(true),
+        battery_alert_command: base.battery_alert_command,
+        expandables,
         hyprland_expandables: load_hyprland_expandables(),
     };
     (cfg, layers)
@@ -403,17 +1349,67 @@ pub struct ConfigManager {
     inotify_fd: Inotify,
     watch_desc_etc: Option<WatchDescriptor>,
     watch_desc_user: Option<WatchDescriptor>,
+    // Drop-in directories by path, so ones created later can be told apart; None
+    // where the directory exists but cannot be watched
+    watch_desc_dropins: Vec<(PathBuf, Option<WatchDescriptor>)>,
+    watch_desc_includes: Vec<WatchDescriptor>,
 }
 
-fn arm_inotify(inotify_fd: &Inotify, path: &str) -> Option<WatchDescriptor> {
-    let flags = AddWatchFlags::IN_MOVED_TO | AddWatchFlags::IN_CLOSE | AddWatchFlags::IN_ONESHOT;
+// Missing paths are expected; anything else (EACCES, say) is logged and left unwatched
+fn add_watch(inotify_fd: &Inotify, path: &Path, flags: AddWatchFlags) -> Option<WatchDescriptor> {
     match inotify_fd.add_watch(path, flags) {
         Ok(wd) => Some(wd),
-        Err(Errno::ENOENT) => None,
-        e => Some(e.unwrap()),
+        Err(Errno::ENOENT) | Err(Errno::ENOTDIR) => None,
+        Err(e) => {
+            eprintln!("Cannot watch {} for config changes: {}", path.display(), e);
+            None
+        }
     }
 }
 
+fn arm_inotify(inotify_fd: &Inotify, path: &str) -> Option<WatchDescriptor> {
+    let flags = AddWatchFlags::IN_MOVED_TO | AddWatchFlags::IN_CLOSE | AddWatchFlags::IN_ONESHOT;
+    add_watch(inotify_fd, Path::new(path), flags)
+}
+
+// The drop-in directories that exist, /usr/share excepted as packages own it
+fn dropin_dirs() -> Vec<PathBuf> {
+    config_tiers().iter().skip(1).map(|tier| tier.join(DROPIN_DIR)).filter(|dir| dir.is_dir()).collect()
+}
+
+// Drop-in directories only fire on writes, renames and deletions so that reading the
+// files while loading does not immediately trigger another reload.
+fn arm_dropin_watches(inotify_fd: &Inotify) -> Vec<(PathBuf, Option<WatchDescriptor>)> {
+    let flags = AddWatchFlags::IN_MOVED_TO
+        | AddWatchFlags::IN_CLOSE_WRITE
+        | AddWatchFlags::IN_DELETE
+        | AddWatchFlags::IN_ONESHOT;
+    dropin_dirs()
+        .into_iter()
+        .map(|dir| {
+            let wd = add_watch(inotify_fd, &dir, flags);
+            (dir, wd)
+        })
+        .collect()
+}
+
+// Included files fire when rewritten or replaced, directories of globbed includes
+// like drop-in directories
+fn arm_include_watches(inotify_fd: &Inotify) -> Vec<WatchDescriptor> {
+    let paths = INCLUDE_WATCHES.lock().map(|watches| watches.clone()).unwrap_or_default();
+    paths
+        .iter()
+        .filter_map(|path| {
+            let flags = if path.is_dir() {
+                AddWatchFlags::IN_MOVED_TO | AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_DELETE
+            } else {
+                AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_DELETE_SELF | AddWatchFlags::IN_MOVE_SELF
+            };
+            add_watch(inotify_fd, path, flags | AddWatchFlags::IN_ONESHOT)
+        })
+        .collect()
+}
+
 impl ConfigManager {
     pub fn new() -> ConfigManager {
         let inotify_fd = Inotify::init(InitFlags::IN_NONBLOCK).unwrap();
@@ -424,14 +1420,19 @@ impl ConfigManager {
             .config
             .as_deref()
             .and_then(|p| arm_inotify(&inotify_fd, p));
+        let watch_desc_dropins = arm_dropin_watches(&inotify_fd);
         ConfigManager {
             inotify_fd,
             watch_desc_etc,
             watch_desc_user,
+            watch_desc_dropins,
+            watch_desc_includes: Vec::new(),
         }
     }
-    pub fn load_config(&self, width: u16) -> (Config, [FunctionLayer; 2]) {
-        load_config(width)
+    pub fn load_config(&mut self, width: u16) -> (Config, [FunctionLayer; 2]) {
+        let parts = load_config(width);
+        self.watch_desc_includes = arm_include_watches(&self.inotify_fd);
+        parts
     }
     pub fn update_config(
         &mut self,
@@ -442,6 +1443,12 @@ impl ConfigManager {
         if self.watch_desc_etc.is_none() {
             self.watch_desc_etc = arm_inotify(&self.inotify_fd, ETC_CFG_PATH);
         }
+        // A config.d created since the last load has drop-ins to apply
+        let dropins_appeared = dropin_dirs().iter().any(|dir| self.watch_desc_dropins.iter().all(|(watched, _)| watched != dir));
+        if dropins_appeared {
+            self.reload(cfg, layers, width);
+            return true;
+        }
         if self.watch_desc_user.is_none() {
             if let Some(user_cfg) = detect_user_config_paths().config {
                 self.watch_desc_user = arm_inotify(&self.inotify_fd, &user_cfg);
@@ -457,22 +1464,187 @@ impl ConfigManager {
     fn handle_events(&mut self, cfg: &mut Config, layers: &mut [FunctionLayer; 2], width: u16, evts: Result<Vec<InotifyEvent>, Errno>) -> bool {
         let mut ret = false;
         for evt in evts.unwrap() {
-            // React to either /etc or per-user config changes
-            if Some(evt.wd) == self.watch_desc_etc || Some(evt.wd) == self.watch_desc_user {
-                let parts = load_config(width);
-                *cfg = parts.0;
-                *layers = parts.1;
+            // React to /etc, per-user, drop-in or included config changes
+            if Some(evt.wd) == self.watch_desc_etc
+                || Some(evt.wd) == self.watch_desc_user
+                || self.watch_desc_dropins.iter().any(|(_, wd)| *wd == Some(evt.wd))
+                || self.watch_desc_includes.contains(&evt.wd)
+            {
+                self.reload(cfg, layers, width);
                 ret = true;
-                // Re-arm both watches
-                self.watch_desc_etc = arm_inotify(&self.inotify_fd, ETC_CFG_PATH);
-                if let Some(user_cfg) = detect_user_config_paths().config {
-                    self.watch_desc_user = arm_inotify(&self.inotify_fd, &user_cfg);
-                }
             }
         }
         ret
     }
+    fn reload(&mut self, cfg: &mut Config, layers: &mut [FunctionLayer; 2], width: u16) {
+        let parts = load_config(width);
+        *cfg = parts.0;
+        *layers = parts.1;
+        // Re-arm all watches
+        self.watch_desc_etc = arm_inotify(&self.inotify_fd, ETC_CFG_PATH);
+        if let Some(user_cfg) = detect_user_config_paths().config {
+            self.watch_desc_user = arm_inotify(&self.inotify_fd, &user_cfg);
+        }
+        self.watch_desc_dropins = arm_dropin_watches(&self.inotify_fd);
+        self.watch_desc_includes = arm_include_watches(&self.inotify_fd);
+    }
     pub fn fd(&self) -> &impl AsFd {
         &self.inotify_fd
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*.toml", "10-apps.toml"));
+        assert!(wildcard_match("??-*.toml", "10-apps.toml"));
+        assert!(!wildcard_match("*.toml", "apps.toml.bak"));
+        assert!(!wildcard_match("?-*.toml", "10-apps.toml"));
+    }
+
+    #[test]
+    fn test_include_merge_order() {
+        let dir = TempDir::new("include");
+        dir.write("parts/a.toml", "Command_A = \"a\"\nCommand_B = \"from-a\"\n");
+        dir.write("parts/b.toml", "Command_B = \"from-b\"\n");
+        let commands = dir.write("commands.toml", "Include = [\"parts/*.toml\"]\nCommand_A = \"own\"\n");
+
+        let table = read_table(&commands, 0).unwrap();
+        assert_eq!(table.get("Command_A").and_then(Value::as_str), Some("own"));
+        assert_eq!(table.get("Command_B").and_then(Value::as_str), Some("from-b"));
+        assert!(table.get("Include").is_none());
+
+        dir.write("parts/a.toml", "Include = \"../commands.toml\"\n");
+        assert!(read_table(&commands, 0).is_err());
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let dir = TempDir::new("include-errors");
+        let error = |contents: &str| {
+            let path = dir.write("commands.toml", contents);
+            read_table(&path, 0).unwrap_err().to_string()
+        };
+        assert!(error("Include = 3").contains("string or an array"));
+        assert!(error("Include = [3]").contains("entries must be strings"));
+        dir.write("broken.toml", "Command_A = ");
+        assert!(error("Include = \"broken.toml\"").contains("while including"));
+        assert!(error("Command_A = ").contains("TOML parse error"));
+        assert!(read_table(&dir.join("missing.toml"), 0).is_err());
+
+        // A pattern matching nothing is not an error
+        let table = read_table(&dir.write("commands.toml", "Include = \"none/*.toml\"\nCommand_A = \"a\""), 0).unwrap();
+        assert_eq!(table.get("Command_A").and_then(Value::as_str), Some("a"));
+    }
+
+    #[test]
+    fn test_profiles_apply_in_order() {
+        let mut proxy: ConfigProxy = toml::from_str(
+            r#"
+            AutoEsc = true
+            ActiveBrightness = 100
+
+            [[Profiles]]
+            Match = { ProductName = "MacBookPro16,*" }
+            AutoEsc = false
+            MediaLayerKeys = [{ Text = "F1", Action = "F1" }]
+
+            [[Profiles]]
+            Match = { ProductName = "MacBookPro16,*", Hostname = "desk-*" }
+            ActiveBrightness = 200
+
+            [[Profiles]]
+            Match = { Width = 2008 }
+            ActiveBrightness = 50
+            "#,
+        )
+        .unwrap();
+        proxy.apply_profiles(&MachineInfo {
+            product_name: "MacBookPro16,1".into(),
+            width: 2170,
+            hostname: "desk-01".into(),
+        });
+        assert_eq!(proxy.auto_esc, Some(false));
+        assert_eq!(proxy.active_brightness, Some(200));
+        assert_eq!(proxy.media_layer_keys.unwrap()[0].action, Some(ButtonAction::Key(Key::F1)));
+    }
+
+    #[test]
+    fn test_volume_mode() {
+        let button: ButtonConfig = toml::from_str("Volume = \"both\"\nVolumeSlider = true").unwrap();
+        assert!(matches!(button.widget, Widget::Volume { mode: GaugeMode::Both, slider: true }));
+        // A bad mode is a config error rather than a panic when the layer is built
+        let error = toml::from_str::<ButtonConfig>("Volume = \"loud\"").unwrap_err().to_string();
+        assert!(error.contains("unknown variant `loud`"));
+    }
+
+    #[test]
+    fn test_one_widget_per_button() {
+        let parse = |button: &str| toml::from_str::<ButtonConfig>(button).map_err(|e| e.to_string());
+        let button = parse("Icon = \"wifi\"\nText = \"Wi-Fi\"\nToggle = { OnCommand = \"On\", OffCommand = \"Off\" }").unwrap();
+        assert!(matches!(button.widget, Widget::Toggle(_)) && button.icon.is_some());
+        assert!(matches!(parse("Svg = \"back\"").unwrap().widget, Widget::Label));
+        assert!(matches!(parse("Spacer = false\nText = \"x\"").unwrap().widget, Widget::Label));
+        let error = parse("Toggle = { OnCommand = \"On\", OffCommand = \"Off\", OnText = \"on\" }").unwrap_err();
+        assert!(error.contains("Toggle button needs an Icon or Text"), "{}", error);
+
+        let error = parse("Timer = { Kind = \"Pomodoro\", Minutes = 0 }").unwrap_err();
+        assert!(error.contains("Minutes must be above 0"), "{}", error);
+        assert!(parse("Timer = { Kind = \"Pomodoro\", BreakMinutes = -5 }").unwrap_err().contains("BreakMinutes"));
+
+        let error = parse("Time = \"24hr\"\nBattery = \"both\"").unwrap_err();
+        assert!(error.contains("only be one of Time, Battery"), "{}", error);
+        assert!(parse("Spacer = true\nNotification = 1").unwrap_err().contains("Spacer, Notification"));
+        assert!(parse("Text = \"x\"\nLocale = \"fr_FR\"").unwrap_err().contains("Locale only applies to Time"));
+        assert!(parse("Network = \"icon\"\nSensor = \"nvme/*\"").unwrap_err().contains("Sensor only applies to Resource"));
+    }
+
+    #[test]
+    fn test_action_table_form() {
+        let parse = |action: &str| {
+            toml::from_str::<Table>(&format!("Action = {}", action))
+                .unwrap()
+                .remove("Action")
+                .unwrap()
+                .try_into::<ButtonAction>()
+        };
+        assert_eq!(parse(r#"{ type = "key", key = "F5" }"#).unwrap(), ButtonAction::Key(Key::F5));
+        assert_eq!(
+            parse(r#"{ type = "combo", keys = ["LeftCtrl", "C"] }"#).unwrap(),
+            ButtonAction::KeyCombos(vec![Key::LeftCtrl, Key::C])
+        );
+        assert_eq!(
+            parse(r#"{ type = "command", run = "btop" }"#).unwrap(),
+            ButtonAction::Run("btop".into())
+        );
+        assert_eq!(
+            parse(r#"{ type = "expand", name = "Hyprland_Expand_ActiveWindow" }"#).unwrap(),
+            ButtonAction::HyprlandExpand("Hyprland_Expand_ActiveWindow".into())
+        );
+        assert_eq!(parse(r#""Screenshot""#).unwrap(), ButtonAction::Command("Screenshot".into()));
+
+        let error = |action: &str| parse(action).unwrap_err().to_string();
+        assert!(error(r#""KeyCombos_CTRL_SHFIT_T""#).contains("SHFIT"));
+        assert!(error(r#"{ type = "key", key = "F55" }"#).contains("unknown key name `F55`"));
+        assert!(error(r#"{ type = "combo", keys = [] }"#).contains("at least one key"));
+        assert!(error(r#"{ type = "key", key = "F5", run = "x" }"#).contains("unknown field `run`"));
+        assert!(error(r#"{ type = "launch", run = "x" }"#).contains("unknown variant `launch`"));
+        assert!(error(r#"{ type = "expand", name = "Apps" }"#).contains("must start with Expand_"));
+    }
+
+    #[test]
+    fn test_swipe_bindings() {
+        // Swipes take navigation names before key names, so Back is not the Back key
+        let swipes: SwipeConfig =
+            toml::from_str(r#"Right = "Back"
+Left = "F5"
+TwoFingerLeft = "NextPage""#).unwrap();
+        assert_eq!(swipes.right, Some(SwipeAction::Back));
+        assert_eq!(swipes.left, Some(SwipeAction::Button(ButtonAction::Key(Key::F5))));
+        assert_eq!(swipes.binding(true, 2), Some(&SwipeAction::NextPage));
+    }
+}
@@ -0,0 +1,271 @@
+// Per-touch gesture recognition for buttons with one-shot actions. Buttons with
+// nothing but a tap fire on press and the main loop cancels their touch here. For
+// the rest a tap fires on release while the finger is still on the button, held
+// back until it is clear whether it was a long press or double tap instead.
+// The recognizer only decides; the main loop performs the actions it returns.
+// Swipes are tracked separately and take the touch away from its button.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Target {
+    pub layer: usize,
+    pub button: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gesture {
+    Tap,
+    LongPress,
+    DoubleTap,
+}
+
+/// Which secondary actions the touched button has.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Secondary {
+    pub long_press: bool,
+    pub double_tap: bool,
+}
+
+#[derive(Debug)]
+struct Touch {
+    target: Target,
+    secondary: Secondary,
+    down_at: Instant,
+    // Set once the touch fired something or slid off; its release then does nothing
+    done: bool,
+}
+
+pub struct GestureRecognizer {
+    long_press: Duration,
+    double_tap: Duration,
+    touches: HashMap<u32, Touch>,
+    // A tap held back while waiting for a second one
+    pending_tap: Option<(Target, Instant)>,
+}
+
+impl GestureRecognizer {
+    pub fn new(long_press: Duration, double_tap: Duration) -> GestureRecognizer {
+        GestureRecognizer {
+            long_press,
+            double_tap,
+            touches: HashMap::new(),
+            pending_tap: None,
+        }
+    }
+
+    pub fn set_thresholds(&mut self, long_press: Duration, double_tap: Duration) {
+        self.long_press = long_press;
+        self.double_tap = double_tap;
+    }
+
+    pub fn down(&mut self, slot: u32, target: Target, secondary: Secondary, now: Instant) -> Vec<(Target, Gesture)> {
+        let mut fired = Vec::new();
+        let mut done = false;
+        match self.pending_tap.take() {
+            Some((pending, _)) if pending == target => {
+                fired.push((target, Gesture::DoubleTap));
+                done = true;
+            }
+            // Touching something else settles the held-back tap first
+            Some((pending, _)) => fired.push((pending, Gesture::Tap)),
+            None => {}
+        }
+        self.touches.insert(
+            slot,
+            Touch {
+                target,
+                secondary,
+                down_at: now,
+                done,
+            },
+        );
+        fired
+    }
+
+    /// The finger left the button: nothing fires for this touch any more.
+    pub fn cancel(&mut self, slot: u32) {
+        if let Some(touch) = self.touches.get_mut(&slot) {
+            touch.done = true;
+        }
+    }
+
+    pub fn up(&mut self, slot: u32, now: Instant) -> Vec<(Target, Gesture)> {
+        let Some(touch) = self.touches.remove(&slot) else {
+            return Vec::new();
+        };
+        if touch.done {
+            return Vec::new();
+        }
+        if touch.secondary.double_tap {
+            self.pending_tap = Some((touch.target, now + self.double_tap));
+            return Vec::new();
+        }
+        vec![(touch.target, Gesture::Tap)]
+    }
+
+    /// Fires long presses and held-back taps whose time has come.
+    pub fn tick(&mut self, now: Instant) -> Vec<(Target, Gesture)> {
+        let mut fired = Vec::new();
+        for touch in self.touches.values_mut() {
+            if !touch.done && touch.secondary.long_press && now >= touch.down_at + self.long_press {
+                touch.done = true;
+                fired.push((touch.target, Gesture::LongPress));
+            }
+        }
+        if let Some((target, deadline)) = self.pending_tap {
+            if now >= deadline {
+                self.pending_tap = None;
+                fired.push((target, Gesture::Tap));
+            }
+        }
+        fired
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let long_presses = self
+            .touches
+            .values()
+            .filter(|t| !t.done && t.secondary.long_press)
+            .map(|t| t.down_at + self.long_press);
+        long_presses.chain(self.pending_tap.map(|(_, deadline)| deadline)).min()
+    }
+
+    /// Forgets touches the main loop no longer tracks (cleared on navigation).
+    pub fn retain_touches(&mut self, alive: impl Fn(u32) -> bool) {
+        self.touches.retain(|slot, _| alive(*slot));
+    }
+
+    /// Drops all state, e.g. when the layer was replaced and targets are stale.
+    pub fn reset(&mut self) {
+        self.touches.clear();
+        self.pending_tap = None;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Swipe {
+    pub left: bool,
+    pub fingers: usize,
+}
+
+#[derive(Debug)]
+struct SwipeTouch {
+    start_x: f64,
+    x: f64,
+    // Spent on a swipe; the touch does nothing more until lifted
+    swiped: bool,
+}
+
+/// Follows every touch on the bar, whether or not it landed on a button.
+#[derive(Default)]
+pub struct SwipeTracker {
+    touches: HashMap<u32, SwipeTouch>,
+}
+
+impl SwipeTracker {
+    pub fn down(&mut self, slot: u32, x: f64) {
+        self.touches.insert(slot, SwipeTouch { start_x: x, x, swiped: false });
+    }
+
+    /// Returns a swipe once the finger has travelled `distance`, with the slots it
+    /// spends. Other fingers that moved at least half as far the same way join in.
+    pub fn motion(&mut self, slot: u32, x: f64, distance: f64) -> Option<(Swipe, Vec<u32>)> {
+        let touch = self.touches.get_mut(&slot)?;
+        touch.x = x;
+        let dx = x - touch.start_x;
+        if touch.swiped || dx.abs() < distance {
+            return None;
+        }
+        let slots: Vec<u32> = self
+            .touches
+            .iter()
+            .filter(|(s, t)| **s == slot || (!t.swiped && (t.x - t.start_x) * dx.signum() >= distance / 2.0))
+            .map(|(s, _)| *s)
+            .collect();
+        for s in &slots {
+            if let Some(t) = self.touches.get_mut(s) {
+                t.swiped = true;
+            }
+        }
+        Some((Swipe { left: dx < 0.0, fingers: slots.len() }, slots))
+    }
+
+    pub fn is_swiping(&self, slot: u32) -> bool {
+        self.touches.get(&slot).is_some_and(|t| t.swiped)
+    }
+
+    pub fn up(&mut self, slot: u32) {
+        self.touches.remove(&slot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gestures() {
+        let mut g = GestureRecognizer::new(Duration::from_millis(500), Duration::from_millis(250));
+        let t0 = Instant::now();
+        let ms = |n| t0 + Duration::from_millis(n);
+        let a = Target { layer: 0, button: 1 };
+        let b = Target { layer: 0, button: 2 };
+        let both = Secondary { long_press: true, double_tap: true };
+
+        // Plain tap fires on release
+        assert!(g.down(0, a, Secondary::default(), t0).is_empty());
+        assert_eq!(g.up(0, ms(50)), vec![(a, Gesture::Tap)]);
+
+        // Long press fires once, release does nothing
+        g.down(0, a, both, ms(100));
+        assert_eq!(g.next_deadline(), Some(ms(600)));
+        assert!(g.tick(ms(599)).is_empty());
+        assert_eq!(g.tick(ms(600)), vec![(a, Gesture::LongPress)]);
+        assert!(g.tick(ms(700)).is_empty());
+        assert!(g.up(0, ms(800)).is_empty());
+
+        // Double tap replaces both taps
+        g.down(0, a, both, ms(1000));
+        assert!(g.up(0, ms(1050)).is_empty());
+        assert_eq!(g.down(0, a, both, ms(1150)), vec![(a, Gesture::DoubleTap)]);
+        assert!(g.up(0, ms(1200)).is_empty());
+
+        // A single tap is released after the double-tap window
+        g.down(0, a, both, ms(2000));
+        g.up(0, ms(2050));
+        assert_eq!(g.tick(ms(2300)), vec![(a, Gesture::Tap)]);
+
+        // Touching another button settles the held-back tap first
+        g.down(0, a, both, ms(3000));
+        g.up(0, ms(3050));
+        assert_eq!(g.down(1, b, Secondary::default(), ms(3100)), vec![(a, Gesture::Tap)]);
+
+        // Sliding off cancels
+        g.down(2, a, Secondary::default(), ms(4000));
+        g.cancel(2);
+        assert!(g.up(2, ms(4100)).is_empty());
+    }
+
+    #[test]
+    fn test_swipes() {
+        let mut t = SwipeTracker::default();
+        t.down(0, 1000.0);
+        assert!(t.motion(0, 850.0, 200.0).is_none());
+        assert_eq!(t.motion(0, 790.0, 200.0), Some((Swipe { left: true, fingers: 1 }, vec![0])));
+        assert!(t.is_swiping(0));
+        assert!(t.motion(0, 500.0, 200.0).is_none());
+        t.up(0);
+
+        // A second finger moving along makes it a two-finger swipe; a resting one does not
+        t.down(1, 100.0);
+        t.down(2, 300.0);
+        t.down(3, 1500.0);
+        t.motion(2, 420.0, 200.0);
+        let (swipe, mut slots) = t.motion(1, 310.0, 200.0).unwrap();
+        slots.sort();
+        assert_eq!(swipe, Swipe { left: false, fingers: 2 });
+        assert_eq!(slots, vec![1, 2]);
+        assert!(!t.is_swiping(3));
+    }
+}
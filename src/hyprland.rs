@@ -50,6 +50,17 @@ pub struct HyprlandWorkspace {
     pub name: String,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+struct HyprlandKeyboard {
+    active_keymap: String,
+    main: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct HyprlandDevices {
+    keyboards: Vec<HyprlandKeyboard>,
+}
+
 pub struct HyprlandIpc {
     socket_path: String,
     socket2_path: String,
@@ -209,6 +220,15 @@ impl HyprlandIpc {
         Ok(clients)
     }
 
+    /// Layout of the keyboard Hyprland treats as the main one.
+    pub fn get_active_layout(&self) -> Result<String> {
+        let response = self.send_command("j/devices")?;
+        let devices: HyprlandDevices = serde_json::from_str(&response)
+            .map_err(|e| anyhow!("Failed to parse devices response: {}", e))?;
+        let keyboard = devices.keyboards.iter().find(|k| k.main).or(devices.keyboards.first());
+        keyboard.map(|k| k.active_keymap.clone()).ok_or_else(|| anyhow!("No keyboards"))
+    }
+
     pub fn start_event_listener(&self) -> Result<()> {
         let socket2_path = self.socket2_path.clone();
 
@@ -276,6 +296,11 @@ impl HyprlandIpc {
                     }
                 }
             }
+        } else if let Some(data) = event_line.strip_prefix("activelayout>>") {
+            // Format: activelayout>>KEYBOARD,LAYOUT
+            if let Some((_, layout)) = data.split_once(',') {
+                crate::keyboard_state::set_layout(layout);
+            }
         }
     }
 }
@@ -329,6 +354,10 @@ pub fn get_active_window_info() -> Result<ActiveWindowInfo> {
         if !*started {
             if let Ok(()) = ipc.start_event_listener() {
                 *started = true;
+                // Later switches arrive as activelayout events
+                if let Ok(layout) = ipc.get_active_layout() {
+                    crate::keyboard_state::set_layout(&layout);
+                }
             }
         }
     }
@@ -353,6 +382,11 @@ pub fn get_active_window_info() -> Result<ActiveWindowInfo> {
     Ok(window_info)
 }
 
+/// Class of the focused window as last reported by Hyprland, without asking it.
+pub fn cached_window_class() -> Option<String> {
+    CACHED_WINDOW_INFO.lock().ok()?.as_ref().map(|info| info.class.clone())
+}
+
 pub fn check_and_reset_cache_updated() -> bool {
     if let Ok(mut updated) = CACHE_UPDATED.lock() {
         let was_updated = *updated;
@@ -363,88 +397,123 @@ pub fn check_and_reset_cache_updated() -> bool {
     }
 }
 
-pub fn parse_key_combos(action: &str) -> Vec<input_linux::Key> {
-    if !action.starts_with("KeyCombos_") {
-        return Vec::new();
-    }
+// Friendly names for KeyCombos_ tokens. Anything else must be an `input_linux::Key`
+// name (`F13`, `KpMinus`, `VolumeUp`, `RightAlt`, ...), compared case-insensitively.
+const KEY_ALIASES: &[(&str, input_linux::Key)] = &[
+    ("CTRL", input_linux::Key::LeftCtrl),
+    ("CONTROL", input_linux::Key::LeftCtrl),
+    ("SHIFT", input_linux::Key::LeftShift),
+    ("ALT", input_linux::Key::LeftAlt),
+    ("META", input_linux::Key::LeftMeta),
+    ("CMD", input_linux::Key::LeftMeta),
+    ("SUPER", input_linux::Key::LeftMeta),
+    ("WIN", input_linux::Key::LeftMeta),
+    ("RCTRL", input_linux::Key::RightCtrl),
+    ("RSHIFT", input_linux::Key::RightShift),
+    ("RALT", input_linux::Key::RightAlt),
+    ("ALTGR", input_linux::Key::RightAlt),
+    ("RMETA", input_linux::Key::RightMeta),
+    ("RSUPER", input_linux::Key::RightMeta),
+    ("RETURN", input_linux::Key::Enter),
+    ("ESCAPE", input_linux::Key::Esc),
+    ("DEL", input_linux::Key::Delete),
+    ("INS", input_linux::Key::Insert),
+    ("PGUP", input_linux::Key::PageUp),
+    ("PGDN", input_linux::Key::PageDown),
+    ("PAGEDN", input_linux::Key::PageDown),
+    ("CAPS", input_linux::Key::CapsLock),
+    // The PrintScreen key reports KEY_SYSRQ; PRINT stays the kernel's "AC Print" KEY_PRINT
+    ("PRINTSCREEN", input_linux::Key::Sysrq),
+    ("PRTSC", input_linux::Key::Sysrq),
+    ("DASH", input_linux::Key::Minus),
+    ("EQUALS", input_linux::Key::Equal),
+    ("PERIOD", input_linux::Key::Dot),
+    ("QUOTE", input_linux::Key::Apostrophe),
+    ("BACKTICK", input_linux::Key::Grave),
+    ("LBRACKET", input_linux::Key::LeftBrace),
+    ("LEFTBRACKET", input_linux::Key::LeftBrace),
+    ("RBRACKET", input_linux::Key::RightBrace),
+    ("RIGHTBRACKET", input_linux::Key::RightBrace),
+    ("PREV", input_linux::Key::PreviousSong),
+    ("MEDIASTOP", input_linux::Key::StopCD),
+    ("VOLUP", input_linux::Key::VolumeUp),
+    ("VOLDOWN", input_linux::Key::VolumeDown),
+    ("KPMULTIPLY", input_linux::Key::KpAsterisk),
+    ("KPDIVIDE", input_linux::Key::KpSlash),
+    ("KPPERIOD", input_linux::Key::KpDot),
+];
+
+static KEY_NAMES: std::sync::LazyLock<std::collections::HashMap<String, input_linux::Key>> =
+    std::sync::LazyLock::new(|| {
+        let mut names: std::collections::HashMap<String, input_linux::Key> = input_linux::Key::iter()
+            .filter(|key| key.is_key())
+            .map(|key| (format!("{:?}", key).to_uppercase(), key))
+            .collect();
+        // Bare digits are the number row, not the keypad
+        for (digit, key) in ('0'..='9').zip([
+            input_linux::Key::Num0,
+            input_linux::Key::Num1,
+            input_linux::Key::Num2,
+            input_linux::Key::Num3,
+            input_linux::Key::Num4,
+            input_linux::Key::Num5,
+            input_linux::Key::Num6,
+            input_linux::Key::Num7,
+            input_linux::Key::Num8,
+            input_linux::Key::Num9,
+        ]) {
+            names.insert(digit.to_string(), key);
+        }
+        // Kernel names win, so an alias never hides a key
+        for (alias, key) in KEY_ALIASES {
+            names.entry(alias.to_string()).or_insert(*key);
+        }
+        names
+    });
 
-    let combo_part = &action[10..]; // Remove "KeyCombos_" prefix
-    let key_parts: Vec<&str> = combo_part.split('_').collect();
+/// Looks up a key by `input_linux::Key` name or alias, ignoring case.
+pub fn lookup_key(name: &str) -> Option<input_linux::Key> {
+    KEY_NAMES.get(&name.to_uppercase()).copied()
+}
 
-    let mut keys = Vec::new();
+/// Parses `KeyCombos_CTRL_SHIFT_I` into the keys to press together. Every token must
+/// name a key; unknown tokens are an error rather than being dropped.
+pub fn parse_key_combos(action: &str) -> Result<Vec<input_linux::Key>> {
+    let combo_part = action
+        .strip_prefix("KeyCombos_")
+        .ok_or_else(|| anyhow!("'{}' does not start with KeyCombos_", action))?;
 
-    for part in key_parts {
-        let key = match part.to_uppercase().as_str() {
-            "CTRL" => input_linux::Key::LeftCtrl,
-            "SHIFT" => input_linux::Key::LeftShift,
-            "ALT" => input_linux::Key::LeftAlt,
-            "META" | "CMD" | "SUPER" => input_linux::Key::LeftMeta,
-            "A" => input_linux::Key::A,
-            "B" => input_linux::Key::B,
-            "C" => input_linux::Key::C,
-            "D" => input_linux::Key::D,
-            "E" => input_linux::Key::E,
-            "F" => input_linux::Key::F,
-            "G" => input_linux::Key::G,
-            "H" => input_linux::Key::H,
-            "I" => input_linux::Key::I,
-            "J" => input_linux::Key::J,
-            "K" => input_linux::Key::K,
-            "L" => input_linux::Key::L,
-            "M" => input_linux::Key::M,
-            "N" => input_linux::Key::N,
-            "O" => input_linux::Key::O,
-            "P" => input_linux::Key::P,
-            "Q" => input_linux::Key::Q,
-            "R" => input_linux::Key::R,
-            "S" => input_linux::Key::S,
-            "T" => input_linux::Key::T,
-            "U" => input_linux::Key::U,
-            "V" => input_linux::Key::V,
-            "W" => input_linux::Key::W,
-            "X" => input_linux::Key::X,
-            "Y" => input_linux::Key::Y,
-            "Z" => input_linux::Key::Z,
-            "F1" => input_linux::Key::F1,
-            "F2" => input_linux::Key::F2,
-            "F3" => input_linux::Key::F3,
-            "F4" => input_linux::Key::F4,
-            "F5" => input_linux::Key::F5,
-            "F6" => input_linux::Key::F6,
-            "F7" => input_linux::Key::F7,
-            "F8" => input_linux::Key::F8,
-            "F9" => input_linux::Key::F9,
-            "F10" => input_linux::Key::F10,
-            "F11" => input_linux::Key::F11,
-            "F12" => input_linux::Key::F12,
-            "ENTER" | "RETURN" => input_linux::Key::Enter,
-            "ESC" | "ESCAPE" => input_linux::Key::Esc,
-            "SPACE" => input_linux::Key::Space,
-            "TAB" => input_linux::Key::Tab,
-            "BACKSPACE" => input_linux::Key::Backspace,
-            "DELETE" => input_linux::Key::Delete,
-            "HOME" => input_linux::Key::Home,
-            "END" => input_linux::Key::End,
-            "PAGEUP" => input_linux::Key::PageUp,
-            "PAGEDOWN" => input_linux::Key::PageDown,
-            "UP" => input_linux::Key::Up,
-            "DOWN" => input_linux::Key::Down,
-            "LEFT" => input_linux::Key::Left,
-            "RIGHT" => input_linux::Key::Right,
-            "1" => input_linux::Key::Num1,
-            "2" => input_linux::Key::Num2,
-            "3" => input_linux::Key::Num3,
-            "4" => input_linux::Key::Num4,
-            "5" => input_linux::Key::Num5,
-            "6" => input_linux::Key::Num6,
-            "7" => input_linux::Key::Num7,
-            "8" => input_linux::Key::Num8,
-            "9" => input_linux::Key::Num9,
-            "0" => input_linux::Key::Num0,
-            _ => continue, // Skip unknown keys
-        };
-        keys.push(key);
+    let mut keys = Vec::new();
+    for part in combo_part.split('_') {
+        match lookup_key(part) {
+            Some(key) => keys.push(key),
+            None => return Err(anyhow!("unknown key '{}' in {}", part, action)),
+        }
     }
 
-    keys
-}
\ No newline at end of file
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use input_linux::Key;
+
+    #[test]
+    fn test_parse_key_combos() {
+        assert_eq!(parse_key_combos("KeyCombos_CTRL_MINUS").unwrap(), vec![Key::LeftCtrl, Key::Minus]);
+        assert_eq!(parse_key_combos("KeyCombos_SUPER_PRINTSCREEN").unwrap(), vec![Key::LeftMeta, Key::Sysrq]);
+        assert_eq!(parse_key_combos("KeyCombos_PRINT_PLAY_NEXT").unwrap(), vec![Key::Print, Key::Play, Key::Next]);
+        assert!(KEY_ALIASES.iter().all(|(alias, key)| lookup_key(alias) == Some(*key)));
+        assert_eq!(
+            parse_key_combos("KeyCombos_ctrl_shift_slash").unwrap(),
+            vec![Key::LeftCtrl, Key::LeftShift, Key::Slash]
+        );
+        assert_eq!(
+            parse_key_combos("KeyCombos_RightAlt_F24_KpPlus_VolumeUp_1").unwrap(),
+            vec![Key::RightAlt, Key::F24, Key::KpPlus, Key::VolumeUp, Key::Num1]
+        );
+        assert!(parse_key_combos("KeyCombos_CTRL_FOO").unwrap_err().to_string().contains("'FOO'"));
+        assert!(parse_key_combos("KeyCombos_").is_err());
+    }
+}
@@ -0,0 +1,232 @@
+use input_linux::Key;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const LEDS_PATH: &str = "/sys/class/leds";
+// How long after a lock key the LEDs may still show the old state
+const LED_SETTLE: Duration = Duration::from_millis(500);
+// The LEDs are re-read at most this often
+const LED_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A held modifier, ordered the way macOS lists them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Modifier {
+    Ctrl,
+    Alt,
+    Shift,
+    Super,
+}
+
+impl Modifier {
+    fn from_key(key: u32) -> Option<Modifier> {
+        const KEYS: [(Key, Modifier); 8] = [
+            (Key::LeftCtrl, Modifier::Ctrl),
+            (Key::RightCtrl, Modifier::Ctrl),
+            (Key::LeftAlt, Modifier::Alt),
+            (Key::RightAlt, Modifier::Alt),
+            (Key::LeftShift, Modifier::Shift),
+            (Key::RightShift, Modifier::Shift),
+            (Key::LeftMeta, Modifier::Super),
+            (Key::RightMeta, Modifier::Super),
+        ];
+        KEYS.iter().find(|(k, _)| *k as u32 == key).map(|(_, modifier)| *modifier)
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Modifier::Ctrl => "⌃",
+            Modifier::Alt => "⌥",
+            Modifier::Shift => "⇧",
+            Modifier::Super => "⌘",
+        }
+    }
+}
+
+#[derive(Default)]
+struct KeyboardState {
+    caps_lock: bool,
+    num_lock: bool,
+    locked_at: Option<Instant>,
+    synced_at: Option<Instant>,
+    // Modifier keys held down, by key code, so left and right count separately
+    held: BTreeSet<u32>,
+    layout: Option<String>,
+    changed: bool,
+}
+
+impl KeyboardState {
+    fn key(&mut self, key: u32, pressed: bool) {
+        if pressed && key == Key::CapsLock as u32 {
+            self.caps_lock = !self.caps_lock;
+            self.locked_at = Some(Instant::now());
+            self.changed = true;
+        } else if pressed && key == Key::NumLock as u32 {
+            self.num_lock = !self.num_lock;
+            self.locked_at = Some(Instant::now());
+            self.changed = true;
+        } else if Modifier::from_key(key).is_some() {
+            self.changed |= if pressed { self.held.insert(key) } else { self.held.remove(&key) };
+        }
+    }
+
+    fn modifiers(&self) -> BTreeSet<Modifier> {
+        self.held.iter().filter_map(|key| Modifier::from_key(*key)).collect()
+    }
+
+    // Takes the lock states from the keyboard LEDs where there are any
+    fn sync_leds(&mut self, leds: &Path) {
+        if self.locked_at.is_some_and(|at| at.elapsed() < LED_SETTLE) {
+            return;
+        }
+        let Ok(entries) = fs::read_dir(leds) else {
+            return;
+        };
+        let mut caps_lock = None;
+        let mut num_lock = None;
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let state = match name.rsplit_once("::") {
+                Some((_, "capslock")) => &mut caps_lock,
+                Some((_, "numlock")) => &mut num_lock,
+                _ => continue,
+            };
+            let Ok(brightness) = fs::read_to_string(entry.path().join("brightness")) else {
+                continue;
+            };
+            *state = Some(state.unwrap_or(false) || brightness.trim() != "0");
+        }
+        for (led, lock) in [(caps_lock, &mut self.caps_lock), (num_lock, &mut self.num_lock)] {
+            if let Some(lit) = led.filter(|lit| lit != lock) {
+                *lock = lit;
+                self.changed = true;
+            }
+        }
+    }
+
+    fn set_layout(&mut self, layout: &str) {
+        let layout = Some(layout.trim().to_string()).filter(|l| !l.is_empty());
+        if layout.is_some() && self.layout != layout {
+            self.layout = layout;
+            self.changed = true;
+        }
+    }
+}
+
+// Global keyboard state
+static KEYBOARD: std::sync::LazyLock<Arc<Mutex<KeyboardState>>> = std::sync::LazyLock::new(|| {
+    let mut state = KeyboardState::default();
+    state.sync_leds(Path::new(LEDS_PATH));
+    Arc::new(Mutex::new(state))
+});
+
+// Public API
+/// Follows a key from any seat0 keyboard, including the bar's own virtual one.
+pub fn key_event(key: u32, pressed: bool) {
+    if let Ok(mut state) = KEYBOARD.lock() {
+        state.key(key, pressed);
+    }
+}
+
+/// Corrects the lock states from the LEDs, for toggles made before the daemon started.
+pub fn sync_leds() {
+    if let Ok(mut state) = KEYBOARD.lock() {
+        if state.synced_at.is_none_or(|at| at.elapsed() >= LED_SYNC_INTERVAL) {
+            state.synced_at = Some(Instant::now());
+            state.sync_leds(Path::new(LEDS_PATH));
+        }
+    }
+}
+
+pub fn set_layout(layout: &str) {
+    if let Ok(mut state) = KEYBOARD.lock() {
+        state.set_layout(layout);
+    }
+}
+
+pub fn caps_lock() -> bool {
+    KEYBOARD.lock().is_ok_and(|state| state.caps_lock)
+}
+
+pub fn num_lock() -> bool {
+    KEYBOARD.lock().is_ok_and(|state| state.num_lock)
+}
+
+pub fn layout() -> Option<String> {
+    KEYBOARD.lock().ok()?.layout.clone()
+}
+
+pub fn modifiers() -> BTreeSet<Modifier> {
+    KEYBOARD.lock().map(|state| state.modifiers()).unwrap_or_default()
+}
+
+pub fn is_modifier(key: Key) -> bool {
+    Modifier::from_key(key as u32).is_some()
+}
+
+pub fn take_changed() -> bool {
+    KEYBOARD.lock().is_ok_and(|mut state| std::mem::take(&mut state.changed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_keyboard_state() {
+        let mut state = KeyboardState::default();
+        state.key(Key::CapsLock as u32, true);
+        state.key(Key::CapsLock as u32, false);
+        assert!(state.caps_lock && !state.num_lock && std::mem::take(&mut state.changed));
+
+        state.key(Key::LeftShift as u32, true);
+        state.key(Key::RightShift as u32, true);
+        state.key(Key::LeftCtrl as u32, true);
+        state.key(Key::LeftShift as u32, false);
+        let symbols: String = state.modifiers().into_iter().map(Modifier::symbol).collect();
+        assert_eq!(symbols, "⌃⇧");
+        state.key(Key::A as u32, true);
+        state.changed = false;
+        state.key(Key::A as u32, false);
+        assert!(!state.changed);
+
+        // Once settled, the LEDs win over the toggles counted from key presses
+        let leds = TempDir::new("leds");
+        for (led, brightness) in [("input3::capslock", "0"), ("input3::numlock", "1"), ("input9::capslock", "0")] {
+            leds.write(format!("{}/brightness", led), brightness);
+        }
+        state.sync_leds(&leds);
+        assert!(state.caps_lock && !state.num_lock);
+        state.locked_at = None;
+        state.sync_leds(&leds);
+        assert!(!state.caps_lock && state.num_lock && state.changed);
+
+        state.changed = false;
+        state.set_layout("English (US)\n");
+        state.set_layout("");
+        assert_eq!((state.layout.as_deref(), state.changed), (Some("English (US)"), true));
+    }
+
+    #[test]
+    fn test_keyboard_state_without_leds() {
+        let mut state = KeyboardState::default();
+        state.key(Key::NumLock as u32, true);
+        state.locked_at = None;
+        state.changed = false;
+
+        // No LED directory, or LEDs that can't be read, leave the counted state alone
+        let leds = TempDir::new("leds-errors");
+        state.sync_leds(&leds.join("missing"));
+        fs::create_dir_all(leds.join("input3::numlock")).unwrap();
+        leds.write("input3::scrolllock/brightness", "1");
+        state.sync_leds(&leds);
+        assert!(state.num_lock && !state.caps_lock && !state.changed);
+
+        // Releasing a modifier that was never seen pressed changes nothing
+        state.key(Key::RightMeta as u32, false);
+        assert!(state.modifiers().is_empty() && !state.changed);
+    }
+}
@@ -0,0 +1,373 @@
+// Text to key strokes for typing snippets through uinput. uinput sends keycodes, so
+// what a character needs depends on the layout the compositor applies: the active
+// XKB layout is looked up (Hyprland, then system keyboard config), compiled with
+// `xkbcli compile-keymap` and the resulting symbols table inverted. Without xkbcli
+// only the US layout is known; characters missing from the layout are left to the
+// fallback chosen on the action.
+use crate::hyprland::{self, HyprlandIpc};
+use input_linux::Key;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyStroke {
+    pub key: Key,
+    pub shift: bool,
+    /// Level 3, reached with AltGr (RightAlt)
+    pub altgr: bool,
+}
+
+/// What to do with characters the active layout cannot type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextFallback {
+    /// Run `wtype` in the user's session
+    #[default]
+    Wtype,
+    /// Ctrl+Shift+U, the hex code point, Space (GTK and IBus input methods)
+    Unicode,
+    /// Skip the character
+    None,
+}
+
+#[derive(Debug, Default)]
+pub struct Keymap {
+    strokes: HashMap<char, KeyStroke>,
+}
+
+impl Keymap {
+    pub fn stroke(&self, c: char) -> Option<KeyStroke> {
+        self.strokes.get(&c).copied()
+    }
+
+    pub fn us() -> Keymap {
+        let mut strokes = HashMap::new();
+        for c in (' '..='~').chain(['\n', '\t']) {
+            if let Some((key, shift)) = us_keymap(c) {
+                strokes.insert(c, KeyStroke { key, shift, altgr: false });
+            }
+        }
+        Keymap { strokes }
+    }
+
+    /// Inverts the symbols of a keymap printed by `xkbcli compile-keymap`. The
+    /// first group is used; levels 1-4 are plain, Shift, AltGr and Shift+AltGr.
+    fn from_compiled(text: &str) -> Keymap {
+        let mut keycodes: HashMap<&str, u16> = HashMap::new();
+        let mut aliases: Vec<(&str, &str)> = Vec::new();
+        for line in text.lines().map(str::trim) {
+            if let Some(alias) = line.strip_prefix("alias ") {
+                if let Some((name, target)) = alias.trim_end_matches(';').split_once('=') {
+                    aliases.push((name.trim(), target.trim()));
+                }
+            } else if let Some((name, code)) = line.trim_end_matches(';').split_once('=') {
+                if name.trim_end().starts_with('<') && name.trim_end().ends_with('>') {
+                    if let Ok(code) = code.trim().parse::<u16>() {
+                        keycodes.insert(name.trim(), code);
+                    }
+                }
+            }
+        }
+        for (name, target) in aliases {
+            if let Some(code) = keycodes.get(target).copied() {
+                keycodes.insert(name, code);
+            }
+        }
+
+        let mut strokes: HashMap<char, (u16, usize, KeyStroke)> = HashMap::new();
+        let symbols = text.find("xkb_symbols").map_or("", |i| &text[i..]);
+        for block in symbols.split("key <").skip(1) {
+            let Some((name, body)) = block.split_once('>') else { continue };
+            // Keypad symbols depend on NumLock, the main block is always safe
+            if name.starts_with("KP") {
+                continue;
+            }
+            let body = &body[..body.find("};").unwrap_or(body.len())];
+            let list = match body.find("symbols[") {
+                Some(i) => body[i..].split_once('=').map_or("", |(_, rest)| rest),
+                None => body,
+            };
+            let Some(list) = list.split_once('[').and_then(|(_, rest)| rest.split_once(']')).map(|(l, _)| l) else {
+                continue;
+            };
+            let Some(code) = keycodes.get(format!("<{}>", name).as_str()).copied() else { continue };
+            // XKB keycodes are evdev keycodes + 8
+            let Ok(key) = Key::from_code(code.saturating_sub(8)) else { continue };
+            for (level, keysym) in list.split(',').map(str::trim).enumerate().take(4) {
+                let Some(c) = keysym_to_char(keysym) else { continue };
+                let stroke = KeyStroke { key, shift: level % 2 == 1, altgr: level >= 2 };
+                let better = strokes.get(&c).is_none_or(|(old_code, old_level, _)| (level, code) < (*old_level, *old_code));
+                if better {
+                    strokes.insert(c, (code, level, stroke));
+                }
+            }
+        }
+        Keymap {
+            strokes: strokes.into_iter().map(|(c, (_, _, stroke))| (c, stroke)).collect(),
+        }
+    }
+}
+
+const ASCII_KEYSYMS: [(&str, char); 33] = [
+    ("space", ' '),
+    ("exclam", '!'),
+    ("quotedbl", '"'),
+    ("numbersign", '#'),
+    ("dollar", '$'),
+    ("percent", '%'),
+    ("ampersand", '&'),
+    ("apostrophe", '\''),
+    ("parenleft", '('),
+    ("parenright", ')'),
+    ("asterisk", '*'),
+    ("plus", '+'),
+    ("comma", ','),
+    ("minus", '-'),
+    ("period", '.'),
+    ("slash", '/'),
+    ("colon", ':'),
+    ("semicolon", ';'),
+    ("less", '<'),
+    ("equal", '='),
+    ("greater", '>'),
+    ("question", '?'),
+    ("at", '@'),
+    ("bracketleft", '['),
+    ("backslash", '\\'),
+    ("bracketright", ']'),
+    ("asciicircum", '^'),
+    ("underscore", '_'),
+    ("grave", '`'),
+    ("braceleft", '{'),
+    ("bar", '|'),
+    ("braceright", '}'),
+    ("asciitilde", '~'),
+];
+
+// Latin-1 keysyms U+00A0..U+00FF, in code point order
+const LATIN1_KEYSYMS: [&str; 96] = [
+    "nobreakspace", "exclamdown", "cent", "sterling", "currency", "yen", "brokenbar", "section",
+    "diaeresis", "copyright", "ordfeminine", "guillemotleft", "notsign", "hyphen", "registered", "macron",
+    "degree", "plusminus", "twosuperior", "threesuperior", "acute", "mu", "paragraph", "periodcentered",
+    "cedilla", "onesuperior", "masculine", "guillemotright", "onequarter", "onehalf", "threequarters", "questiondown",
+    "Agrave", "Aacute", "Acircumflex", "Atilde", "Adiaeresis", "Aring", "AE", "Ccedilla",
+    "Egrave", "Eacute", "Ecircumflex", "Ediaeresis", "Igrave", "Iacute", "Icircumflex", "Idiaeresis",
+    "ETH", "Ntilde", "Ograve", "Oacute", "Ocircumflex", "Otilde", "Odiaeresis", "multiply",
+    "Oslash", "Ugrave", "Uacute", "Ucircumflex", "Udiaeresis", "Yacute", "THORN", "ssharp",
+    "agrave", "aacute", "acircumflex", "atilde", "adiaeresis", "aring", "ae", "ccedilla",
+    "egrave", "eacute", "ecircumflex", "ediaeresis", "igrave", "iacute", "icircumflex", "idiaeresis",
+    "eth", "ntilde", "ograve", "oacute", "ocircumflex", "otilde", "odiaeresis", "division",
+    "oslash", "ugrave", "uacute", "ucircumflex", "udiaeresis", "yacute", "thorn", "ydiaeresis",
+];
+
+fn keysym_to_char(keysym: &str) -> Option<char> {
+    let mut chars = keysym.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return c.is_ascii_alphanumeric().then_some(c);
+    }
+    // U20AC; note Udiaeresis and friends also start with U
+    let unicode = keysym.strip_prefix('U').and_then(|hex| u32::from_str_radix(hex, 16).ok());
+    if let Some(c) = unicode.and_then(char::from_u32) {
+        return Some(c);
+    }
+    match keysym {
+        "Return" => return Some('\n'),
+        "Tab" => return Some('\t'),
+        "EuroSign" => return Some('€'),
+        _ => {}
+    }
+    if let Some((_, c)) = ASCII_KEYSYMS.iter().find(|(name, _)| *name == keysym) {
+        return Some(*c);
+    }
+    LATIN1_KEYSYMS
+        .iter()
+        .position(|name| *name == keysym)
+        .and_then(|i| char::from_u32(0xA0 + i as u32))
+}
+
+/// Key and whether Shift is needed to type `c` on a US layout.
+pub fn us_keymap(c: char) -> Option<(Key, bool)> {
+    let unshifted = |c: char| match c {
+        'a'..='z' | '0'..='9' => hyprland::lookup_key(&c.to_string()),
+        ' ' => Some(Key::Space),
+        '\n' => Some(Key::Enter),
+        '\t' => Some(Key::Tab),
+        '-' => Some(Key::Minus),
+        '=' => Some(Key::Equal),
+        '[' => Some(Key::LeftBrace),
+        ']' => Some(Key::RightBrace),
+        ';' => Some(Key::Semicolon),
+        '\'' => Some(Key::Apostrophe),
+        '`' => Some(Key::Grave),
+        '\\' => Some(Key::Backslash),
+        ',' => Some(Key::Comma),
+        '.' => Some(Key::Dot),
+        '/' => Some(Key::Slash),
+        _ => None,
+    };
+    let shifted = match c {
+        'A'..='Z' => c.to_ascii_lowercase(),
+        '!' => '1',
+        '@' => '2',
+        '#' => '3',
+        '$' => '4',
+        '%' => '5',
+        '^' => '6',
+        '&' => '7',
+        '*' => '8',
+        '(' => '9',
+        ')' => '0',
+        '_' => '-',
+        '+' => '=',
+        '{' => '[',
+        '}' => ']',
+        ':' => ';',
+        '"' => '\'',
+        '~' => '`',
+        '|' => '\\',
+        '<' => ',',
+        '>' => '.',
+        '?' => '/',
+        _ => return unshifted(c).map(|key| (key, false)),
+    };
+    unshifted(shifted).map(|key| (key, true))
+}
+
+/// Keys a layout may map characters to. Layouts can change while running, so text
+/// actions register the whole main keyboard block rather than what the US layout needs.
+pub fn typing_keys() -> Vec<Key> {
+    (1..=Key::MicMute as u16).filter_map(|code| Key::from_code(code).ok()).collect()
+}
+
+/// Active layout and variant: Hyprland's main keyboard, else the system keyboard
+/// configuration, else `us`.
+fn active_layout() -> (String, String) {
+    if let Some(layout) = hyprland_layout() {
+        return layout;
+    }
+    for (path, layout_key, variant_key) in [
+        ("/etc/vconsole.conf", "XKBLAYOUT=", "XKBVARIANT="),
+        ("/etc/default/keyboard", "XKBLAYOUT=", "XKBVARIANT="),
+    ] {
+        let Ok(contents) = std::fs::read_to_string(path) else { continue };
+        let value = |key: &str| {
+            contents
+                .lines()
+                .find_map(|l| l.trim().strip_prefix(key))
+                .map(|v| v.trim().trim_matches('"').to_string())
+        };
+        if let Some(layout) = value(layout_key).filter(|l| !l.is_empty()) {
+            return first_of(&layout, &value(variant_key).unwrap_or_default(), 0);
+        }
+    }
+    ("us".to_string(), String::new())
+}
+
+fn first_of(layouts: &str, variants: &str, index: usize) -> (String, String) {
+    let pick = |list: &str| list.split(',').nth(index).unwrap_or_default().trim().to_string();
+    (pick(layouts), pick(variants))
+}
+
+fn hyprland_layout() -> Option<(String, String)> {
+    let response = HyprlandIpc::new().ok()?.send_command("j/devices").ok()?;
+    let devices: serde_json::Value = serde_json::from_str(&response).ok()?;
+    let keyboards = devices["keyboards"].as_array()?;
+    let keyboard = keyboards.iter().find(|k| k["main"] == true).or(keyboards.first())?;
+    let index = keyboard["active_layout_index"].as_u64().unwrap_or(0) as usize;
+    let layout = first_of(keyboard["layout"].as_str()?, keyboard["variant"].as_str().unwrap_or(""), index);
+    (!layout.0.is_empty()).then_some(layout)
+}
+
+fn compile(layout: &str, variant: &str) -> Option<Keymap> {
+    let mut command = Command::new("xkbcli");
+    command.args(["compile-keymap", "--layout", layout]);
+    if !variant.is_empty() {
+        command.args(["--variant", variant]);
+    }
+    match command.output() {
+        Ok(output) if output.status.success() => {
+            let keymap = Keymap::from_compiled(&String::from_utf8_lossy(&output.stdout));
+            (!keymap.strokes.is_empty()).then_some(keymap)
+        }
+        Ok(output) => {
+            eprintln!("xkbcli could not compile layout '{}': {}", layout, String::from_utf8_lossy(&output.stderr).trim());
+            None
+        }
+        Err(e) => {
+            eprintln!("xkbcli not available ({}), only the us layout can be typed directly", e);
+            None
+        }
+    }
+}
+
+// Compiled keymaps by "layout(variant)"
+type KeymapCache = HashMap<String, Arc<Keymap>>;
+
+static KEYMAPS: std::sync::LazyLock<Arc<Mutex<KeymapCache>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+/// The keymap for the layout active right now. Unknown layouts yield an empty map,
+/// so every character goes to the fallback instead of being typed wrongly.
+pub fn active() -> Arc<Keymap> {
+    let (layout, variant) = active_layout();
+    let name = format!("{}({})", layout, variant);
+    if let Some(keymap) = KEYMAPS.lock().ok().and_then(|maps| maps.get(&name).cloned()) {
+        return keymap;
+    }
+    let keymap = Arc::new(match compile(&layout, &variant) {
+        Some(keymap) => keymap,
+        None if layout == "us" && variant.is_empty() => Keymap::us(),
+        None => Keymap::default(),
+    });
+    if let Ok(mut maps) = KEYMAPS.lock() {
+        maps.insert(name, keymap.clone());
+    }
+    keymap
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMPILED: &str = r#"xkb_keymap {
+xkb_keycodes "(unnamed)" {
+	minimum = 8;
+	maximum = 708;
+	<AE01>               = 10;
+	<AD06>               = 29;
+	<AC10>               = 47;
+	<LSGT>               = 94;
+	<KP1>                = 87;
+	alias <AZ01>         = <LSGT>;
+};
+xkb_symbols "(unnamed)" {
+	name[Group1]="German";
+
+	key <AE01>               {	[               1,          exclam,     onesuperior,      exclamdown ] };
+	key <AD06>               {	[               z,               Z,       leftarrow,             yen ] };
+	key <AC10>               {
+		type= "FOUR_LEVEL_SEMIALPHABETIC",
+		symbols[1]= [      odiaeresis,      Odiaeresis,     doubleacute,     doubleacute ]
+	};
+	key <AZ01>               {	[            less,         greater,             bar,       brokenbar ] };
+	key <KP1>                {	[          KP_End,            KP_1 ] };
+};
+};"#;
+
+    #[test]
+    fn test_compiled_keymap() {
+        let keymap = Keymap::from_compiled(COMPILED);
+        let stroke = |key, shift, altgr| Some(KeyStroke { key, shift, altgr });
+        assert_eq!(keymap.stroke('z'), stroke(Key::Y, false, false));
+        assert_eq!(keymap.stroke('!'), stroke(Key::Num1, true, false));
+        assert_eq!(keymap.stroke('ö'), stroke(Key::Semicolon, false, false));
+        assert_eq!(keymap.stroke('|'), stroke(Key::NonUsBackslashAndPipe, false, true));
+        assert_eq!(keymap.stroke('¦'), stroke(Key::NonUsBackslashAndPipe, true, true));
+        assert_eq!(keymap.stroke('y'), None);
+        assert_eq!(keysym_to_char("U20AC"), Some('€'));
+        assert_eq!(keysym_to_char("Udiaeresis"), Some('Ü'));
+        assert_eq!(Keymap::us().stroke('Y'), stroke(Key::Y, true, false));
+    }
+}
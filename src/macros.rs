@@ -0,0 +1,302 @@
+// Macro actions: a list of timed steps played on a worker thread so a long macro
+// (or one with sleeps) never blocks touch processing in the main loop. Macros are
+// queued and played one after another on a second handle to the uinput device.
+use crate::config::UserEnvironment;
+use crate::hyprland::{self, HyprlandIpc};
+use crate::keymap::{self, KeyStroke, TextFallback};
+use crate::user_cache;
+use input_linux::{uinput::UInputHandle, EventKind, Key, SynchronizeKind};
+use input_linux_sys::{input_event, timeval};
+use serde::{de, Deserialize, Deserializer};
+use std::fs::File;
+use std::process::Command;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum MacroStep {
+    #[serde(deserialize_with = "key_name")]
+    Press(Key),
+    #[serde(deserialize_with = "key_name")]
+    Release(Key),
+    #[serde(deserialize_with = "key_name")]
+    Tap(Key),
+    /// Keys pressed together, then released in reverse order
+    #[serde(deserialize_with = "key_names")]
+    Chord(Vec<Key>),
+    /// Typed with the active keyboard layout
+    Text(String),
+    /// Milliseconds
+    Sleep(u64),
+    Run(String),
+    /// Hyprland dispatcher with arguments, e.g. `workspace 2`
+    Dispatch(String),
+}
+
+fn key_name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Key, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    hyprland::lookup_key(&name).ok_or_else(|| de::Error::custom(format!("unknown key name `{}`", name)))
+}
+
+fn key_names<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Key>, D::Error> {
+    let names = Vec::<String>::deserialize(deserializer)?;
+    if names.is_empty() {
+        return Err(de::Error::custom("chord needs at least one key"));
+    }
+    names
+        .iter()
+        .map(|name| {
+            hyprland::lookup_key(name).ok_or_else(|| de::Error::custom(format!("unknown key name `{}`", name)))
+        })
+        .collect()
+}
+
+/// Every key a macro may emit, for registering with uinput before the device is created.
+pub fn step_keys(steps: &[MacroStep]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    for step in steps {
+        match step {
+            MacroStep::Press(key) | MacroStep::Release(key) | MacroStep::Tap(key) => keys.push(*key),
+            MacroStep::Chord(chord) => keys.extend(chord),
+            MacroStep::Text(_) => keys.extend(keymap::typing_keys()),
+            _ => {}
+        }
+    }
+    keys
+}
+
+struct QueuedMacro {
+    steps: Vec<MacroStep>,
+    fallback: TextFallback,
+    user_env: Option<UserEnvironment>,
+}
+
+// Global macro queue, set once the player thread runs
+static MACRO_QUEUE: std::sync::LazyLock<Arc<Mutex<Option<Sender<QueuedMacro>>>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(None)));
+
+pub struct MacroPlayer {
+    _handle: thread::JoinHandle<()>,
+}
+
+impl MacroPlayer {
+    /// Takes a clone of the uinput device file; the device must already be created.
+    pub fn new(uinput: File) -> Self {
+        let (sender, receiver) = mpsc::channel::<QueuedMacro>();
+        if let Ok(mut queue) = MACRO_QUEUE.lock() {
+            *queue = Some(sender);
+        }
+        let handle = thread::spawn(move || {
+            let uinput = UInputHandle::new(uinput);
+            for queued in receiver {
+                Self::play_steps(&uinput, &queued);
+            }
+        });
+
+        MacroPlayer { _handle: handle }
+    }
+
+    fn play_steps(uinput: &UInputHandle<File>, queued: &QueuedMacro) {
+        let user_env = queued.user_env.as_ref();
+        let mut held: Vec<Key> = Vec::new();
+        for step in &queued.steps {
+            match step {
+                MacroStep::Press(key) => {
+                    send_key(uinput, *key, 1);
+                    held.push(*key);
+                }
+                MacroStep::Release(key) => {
+                    send_key(uinput, *key, 0);
+                    held.retain(|k| k != key);
+                }
+                MacroStep::Tap(key) => tap(uinput, *key),
+                MacroStep::Chord(keys) => {
+                    for key in keys {
+                        send_key(uinput, *key, 1);
+                    }
+                    for key in keys.iter().rev() {
+                        send_key(uinput, *key, 0);
+                    }
+                }
+                MacroStep::Text(text) => type_text(uinput, text, queued.fallback, user_env),
+                MacroStep::Sleep(ms) => thread::sleep(Duration::from_millis(*ms)),
+                MacroStep::Run(command) => crate::run_as_user(command, user_env),
+                MacroStep::Dispatch(dispatch) => {
+                    let result = HyprlandIpc::new()
+                        .and_then(|ipc| ipc.send_command(&format!("dispatch {}", dispatch)));
+                    if let Err(e) = result {
+                        eprintln!("Macro: Hyprland dispatch '{}' failed: {}", dispatch, e);
+                    }
+                }
+            }
+        }
+        // Never leave keys stuck down when a macro forgets to release them
+        for key in held.iter().rev() {
+            send_key(uinput, *key, 0);
+        }
+    }
+}
+
+/// Queues a macro on the player thread.
+pub fn play(steps: &[MacroStep], user_env: Option<UserEnvironment>) {
+    queue(QueuedMacro {
+        steps: steps.to_vec(),
+        fallback: TextFallback::default(),
+        user_env,
+    });
+}
+
+/// Queues a text snippet on the player thread.
+pub fn type_snippet(text: &str, fallback: TextFallback, user_env: Option<UserEnvironment>) {
+    queue(QueuedMacro {
+        steps: vec![MacroStep::Text(text.to_string())],
+        fallback,
+        user_env,
+    });
+}
+
+fn queue(queued: QueuedMacro) {
+    match MACRO_QUEUE.lock().ok().as_ref().and_then(|queue| queue.as_ref()) {
+        Some(sender) => {
+            let _ = sender.send(queued);
+        }
+        None => eprintln!("Macro player not running"),
+    }
+}
+
+fn send_key(uinput: &UInputHandle<File>, key: Key, value: i32) {
+    let event = |type_: EventKind, code: u16, value: i32| input_event {
+        value,
+        type_: type_ as u16,
+        code,
+        time: timeval { tv_sec: 0, tv_usec: 0 },
+    };
+    let events = [
+        event(EventKind::Key, key as u16, value),
+        event(EventKind::Synchronize, SynchronizeKind::Report as u16, 0),
+    ];
+    if let Err(e) = uinput.write(&events) {
+        eprintln!("Macro: failed to send key {:?}: {}", key, e);
+    }
+}
+
+fn tap(uinput: &UInputHandle<File>, key: Key) {
+    send_key(uinput, key, 1);
+    send_key(uinput, key, 0);
+}
+
+fn type_stroke(uinput: &UInputHandle<File>, stroke: KeyStroke) {
+    let modifiers: Vec<Key> = [(stroke.altgr, Key::RightAlt), (stroke.shift, Key::LeftShift)]
+        .into_iter()
+        .filter_map(|(needed, key)| needed.then_some(key))
+        .collect();
+    for key in &modifiers {
+        send_key(uinput, *key, 1);
+    }
+    tap(uinput, stroke.key);
+    for key in modifiers.iter().rev() {
+        send_key(uinput, *key, 0);
+    }
+}
+
+/// Types `text` with the active layout. Runs of characters the layout lacks go to
+/// the fallback in place, so the snippet keeps its order.
+fn type_text(uinput: &UInputHandle<File>, text: &str, fallback: TextFallback, user_env: Option<&UserEnvironment>) {
+    let keymap = keymap::active();
+    let mut missing = String::new();
+    for c in text.chars() {
+        match keymap.stroke(c) {
+            Some(stroke) => {
+                if !missing.is_empty() {
+                    type_missing(uinput, &std::mem::take(&mut missing), fallback, user_env);
+                }
+                type_stroke(uinput, stroke);
+            }
+            None => missing.push(c),
+        }
+    }
+    if !missing.is_empty() {
+        type_missing(uinput, &missing, fallback, user_env);
+    }
+}
+
+fn type_missing(uinput: &UInputHandle<File>, text: &str, fallback: TextFallback, user_env: Option<&UserEnvironment>) {
+    match fallback {
+        TextFallback::Wtype => wtype(text, user_env),
+        TextFallback::Unicode => {
+            let keymap = keymap::active();
+            let us = keymap::Keymap::us();
+            for c in text.chars() {
+                send_key(uinput, Key::LeftCtrl, 1);
+                send_key(uinput, Key::LeftShift, 1);
+                tap(uinput, Key::U);
+                send_key(uinput, Key::LeftShift, 0);
+                send_key(uinput, Key::LeftCtrl, 0);
+                for digit in format!("{:x}", c as u32).chars() {
+                    if let Some(stroke) = keymap.stroke(digit).or_else(|| us.stroke(digit)) {
+                        type_stroke(uinput, stroke);
+                    }
+                }
+                tap(uinput, Key::Space);
+            }
+        }
+        TextFallback::None => eprintln!("Cannot type '{}' with the active layout", text),
+    }
+}
+
+// Runs wtype as the desktop user and waits, so later key strokes land after its text
+fn wtype(text: &str, user_env: Option<&UserEnvironment>) {
+    let Some(cached_env) = user_cache::get_cached_user_environment() else {
+        eprintln!("Cannot run wtype: desktop user not known yet");
+        return;
+    };
+    let wayland_display = user_env.map_or(cached_env.wayland_display.clone(), |env| env.wayland_display.clone());
+    let status = Command::new("sudo")
+        .args(["-u", &cached_env.username, "env"])
+        .arg(format!("XDG_RUNTIME_DIR={}", cached_env.runtime_dir))
+        .arg(format!("WAYLAND_DISPLAY={}", wayland_display))
+        .args(["wtype", "--", text])
+        .status();
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!("wtype exited with {}", status),
+        Err(e) => eprintln!("Failed to run wtype: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ButtonAction;
+
+    #[test]
+    fn test_macro_steps() {
+        let table: toml::Table = toml::from_str(
+            r#"Action = { type = "macro", steps = [
+                { chord = ["CTRL", "K"] },
+                { chord = ["LeftCtrl", "C"] },
+                { sleep = 50 },
+                { text = "Hi!" },
+                { dispatch = "workspace 2" },
+            ] }"#,
+        )
+        .unwrap();
+        let action: ButtonAction = table["Action"].clone().try_into().unwrap();
+        let ButtonAction::Macro(steps) = &action else {
+            panic!("not a macro: {:?}", action);
+        };
+        assert_eq!(steps[0], MacroStep::Chord(vec![Key::LeftCtrl, Key::K]));
+        assert_eq!(steps[2], MacroStep::Sleep(50));
+        let keys = action.keys();
+        for key in [Key::K, Key::C, Key::LeftShift, Key::RightAlt, Key::H, Key::Num1] {
+            assert!(keys.contains(&key), "{:?} not registered", key);
+        }
+
+        let bad: toml::Table = toml::from_str(r#"Action = { type = "macro", steps = [{ tap = "Hyper" }] }"#).unwrap();
+        let error = bad["Action"].clone().try_into::<ButtonAction>().unwrap_err();
+        assert!(error.to_string().contains("unknown key name `Hyper`"), "{}", error);
+    }
+}
@@ -1,6 +1,7 @@
 use anyhow::{anyhow, Result};
 use cairo::{Antialias, Context, Format, ImageSurface, Surface};
-use chrono::{Local, Locale, Timelike, format::{StrftimeItems, Item as ChronoItem}};
+use chrono::{DateTime, Local, Locale, Timelike, Utc, format::{Fixed, Numeric, StrftimeItems, Item as ChronoItem}};
+use chrono_tz::Tz;
 use drm::control::ClipRect;
 use freedesktop_icons::lookup;
 use input::{
@@ -26,7 +27,7 @@ use nix::{
 use std::{
     cmp::min,
     collections::HashMap,
-    fs::{self, File, OpenOptions},
+    fs::{File, OpenOptions},
     os::{
         fd::{AsFd, AsRawFd},
         unix::{fs::OpenOptionsExt, io::OwnedFd},
@@ -36,31 +37,66 @@ use std::{
 };
 use udev::MonitorBuilder;
 
+mod audio;
 mod backlight;
+mod battery_alert;
 mod battery_monitor;
 mod config;
 mod display;
 mod fonts;
+mod gesture;
 mod hyprland;
 mod icon_cache;
 mod keyboard_backlight;
+mod keyboard_state;
+mod keymap;
+mod macros;
+mod network;
+mod notifications;
 mod pixel_shift;
+mod resource_monitor;
+mod schema;
+mod shared_state;
 mod system_monitor;
+#[cfg(test)]
+mod test_util;
+mod timer;
+mod toggle;
+mod upstream;
 mod user_cache;
+mod visibility;
 
 use crate::config::ConfigManager;
+use crate::battery_alert::{AlertChange, BatteryAlert};
 use crate::battery_monitor::BatteryState;
 use backlight::BacklightManager;
-use config::{ButtonConfig, Config, ButtonAction, ButtonColor};
+use config::{Align, ButtonConfig, Config, ButtonAction, ButtonColor, GaugeMode, Indicator, NetworkMode, RepeatConfig, ResourceStyle, SwipeAction, UserEnvironment, Widget};
 use display::DrmBackend;
+use gesture::{Gesture, GestureRecognizer, SwipeTracker};
 use keyboard_backlight::KeyboardBacklightManager;
 use pixel_shift::{PixelShiftManager, PIXEL_SHIFT_WIDTH_PX};
+use visibility::Condition;
 
 const BUTTON_SPACING_PX: i32 = 16;
 const BUTTON_COLOR_INACTIVE: f64 = 0.200;
 const BUTTON_COLOR_ACTIVE: f64 = 0.400;
 const ICON_SIZE: i32 = 48;
+const PAGE_ARROW_WIDTH_PX: f64 = 64.0;
 const TIMEOUT_MS: i32 = 10 * 1000;
+// Polled VisibleWhen facts and toggle probes are rechecked this often
+const STATE_POLL_MS: i32 = 2 * 1000;
+const RESOURCE_SAMPLE_MS: i32 = 1000;
+const NETWORK_BARS: f64 = 4.0;
+const MIC_MUTED_COLOR: ButtonColor = ButtonColor::Rgb([0.6, 0.1, 0.1]);
+const TIMER_FLASH_COLOR: ButtonColor = ButtonColor::Rgb([0.8, 0.5, 0.0]);
+const INDICATOR_ON_COLOR: ButtonColor = ButtonColor::Rgb([0.1, 0.4, 0.7]);
+const DND_COLOR: ButtonColor = ButtonColor::Rgb([0.4, 0.2, 0.6]);
+// What a Layout indicator runs on tap unless given an Action
+const LAYOUT_SWITCH_COMMAND: &str = "hyprctl switchxkblayout all next";
+// How far a finger moves on a volume slider before it drags instead of tapping
+const SLIDER_START_PX: f64 = 12.0;
+// Wi-Fi signal strength changes raise no netlink event, so it is re-read this often
+const NETWORK_SIGNAL_REFRESH: std::time::Duration = std::time::Duration::from_secs(10);
 
 #[derive(Clone, Debug)]
 struct NavigationState {
@@ -70,11 +106,44 @@ struct NavigationState {
 }
 
 #[derive(Clone, Debug)]
-struct PendingAction {
-    action: ButtonAction,
-    execution_time: std::time::Instant,
-    button_index: usize,
-    layer_index: usize,
+struct KeyRepeat {
+    layer: usize,
+    button: usize,
+    next: std::time::Instant,
+    interval: std::time::Duration,
+}
+
+impl KeyRepeat {
+    fn new(layer: usize, button: usize, repeat: RepeatConfig, now: std::time::Instant) -> KeyRepeat {
+        KeyRepeat { layer, button, next: now + repeat.delay(), interval: repeat.interval() }
+    }
+
+    // A repeat lasts while its touch stays on the button it started on, in that layer
+    fn held_by(&self, touch: Option<&(usize, usize)>) -> bool {
+        touch == Some(&(self.layer, self.button))
+    }
+
+    // Whether the action repeats at `now`. Repeats missed during a stall are skipped
+    // rather than fired in a burst.
+    fn due(&mut self, now: std::time::Instant) -> bool {
+        if now < self.next {
+            return false;
+        }
+        self.next += self.interval;
+        if self.next <= now {
+            self.next = now + self.interval;
+        }
+        true
+    }
+}
+
+// A finger on a volume slider; dragging one button width covers the full range
+#[derive(Clone, Debug)]
+struct VolumeSlider {
+    start_x: f64,
+    start_volume: u32,
+    button_width: f64,
+    sliding: bool,
 }
 
 impl NavigationState {
@@ -132,30 +201,49 @@ struct BatteryImages {
     bolt: Handle,
 }
 
-#[derive(Eq, PartialEq, Copy, Clone)]
-enum BatteryIconMode {
-    Percentage,
-    Icon,
-    Both
-}
-
-impl BatteryIconMode {
-    fn should_draw_icon(self) -> bool {
-        self != BatteryIconMode::Percentage
-    }
-    fn should_draw_text(self) -> bool {
-        self != BatteryIconMode::Icon
-    }
-}
-
 #[derive(Clone)]
 enum ButtonImage {
     Text(String),
     Svg(Handle),
     Bitmap(ImageSurface),
-    Time(Vec<ChronoItem<'static>>, Locale),
-    Battery(String, BatteryIconMode, BatteryImages),
+    // Format, locale, and a zone for world clocks
+    Time(Vec<ChronoItem<'static>>, Locale, Option<Tz>),
+    // Mode, icons, and whether the time remaining follows the percentage
+    Battery(GaugeMode, BatteryImages, bool),
     TextWithIcon(String, Handle),
+    // A toggle by name, drawn as its off or on image
+    Toggle(String, Box<ToggleImages>),
+    Resource(resource_monitor::Source, ResourceStyle, String),
+    Network(NetworkMode),
+    // Mode, volume_off/down/up icons, and whether a press shows a slider
+    Volume(GaugeMode, Box<[Handle; 3]>, bool),
+    // By timer name
+    Timer(String),
+    // The keyboard state shown, with the label of the lock indicators
+    Indicator(Indicator, String),
+    // Unread count after a label
+    Notifications(String),
+    // A recent notification by its 1-based slot
+    Notification(usize),
+}
+
+#[derive(Clone)]
+struct ToggleImages {
+    off: ButtonImage,
+    on: ButtonImage,
+    off_color: Option<ButtonColor>,
+    on_color: Option<ButtonColor>,
+}
+
+impl ButtonImage {
+    // The image to draw, which for a toggle depends on its state
+    fn resolved(&self) -> &ButtonImage {
+        match self {
+            ButtonImage::Toggle(name, images) if toggle::is_on(name) => &images.on,
+            ButtonImage::Toggle(_, images) => &images.off,
+            image => image,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -166,6 +254,9 @@ struct Button {
     action: ButtonAction,
     show_outline: Option<bool>,
     outline_color: Option<ButtonColor>,
+    repeat: Option<RepeatConfig>,
+    long_press_action: Option<ButtonAction>,
+    double_tap_action: Option<ButtonAction>,
 }
 
 fn try_load_svg(path: &str) -> Result<ButtonImage> {
@@ -268,75 +359,249 @@ fn try_load_image_sync(name: &str, theme: Option<&str>) -> Result<ButtonImage> {
     Err(last_err.context(format!("failed loading all possible paths for icon {name}")))
 }
 
-fn find_battery_device() -> Option<String> {
-    let power_supply_path = "/sys/class/power_supply";
-    if let Ok(entries) = fs::read_dir(power_supply_path) {
-        for entry in entries.flatten() {
-            let dev_path = entry.path();
-            let type_path = dev_path.join("type");
-            if let Ok(typ) = fs::read_to_string(&type_path) {
-                if typ.trim() == "Battery" {
-                    if let Some(name) = dev_path.file_name().and_then(|n| n.to_str()) {
-                        return Some(name.to_string());
-                    }
-                }
-            }
-        }
+// A clock's text at `now`, in its zone or else the local one
+fn format_time(format: &[ChronoItem<'static>], locale: Locale, zone: Option<&Tz>, now: DateTime<Utc>) -> String {
+    match zone {
+        Some(zone) => now.with_timezone(zone).format_localized_with_items(format.iter(), locale).to_string(),
+        None => now.with_timezone(&Local).format_localized_with_items(format.iter(), locale).to_string(),
     }
-    None
 }
 
-fn get_battery_state(battery: &str) -> (u32, BatteryState) {
-    // Try to get cached battery state first
-    if let Some(cached_state) = battery_monitor::get_cached_battery_state() {
-        return cached_state;
+// Milliseconds until clocks need redrawing: past the next minute, or the next second
+// while one on screen shows seconds
+fn ms_to_clock_tick(now: &impl Timelike, seconds: bool) -> i32 {
+    if seconds {
+        1000 - (now.nanosecond() / 1_000_000).min(999) as i32
+    } else {
+        (60 - now.second() as i32) * 1000
     }
+}
 
-    // Fallback to direct reading if cache is not available
-    get_battery_state_direct(battery)
+// "1:05" for a battery time estimate
+fn format_time_remaining(time: std::time::Duration) -> String {
+    let minutes = time.as_secs() / 60;
+    format!("{}:{:02}", minutes / 60, minutes % 60)
 }
 
-fn get_battery_state_direct(battery: &str) -> (u32, BatteryState) {
-    let status_path = format!("/sys/class/power_supply/{}/status", battery);
-    let status = fs::read_to_string(&status_path)
-        .unwrap_or_else(|_| "Unknown".to_string());
-
-    #[cfg(target_arch = "x86_64")]
-    let capacity = {
-        let charge_now_path = format!("/sys/class/power_supply/{}/charge_now", battery);
-        let charge_full_path = format!("/sys/class/power_supply/{}/charge_full", battery);
-        let charge_now = fs::read_to_string(&charge_now_path)
-            .ok()
-            .and_then(|s| s.trim().parse::<f64>().ok());
-        let charge_full = fs::read_to_string(&charge_full_path)
-            .ok()
-            .and_then(|s| s.trim().parse::<f64>().ok());
-        match (charge_now, charge_full) {
-            (Some(now), Some(full)) if full > 0.0 => ((now / full) * 100.0).round() as u32,
-            _ => 100,
+// The text, cut short with an ellipsis if wider than `max_width`, and its extents
+fn fit_text(c: &Context, text: &str, max_width: f64) -> (String, cairo::TextExtents) {
+    let original_extents = c.text_extents(text).unwrap();
+    if original_extents.width() <= max_width {
+        return (text.to_string(), original_extents);
+    }
+    let ellipsis = "...";
+    let ellipsis_width = c.text_extents(ellipsis).unwrap().width();
+    let target_width = max_width - ellipsis_width;
+
+    // Binary search to find optimal text length
+    let mut left = 0;
+    let mut right = text.chars().count();
+    while left < right {
+        let mid = (left + right + 1) / 2;
+        let test_text: String = text.chars().take(mid).collect();
+        let test_width = c.text_extents(&test_text).unwrap().width();
+        if test_width <= target_width {
+            left = mid;
+        } else {
+            right = mid - 1;
         }
-    };
+    }
 
-    #[cfg(target_arch = "aarch64")]
-    let capacity = {
-        let capacity_path = format!("/sys/class/power_supply/{}/capacity", battery);
-        fs::read_to_string(&capacity_path)
-            .ok()
-            .and_then(|s| s.trim().parse::<u32>().ok())
-            .unwrap_or(100)
-    };
+    let final_text: String = text.chars().take(left).collect::<String>() + ellipsis;
+    let final_extents = c.text_extents(&final_text).unwrap();
+    (final_text, final_extents)
+}
 
-    let status = match status.trim() {
-        "Charging" | "Full" => BatteryState::Charging,
-        "Discharging" if capacity < 10 => BatteryState::Low,
-        _ => BatteryState::NotCharging,
+// Lit lock indicators, and the modifier indicator while a modifier is held
+fn indicator_on(indicator: Indicator) -> bool {
+    match indicator {
+        Indicator::CapsLock => keyboard_state::caps_lock(),
+        Indicator::NumLock => keyboard_state::num_lock(),
+        Indicator::Layout => false,
+        Indicator::Modifiers => !keyboard_state::modifiers().is_empty(),
+    }
+}
+
+// The critical battery notice, across the whole bar in place of the layer
+fn draw_battery_alert(config: &Config, width: i32, height: i32, surface: &Surface, pixel_shift: (f64, f64), bright: bool) -> Vec<ClipRect> {
+    let c = Context::new(surface).unwrap();
+    c.translate(height as f64, 0.0);
+    c.rotate((90.0f64).to_radians());
+    c.set_source_rgb(0.0, 0.0, 0.0);
+    c.paint().unwrap();
+
+    let pixel_shift_width = if config.enable_pixel_shift { PIXEL_SHIFT_WIDTH_PX } else { 0 };
+    let (pixel_shift_x, pixel_shift_y) = pixel_shift;
+    let left = pixel_shift_x + (pixel_shift_width / 2) as f64;
+    let red = if bright { 0.8 } else { 0.45 };
+    c.set_source_rgb(red, 0.0, 0.0);
+    c.rectangle(left, height as f64 * 0.1, (width - pixel_shift_width as i32) as f64, height as f64 * 0.8);
+    c.fill().unwrap();
+
+    let info = battery_monitor::get_cached_battery_state();
+    let text = match info.as_ref().map(|info| (info.charge_text(), info.time_remaining)) {
+        Some((charge, Some(time))) => format!("Battery critical: {}, {} left", charge, format_time_remaining(time)),
+        Some((charge, None)) => format!("Battery critical: {}", charge),
+        None => "Battery critical: 0%".to_string(),
     };
-    (capacity, status)
+    c.set_font_face(&config.font_face);
+    c.set_font_size(32.0);
+    c.set_source_rgb(1.0, 1.0, 1.0);
+    let extents = c.text_extents(&text).unwrap();
+    c.move_to(
+        left + ((width - pixel_shift_width as i32) as f64 / 2.0 - extents.width() / 2.0).round(),
+        pixel_shift_y + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+    );
+    c.show_text(&text).unwrap();
+    vec![ClipRect::new(0, 0, height as u16, width as u16)]
 }
 
 impl Button {
-    fn with_config(cfg: ButtonConfig) -> Button {
-        let mut button = if let Some(text) = cfg.text {
+    fn secondary(&self) -> gesture::Secondary {
+        gesture::Secondary {
+            long_press: self.long_press_action.is_some(),
+            double_tap: self.double_tap_action.is_some(),
+        }
+    }
+
+    fn has_secondary(&self) -> bool {
+        self.long_press_action.is_some() || self.double_tap_action.is_some()
+    }
+
+    fn gesture_action(&self, gesture: Gesture) -> Option<&ButtonAction> {
+        match gesture {
+            Gesture::Tap => Some(&self.action),
+            Gesture::LongPress => self.long_press_action.as_ref(),
+            Gesture::DoubleTap => self.double_tap_action.as_ref(),
+        }
+    }
+
+    fn keys(&self) -> Vec<Key> {
+        let secondary = self.long_press_action.iter().chain(&self.double_tap_action);
+        std::iter::once(&self.action).chain(secondary).flat_map(ButtonAction::keys).collect()
+    }
+
+    // Plain key buttons hold their keys down for as long as the touch. Volume buttons
+    // don't: a press may turn into a slide, so their key (Mute by default) waits for a tap.
+    fn holds_keys(&self) -> bool {
+        self.repeat.is_none()
+            && !self.has_secondary()
+            && !matches!(self.image, ButtonImage::Volume(..))
+            && matches!(self.action, ButtonAction::Key(_) | ButtonAction::KeyCombos(_))
+    }
+
+    // Whether a touch holds the keys down. Swipes are bound for the whole bar rather
+    // than per layer, so with any bound a touch may still turn into a swipe and key
+    // buttons send a tap instead. Modifiers, Fn and arrows are only useful held, so
+    // they keep holding and a swipe starting on them lets go of the keys.
+    fn holds_keys_under(&self, config: &Config) -> bool {
+        self.holds_keys() && !self.is_kbd_backlight(config) && (!config.swipes.enabled() || self.only_held_keys())
+    }
+
+    fn only_held_keys(&self) -> bool {
+        let keys = match &self.action {
+            ButtonAction::Key(key) => std::slice::from_ref(key),
+            ButtonAction::KeyCombos(keys) => keys.as_slice(),
+            _ => &[],
+        };
+        !keys.is_empty()
+            && keys.iter().all(|&key| {
+                keyboard_state::is_modifier(key) || matches!(key, Key::Fn | Key::Up | Key::Down | Key::Left | Key::Right)
+            })
+    }
+
+    // Keyboard backlight keys adjust the backlight directly when it is managed here
+    fn is_kbd_backlight(&self, config: &Config) -> bool {
+        config.keyboard_brightness_enabled && matches!(self.action, ButtonAction::Key(Key::IllumUp | Key::IllumDown))
+    }
+
+    // Whether holding the button repeats its action; navigation never repeats and
+    // a hold on a button with secondary actions is a long press instead
+    fn repeats(&self) -> bool {
+        self.repeat.is_some()
+            && !self.has_secondary()
+            && match &self.action {
+                ButtonAction::Command(command_id) => command_id != "Back",
+                ButtonAction::Expand(_) | ButtonAction::HyprlandExpand(_) => false,
+                _ => true,
+            }
+    }
+
+    fn with_config(cfg: ButtonConfig, action: ButtonAction) -> Button {
+        let theme = cfg.theme.clone();
+        let mut button = match &cfg.widget {
+            Widget::Resource { kind, style, sensor } => {
+                let source = resource_monitor::Source { kind: *kind, sensor: sensor.clone() };
+                resource_monitor::watch(&source);
+                let label = cfg.text.clone().unwrap_or_else(|| source.label().to_string());
+                Button::with_image(action, ButtonImage::Resource(source, *style, label))
+            }
+            Widget::Timer(timer) => {
+                timer::register(timer);
+                Button::with_image(action, ButtonImage::Timer(timer.name()))
+            }
+            Widget::Indicator(indicator) => {
+                let label = cfg.text.clone().unwrap_or_else(|| match indicator {
+                    Indicator::CapsLock => "Caps".to_string(),
+                    Indicator::NumLock => "Num".to_string(),
+                    Indicator::Layout | Indicator::Modifiers => String::new(),
+                });
+                Button::with_image(action, ButtonImage::Indicator(*indicator, label))
+            }
+            Widget::Notifications => {
+                notifications::watch();
+                let label = cfg.text.clone().unwrap_or_else(|| "Notifications".to_string());
+                Button::with_image(action, ButtonImage::Notifications(label))
+            }
+            Widget::Notification(slot) => {
+                notifications::watch();
+                Button::with_image(action, ButtonImage::Notification(*slot))
+            }
+            Widget::Network(mode) => Button::with_image(action, ButtonImage::Network(*mode)),
+            Widget::Volume { mode, slider } => Button::new_volume(action, *mode, *slider, cfg.theme.as_ref()),
+            Widget::Time { format, locale, time_zone } => {
+                Button::new_time(action, format, locale.as_deref(), time_zone.as_deref())
+            }
+            Widget::Battery { mode, show_time } => {
+                if battery_monitor::get_cached_battery_state().is_some() {
+                    Button::new_battery(action, *mode, *show_time, cfg.theme.as_ref())
+                } else {
+                    Button::new_text("Battery N/A".to_string(), action)
+                }
+            }
+            Widget::Label | Widget::Toggle(_) | Widget::Spacer => Button::with_label(&cfg, action),
+        };
+
+        button.show_outline = cfg.show_button_outlines;
+        button.outline_color = cfg.button_outlines_color;
+        button.repeat = cfg.repeat;
+        button.long_press_action = cfg.long_press_action;
+        button.double_tap_action = cfg.double_tap_action;
+        if button.shows_audio() {
+            audio::watch();
+        }
+        if let Widget::Toggle(toggle) = cfg.widget {
+            toggle::register(&toggle);
+            let off = std::mem::replace(&mut button.image, ButtonImage::Text(String::new()));
+            let on = if let Some(text) = toggle.on_text {
+                ButtonImage::Text(text)
+            } else if let Some(icon) = toggle.on_icon {
+                try_load_image(&icon, theme).unwrap_or_else(|e| {
+                    eprintln!("Failed to load toggle icon '{}': {}", icon, e);
+                    off.clone()
+                })
+            } else {
+                off.clone()
+            };
+            let images = ToggleImages { off, on, off_color: toggle.off_color, on_color: toggle.on_color };
+            button.image = ButtonImage::Toggle(toggle.name.unwrap_or(toggle.on_command), Box::new(images));
+        }
+        button
+    }
+
+    // A button showing its Text or Icon, or the active window for the Hyprland plugin
+    fn with_label(cfg: &ButtonConfig, action: ButtonAction) -> Button {
+        if let Some(text) = cfg.text.clone() {
             if text == "plugin-hyprland" {
                 // Get Hyprland active window text - use "title" as default button title
                 let (window_text, window_class) = match hyprland::get_active_window_info() {
@@ -362,17 +627,17 @@ impl Button {
 
                     // Check if the icon exists before trying to create TextWithIcon button
                     if try_load_image(&icon_name, cfg.theme.as_deref()).is_ok() {
-                        Button::new_text_with_icon(window_text, icon_name, cfg.theme, cfg.action)
+                        Button::new_text_with_icon(window_text, icon_name, cfg.theme.clone(), action)
                     } else {
-                        Button::new_text(window_text, cfg.action)
+                        Button::new_text(window_text, action)
                     }
                 } else {
-                    Button::new_text(window_text, cfg.action)
+                    Button::new_text(window_text, action)
                 }
             } else {
-                Button::new_text(text, cfg.action)
+                Button::new_text(text, action)
             }
-        } else if let Some(icon) = cfg.icon {
+        } else if let Some(icon) = &cfg.icon {
             if icon == "plugin-hyprland" || icon == "plugin-hyprland-icon" {
                 // Get Hyprland active window icon with efficient cache-based fallbacks
                 let icon_name = match hyprland::get_active_window_info() {
@@ -415,43 +680,68 @@ impl Button {
                         })
                 });
 
-                Button {
-                    action: cfg.action,
-                    image: final_icon,
-                    active: false,
-                    changed: false,
-                    show_outline: None,
-                    outline_color: None,
-                }
+                Button::with_image(action, final_icon)
             } else {
-                Button::new_icon(&icon, cfg.theme, cfg.action)
-            }
-        } else if let Some(time) = cfg.time {
-            Button::new_time(cfg.action, &time, cfg.locale.as_deref())
-        } else if let Some(battery_mode) = cfg.battery {
-            if let Some(battery) = find_battery_device() {
-                Button::new_battery(cfg.action, battery, battery_mode, cfg.theme)
-            } else {
-                Button::new_text("Battery N/A".to_string(), cfg.action)
+                Button::new_icon(icon, cfg.theme.as_ref(), action)
             }
         } else {
-            panic!("Invalid config, a button must have either Text, Icon or Time")
+            panic!("Invalid config, a button must have either Text or Icon")
+        }
+    }
+
+    // Fill color of a toggle in its current state, or of a MicMute button while muted
+    fn state_color(&self) -> Option<&ButtonColor> {
+        match &self.image {
+            ButtonImage::Toggle(name, images) if toggle::is_on(name) => images.on_color.as_ref(),
+            ButtonImage::Toggle(_, images) => images.off_color.as_ref(),
+            _ if self.action == ButtonAction::Key(Key::MicMute) && audio::current().mic_muted => Some(&MIC_MUTED_COLOR),
+            ButtonImage::Timer(name) if timer::highlighted(name) => Some(&TIMER_FLASH_COLOR),
+            ButtonImage::Indicator(indicator, _) if indicator_on(*indicator) => Some(&INDICATOR_ON_COLOR),
+            ButtonImage::Notifications(_) if notifications::current().dnd => Some(&DND_COLOR),
+            _ => None,
+        }
+    }
+
+    fn shows_seconds(&self) -> bool {
+        let ButtonImage::Time(items, _, _) = &self.image else {
+            return false;
         };
+        items.iter().any(|item| {
+            matches!(
+                item,
+                ChronoItem::Numeric(Numeric::Second | Numeric::Nanosecond | Numeric::Timestamp, _)
+                    | ChronoItem::Fixed(Fixed::Nanosecond | Fixed::Nanosecond3 | Fixed::Nanosecond6 | Fixed::Nanosecond9)
+            )
+        })
+    }
 
-        button.show_outline = cfg.show_button_outlines;
-        button.outline_color = cfg.button_outlines_color;
-        button
+    fn shows_audio(&self) -> bool {
+        matches!(self.image, ButtonImage::Volume(..)) || self.action == ButtonAction::Key(Key::MicMute)
     }
-    fn new_text(text: String, action: ButtonAction) -> Button {
+
+    fn shows_notifications(&self) -> bool {
+        matches!(self.image, ButtonImage::Notifications(_) | ButtonImage::Notification(_))
+    }
+
+    fn is_volume_slider(&self) -> bool {
+        matches!(self.image, ButtonImage::Volume(_, _, true))
+    }
+    fn with_image(action: ButtonAction, image: ButtonImage) -> Button {
         Button {
             action,
+            image,
             active: false,
             changed: false,
-            image: ButtonImage::Text(text),
             show_outline: None,
             outline_color: None,
+            repeat: None,
+            long_press_action: None,
+            double_tap_action: None,
         }
     }
+    fn new_text(text: String, action: ButtonAction) -> Button {
+        Button::with_image(action, ButtonImage::Text(text))
+    }
     fn new_text_with_icon(text: String, icon_name: String, theme: Option<impl AsRef<str>>, action: ButtonAction) -> Button {
         let icon = try_load_image(icon_name, theme).unwrap_or_else(|_| {
             // Fallback to a default icon if the specific app icon is not found
@@ -468,25 +758,10 @@ impl Button {
             }
         };
 
-        Button {
-            action,
-            active: false,
-            changed: false,
-            image: ButtonImage::TextWithIcon(format!(" {}", text), icon_handle), // Add space before text
-            show_outline: None,
-            outline_color: None,
-        }
+        Button::with_image(action, ButtonImage::TextWithIcon(format!(" {}", text), icon_handle)) // Add space before text
     }
     fn new_icon(path: impl AsRef<str>, theme: Option<impl AsRef<str>>, action: ButtonAction) -> Button {
-        let image = try_load_image(path, theme).expect("failed to load icon");
-        Button {
-            action,
-            image,
-            active: false,
-            changed: false,
-            show_outline: None,
-            outline_color: None,
-        }
+        Button::with_image(action, try_load_image(path, theme).expect("failed to load icon"))
     }
     fn load_battery_image(icon: &str, theme: Option<impl AsRef<str>>) -> Handle {
         if let ButtonImage::Svg(svg) = try_load_image(icon, theme).unwrap() {
@@ -494,7 +769,7 @@ impl Button {
         }
         panic!("failed to load icon");
     }
-    fn new_battery(action: ButtonAction, battery: String, battery_mode: String, theme: Option<impl AsRef<str>>) -> Button {
+    fn new_battery(action: ButtonAction, battery_mode: GaugeMode, show_time: bool, theme: Option<impl AsRef<str>>) -> Button {
         let bolt = Self::load_battery_image("bolt", theme.as_ref());
         let mut plain = Vec::new();
         let mut charging = Vec::new();
@@ -511,25 +786,12 @@ impl Button {
         ] {
             charging.push(Self::load_battery_image(icon, theme.as_ref()));
         }
-        let battery_mode = match battery_mode.as_str() {
-            "icon" => BatteryIconMode::Icon,
-            "percentage" => BatteryIconMode::Percentage,
-            "both" => BatteryIconMode::Both,
-            _ => panic!("invalid battery mode, accepted modes: icon, percentage, both"),
-        };
-        Button {
-            action,
-            active: false,
-            changed: false,
-            image: ButtonImage::Battery(battery, battery_mode, BatteryImages {
-                plain, bolt, charging
-            }),
-            show_outline: None,
-            outline_color: None,
-        }
+        Button::with_image(action, ButtonImage::Battery(battery_mode, BatteryImages {
+            plain, bolt, charging
+        }, show_time))
     }
 
-    fn new_time(action: ButtonAction, format: &str, locale_str: Option<&str>) -> Button {
+    fn new_time(action: ButtonAction, format: &str, locale_str: Option<&str>, zone_name: Option<&str>) -> Button {
         let format_str = if format == "24hr" {
             "%H:%M    %a %-e %b"
         } else if format == "12hr" {
@@ -544,14 +806,18 @@ impl Button {
         };
 
         let locale = locale_str.and_then(|l| Locale::try_from(l).ok()).unwrap_or(Locale::POSIX);
-        Button {
-            action,
-            active: false,
-            changed: false,
-            image: ButtonImage::Time(format_items, locale),
-            show_outline: None,
-            outline_color: None,
-        }
+        let zone = zone_name.and_then(|name| match name.parse::<Tz>() {
+            Ok(zone) => Some(zone),
+            Err(e) => {
+                eprintln!("Failed to load time zone '{}', using local time: {}", name, e);
+                None
+            }
+        });
+        Button::with_image(action, ButtonImage::Time(format_items, locale, zone))
+    }
+    fn new_volume(action: ButtonAction, mode: GaugeMode, slider: bool, theme: Option<impl AsRef<str>>) -> Button {
+        let icons = ["volume_off", "volume_down", "volume_up"].map(|icon| Self::load_battery_image(icon, theme.as_ref()));
+        Button::with_image(action, ButtonImage::Volume(mode, Box::new(icons), slider))
     }
     fn render(
         &self,
@@ -561,7 +827,7 @@ impl Button {
         button_width: u64,
         y_shift: f64,
     ) {
-        match &self.image {
+        match self.image.resolved() {
             ButtonImage::Text(text) => {
                 let extents = c.text_extents(text).unwrap();
                 c.move_to(
@@ -577,36 +843,7 @@ impl Button {
                 let available_text_width = button_width as f64 - icon_size - 8.0; // Reserve some padding
 
                 // Trim text if it's too wide for the button
-                let (display_text, text_extents) = {
-                    let original_extents = c.text_extents(text).unwrap();
-                    if original_extents.width() <= available_text_width {
-                        (text.clone(), original_extents)
-                    } else {
-                        // Trim text to fit with ellipsis
-                        let trimmed = text.clone();
-                        let ellipsis = "...";
-                        let ellipsis_width = c.text_extents(ellipsis).unwrap().width();
-                        let target_width = available_text_width - ellipsis_width;
-
-                        // Binary search to find optimal text length
-                        let mut left = 0;
-                        let mut right = trimmed.chars().count();
-                        while left < right {
-                            let mid = (left + right + 1) / 2;
-                            let test_text: String = trimmed.chars().take(mid).collect();
-                            let test_width = c.text_extents(&test_text).unwrap().width();
-                            if test_width <= target_width {
-                                left = mid;
-                            } else {
-                                right = mid - 1;
-                            }
-                        }
-
-                        let final_text: String = trimmed.chars().take(left).collect::<String>() + ellipsis;
-                        let final_extents = c.text_extents(&final_text).unwrap();
-                        (final_text, final_extents)
-                    }
-                };
+                let (display_text, text_extents) = fit_text(c, text, available_text_width);
 
                 let total_width = icon_size + text_extents.width();
 
@@ -641,9 +878,8 @@ impl Button {
                 c.rectangle(x, y, ICON_SIZE as f64, ICON_SIZE as f64);
                 c.fill().unwrap();
             }
-            ButtonImage::Time(format, locale) => {
-                let current_time = Local::now();
-                let formatted_time = current_time.format_localized_with_items(format.iter(), *locale).to_string();
+            ButtonImage::Time(format, locale, zone) => {
+                let formatted_time = format_time(format, *locale, zone.as_ref(), Utc::now());
                 let time_extents = c.text_extents(&formatted_time).unwrap();
                 c.move_to(
                     button_left_edge + (button_width as f64 / 2.0 - time_extents.width() / 2.0).round(),
@@ -651,8 +887,11 @@ impl Button {
                 );
                 c.show_text(&formatted_time).unwrap();
             }
-            ButtonImage::Battery(battery, battery_mode, icons) => {
-                let (capacity, state) = get_battery_state(battery);
+            ButtonImage::Battery(battery_mode, icons, show_time) => {
+                let Some(info) = battery_monitor::get_cached_battery_state() else {
+                    return;
+                };
+                let (capacity, state) = (info.capacity, info.state);
                 let icon = if battery_mode.should_draw_icon() {
                     Some(match state {
                         BatteryState::Charging => match capacity {
@@ -680,7 +919,10 @@ impl Button {
                 } else {
                     None
                 };
-                let percent_str = format!("{:.0}%", capacity);
+                let percent_str = match info.time_remaining {
+                    Some(time) if *show_time => format!("{} {}", info.charge_text(), format_time_remaining(time)),
+                    _ => info.charge_text(),
+                };
                 let extents = c.text_extents(&percent_str).unwrap();
                 let mut width = extents.width();
                 let mut text_offset = 0;
@@ -706,16 +948,183 @@ impl Button {
                     c.show_text(&percent_str).unwrap();
                 }
             }
+            ButtonImage::Resource(source, style, label) => {
+                let history = resource_monitor::history(source);
+                let text = match history.last() {
+                    Some(value) => format!("{} {}", label, source.format(*value)),
+                    None => label.clone(),
+                };
+                if let (Some(&last), false) = (history.last(), *style == ResourceStyle::Text) {
+                    // Meter inside the rounded box, under the text
+                    let (x, y) = (button_left_edge + 8.0, y_shift + height as f64 * 0.15);
+                    let (w, h) = (button_width as f64 - 16.0, height as f64 * 0.7);
+                    c.set_source_rgb(BUTTON_COLOR_ACTIVE, BUTTON_COLOR_ACTIVE, BUTTON_COLOR_ACTIVE);
+                    if *style == ResourceStyle::Bar {
+                        c.rectangle(x, y, w * source.fraction(last), h);
+                    } else {
+                        // Newest sample at the right edge
+                        let step = w / (resource_monitor::HISTORY_LEN - 1) as f64;
+                        let start = x + w - step * (history.len() - 1) as f64;
+                        c.move_to(start, y + h);
+                        for (i, value) in history.iter().enumerate() {
+                            c.line_to(start + step * i as f64, y + h * (1.0 - source.fraction(*value)));
+                        }
+                        c.line_to(x + w, y + h);
+                        c.close_path();
+                    }
+                    c.fill().unwrap();
+                    c.set_source_rgb(1.0, 1.0, 1.0);
+                }
+                let extents = c.text_extents(&text).unwrap();
+                c.move_to(
+                    button_left_edge + (button_width as f64 / 2.0 - extents.width() / 2.0).round(),
+                    y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+                );
+                c.show_text(&text).unwrap();
+            }
+            ButtonImage::Network(mode) => {
+                let state = network::current();
+                let text = state.describe();
+                let extents = c.text_extents(&text).unwrap();
+                let bars_width = NETWORK_BARS * 8.0;
+                let width = match mode {
+                    NetworkMode::Icon => bars_width,
+                    NetworkMode::Text => extents.width(),
+                    NetworkMode::Both => bars_width + 12.0 + extents.width(),
+                };
+                let x = button_left_edge + (button_width as f64 / 2.0 - width / 2.0).round();
+                if *mode != NetworkMode::Text {
+                    // Signal bars growing to the right, unlit ones dimmed
+                    let base = y_shift + (height as f64 + ICON_SIZE as f64) / 2.0 - 4.0;
+                    for bar in 0..NETWORK_BARS as usize {
+                        let lit = bar < state.bars();
+                        let shade = if lit { 1.0 } else { BUTTON_COLOR_ACTIVE };
+                        let bar_height = (ICON_SIZE as f64 - 8.0) * (bar + 1) as f64 / NETWORK_BARS;
+                        c.set_source_rgb(shade, shade, shade);
+                        c.rectangle(x + bar as f64 * 8.0, base - bar_height, 5.0, bar_height);
+                        c.fill().unwrap();
+                    }
+                    c.set_source_rgb(1.0, 1.0, 1.0);
+                }
+                if *mode != NetworkMode::Icon {
+                    c.move_to(
+                        x + width - extents.width(),
+                        y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+                    );
+                    c.show_text(&text).unwrap();
+                }
+            }
+            ButtonImage::Volume(mode, icons, slider) => {
+                let state = audio::current();
+                let text = format!("{}%", state.volume);
+                let extents = c.text_extents(&text).unwrap();
+                if self.active && *slider {
+                    // Slider: the button fills up to the volume
+                    let (x, y) = (button_left_edge + 8.0, y_shift + height as f64 * 0.15);
+                    let (w, h) = (button_width as f64 - 16.0, height as f64 * 0.7);
+                    c.set_source_rgb(BUTTON_COLOR_INACTIVE, BUTTON_COLOR_INACTIVE, BUTTON_COLOR_INACTIVE);
+                    c.rectangle(x, y, w * (state.volume.min(100) as f64 / 100.0), h);
+                    c.fill().unwrap();
+                    c.set_source_rgb(1.0, 1.0, 1.0);
+                    c.move_to(
+                        button_left_edge + (button_width as f64 / 2.0 - extents.width() / 2.0).round(),
+                        y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+                    );
+                    c.show_text(&text).unwrap();
+                    return;
+                }
+                let icon = match state.volume {
+                    _ if state.muted || !state.connected => &icons[0],
+                    0 => &icons[0],
+                    1..=49 => &icons[1],
+                    _ => &icons[2],
+                };
+                let draws_text = mode.should_draw_text() && state.connected;
+                let width = match (mode.should_draw_icon(), draws_text) {
+                    (true, true) => ICON_SIZE as f64 + extents.width(),
+                    (true, false) => ICON_SIZE as f64,
+                    _ => extents.width(),
+                };
+                let x = button_left_edge + (button_width as f64 / 2.0 - width / 2.0).round();
+                if mode.should_draw_icon() {
+                    let y = y_shift + ((height as f64 - ICON_SIZE as f64) / 2.0).round();
+                    icon.render_document(c, &Rectangle::new(x, y, ICON_SIZE as f64, ICON_SIZE as f64)).unwrap();
+                }
+                if draws_text {
+                    c.move_to(
+                        x + width - extents.width(),
+                        y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+                    );
+                    c.show_text(&text).unwrap();
+                }
+            }
+            ButtonImage::Timer(name) => {
+                let text = timer::text(name);
+                let extents = c.text_extents(&text).unwrap();
+                c.move_to(
+                    button_left_edge + (button_width as f64 / 2.0 - extents.width() / 2.0).round(),
+                    y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+                );
+                c.show_text(&text).unwrap();
+            }
+            ButtonImage::Indicator(indicator, label) => {
+                let text = match indicator {
+                    Indicator::Layout => keyboard_state::layout().unwrap_or_else(|| label.clone()),
+                    Indicator::Modifiers => {
+                        let held: Vec<_> = keyboard_state::modifiers().into_iter().map(|m| m.symbol()).collect();
+                        if held.is_empty() {
+                            label.clone()
+                        } else {
+                            held.join(" ")
+                        }
+                    }
+                    Indicator::CapsLock | Indicator::NumLock => label.clone(),
+                };
+                let extents = c.text_extents(&text).unwrap();
+                c.move_to(
+                    button_left_edge + (button_width as f64 / 2.0 - extents.width() / 2.0).round(),
+                    y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+                );
+                c.show_text(&text).unwrap();
+            }
+            ButtonImage::Notifications(label) => {
+                let text = match notifications::current().unread {
+                    0 => label.clone(),
+                    unread => format!("{} {}", label, unread),
+                };
+                let extents = c.text_extents(&text).unwrap();
+                c.move_to(
+                    button_left_edge + (button_width as f64 / 2.0 - extents.width() / 2.0).round(),
+                    y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+                );
+                c.show_text(&text).unwrap();
+            }
+            ButtonImage::Notification(slot) => {
+                let Some(notification) = slot.checked_sub(1).and_then(|i| notifications::current().recent.get(i).cloned()) else {
+                    return;
+                };
+                let text = match notification.app.as_str() {
+                    "" => notification.summary,
+                    app => format!("{}: {}", app, notification.summary),
+                };
+                let (text, extents) = fit_text(c, &text, button_width as f64 - 16.0);
+                c.move_to(
+                    button_left_edge + (button_width as f64 / 2.0 - extents.width() / 2.0).round(),
+                    y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+                );
+                c.show_text(&text).unwrap();
+            }
+            ButtonImage::Toggle(..) => {}
         }
     }
     fn set_backround_color(&self, c: &Context, color: f64) {
         match &self.image {
-            ButtonImage::Battery(battery, _, _) => {
-                let (_, state) = get_battery_state(battery);
+            ButtonImage::Battery(..) => {
+                let state = battery_monitor::get_cached_battery_state().map(|info| info.state);
                 match state {
-                    BatteryState::NotCharging => c.set_source_rgb(color, color, color),
-                    BatteryState::Charging => c.set_source_rgb(0.0, color, 0.0),
-                    BatteryState::Low => c.set_source_rgb(color, 0.0, 0.0),
+                    Some(BatteryState::Charging) => c.set_source_rgb(0.0, color, 0.0),
+                    Some(BatteryState::Low | BatteryState::Critical) => c.set_source_rgb(color, 0.0, 0.0),
+                    _ => c.set_source_rgb(color, color, color),
                 }
             }
             _ => c.set_source_rgb(color, color, color),
@@ -723,38 +1132,393 @@ impl Button {
     }
 }
 
+/// The strip a layer is laid out on: the full bar width, the margin kept clear for
+/// pixel shift, and how narrow a flexible button may get before the layer pages.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Bar {
+    width: f64,
+    margin: f64,
+    min_button_width: f64,
+}
+
+impl Bar {
+    pub fn new(width: u16, pixel_shift: bool, min_button_width: u32) -> Bar {
+        let margin = if pixel_shift { PIXEL_SHIFT_WIDTH_PX as f64 } else { 0.0 };
+        Bar { width: width as f64, margin, min_button_width: min_button_width as f64 }
+    }
+
+    fn usable_width(&self) -> f64 {
+        self.width - self.margin
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct FunctionLayer {
     displays_time: bool,
     displays_battery: bool,
+    // Buttons with the index of their layout entry
     buttons: Vec<(usize, Button)>,
-    virtual_button_count: usize,
+    entries: Vec<LayoutEntry>,
+    bar: Bar,
+    pages: PageLayout,
+    page: usize,
+    // Entries on the current page with their left edges and widths inside the margin,
+    // worked out by `relayout` whenever they can change and shared by draw and hit
+    rects: Vec<(usize, f64, f64)>,
+}
+
+// A button or spacer in the layer's layout
+#[derive(Clone, Debug)]
+struct LayoutEntry {
+    button: Option<usize>,
+    // Share of the bar in virtual button slots, unless the width is fixed
+    stretch: usize,
+    width: Option<f64>,
+    align: Align,
+    visible_when: Option<Condition>,
+    shown: bool,
+}
+
+impl LayoutEntry {
+    // Slots the entry takes when filling pages; fixed widths round up
+    fn slots(&self, slot_width: f64) -> usize {
+        let spacing = BUTTON_SPACING_PX as f64;
+        match self.width {
+            Some(width) => ((width + spacing) / (slot_width + spacing)).ceil().max(1.0) as usize,
+            None => self.stretch,
+        }
+    }
+}
+
+// How a layer too wide for MinButtonWidth is split into pages
+#[derive(Clone, Debug, PartialEq)]
+struct PageLayout {
+    // First entry of each page
+    starts: Vec<usize>,
+    // Entries before this one (the Back button of an expandable) show on every page
+    pinned: usize,
+    // Virtual button slots on a page, pinned ones included
+    slots: usize,
+}
+
+impl Default for PageLayout {
+    fn default() -> PageLayout {
+        PageLayout {
+            starts: vec![0],
+            pinned: 0,
+            slots: 0,
+        }
+    }
 }
 
 impl FunctionLayer {
-    fn with_config(cfg: Vec<ButtonConfig>) -> FunctionLayer {
+    fn with_config(cfg: Vec<ButtonConfig>, bar: Bar) -> FunctionLayer {
         if cfg.is_empty() {
             panic!("Invalid configuration, layer has 0 buttons");
         }
 
-        let mut virtual_button_count = 0;
-        FunctionLayer {
-            displays_time: cfg.iter().any(|cfg| cfg.time.is_some()),
-            displays_battery: cfg.iter().any(|cfg| cfg.battery.is_some()),
-            buttons: cfg
-                .into_iter()
-                .scan(&mut virtual_button_count, |state, cfg| {
-                    let i = **state;
-                    let mut stretch = cfg.stretch.unwrap_or(1);
-                    if stretch < 1 {
-                        println!("Stretch value must be at least 1, setting to 1.");
-                        stretch = 1;
-                    }
-                    **state += stretch;
-                    Some((i, Button::with_config(cfg)))
-                })
-                .collect(),
-            virtual_button_count,
+        let mut layer = FunctionLayer {
+            displays_time: cfg.iter().any(|cfg| matches!(cfg.widget, Widget::Time { .. })),
+            displays_battery: cfg.iter().any(|cfg| matches!(cfg.widget, Widget::Battery { .. })),
+            bar,
+            ..Default::default()
+        };
+        for (i, mut cfg) in cfg.into_iter().enumerate() {
+            let mut stretch = cfg.stretch.unwrap_or(1);
+            if stretch < 1 {
+                println!("Stretch value must be at least 1, setting to 1.");
+                stretch = 1;
+            }
+            let width = cfg.width.map(|width| width as f64);
+            let align = cfg.align.unwrap_or_default();
+            let visible_when = cfg.visible_when.as_deref().and_then(|expr| match Condition::parse(expr) {
+                Ok(cond) => {
+                    visibility::watch(&cond);
+                    Some(cond)
+                }
+                Err(e) => {
+                    eprintln!("Button #{}: ignoring VisibleWhen \"{}\": {}", i + 1, expr, e);
+                    None
+                }
+            });
+            let entry = layer.entries.len();
+            // Widgets act on their own state unless given other actions
+            let (default_action, default_long_press) = match &cfg.widget {
+                Widget::Timer(timer) => (Some(ButtonAction::Timer(timer.name())), Some(ButtonAction::TimerReset(timer.name()))),
+                Widget::Notifications => (Some(ButtonAction::Expand("Expand_Notifications".into())), Some(ButtonAction::NotificationsDnd)),
+                Widget::Notification(slot) => (Some(ButtonAction::NotificationInvoke(*slot)), Some(ButtonAction::NotificationDismiss(*slot))),
+                Widget::Network(_) => (Some(ButtonAction::Expand("Expand_Network".into())), None),
+                Widget::Volume { .. } => (Some(ButtonAction::Key(Key::Mute)), None),
+                Widget::Indicator(Indicator::CapsLock) => (Some(ButtonAction::Key(Key::CapsLock)), None),
+                Widget::Indicator(Indicator::NumLock) => (Some(ButtonAction::Key(Key::NumLock)), None),
+                Widget::Indicator(Indicator::Layout) => (Some(ButtonAction::Run(LAYOUT_SWITCH_COMMAND.into())), None),
+                _ => (None, None),
+            };
+            let action = match &cfg.widget {
+                Widget::Toggle(toggle) => Some(ButtonAction::Toggle(toggle.name().to_string())),
+                _ => cfg.action.take().or(default_action),
+            };
+            if cfg.long_press_action.is_none() {
+                cfg.long_press_action = default_long_press;
+            }
+            let button = match (matches!(cfg.widget, Widget::Spacer), action) {
+                (false, Some(action)) => {
+                    layer.buttons.push((entry, Button::with_config(cfg, action)));
+                    Some(layer.buttons.len() - 1)
+                }
+                (false, None) => {
+                    eprintln!("Button #{} has no Action, leaving a gap in its place", i + 1);
+                    None
+                }
+                (true, _) => None,
+            };
+            layer.entries.push(LayoutEntry { button, stretch, width, align, visible_when, shown: true });
+        }
+        layer.update_visibility(&visibility::current_facts());
+        layer.relayout();
+        layer
+    }
+
+    /// Re-evaluates `VisibleWhen` conditions; true if an entry appeared or vanished.
+    fn update_visibility(&mut self, facts: &visibility::Facts) -> bool {
+        let mut changed = false;
+        for entry in &mut self.entries {
+            let shown = entry.visible_when.as_ref().is_none_or(|cond| cond.eval(facts));
+            changed |= shown != entry.shown;
+            entry.shown = shown;
+        }
+        if changed {
+            self.relayout();
+        }
+        changed
+    }
+
+    // Splits the layer into pages and places the current one on the bar
+    fn relayout(&mut self) {
+        self.paginate(self.bar.usable_width(), self.bar.min_button_width);
+        self.place_page();
+    }
+
+    fn place_page(&mut self) {
+        let visible = self.visible_entries();
+        let placed = self.place(&visible, self.bar.usable_width());
+        self.rects = visible.into_iter().zip(placed).map(|(entry, (left_edge, width))| (entry, left_edge, width)).collect();
+    }
+
+    /// Marks the buttons on the current page that `pred` picks for redrawing.
+    fn mark_changed(&mut self, pred: impl Fn(&Button) -> bool) {
+        for i in self.visible_buttons() {
+            let button = &mut self.buttons[i].1;
+            if pred(button) {
+                button.changed = true;
+            }
+        }
+    }
+
+    fn shown_entries(&self) -> Vec<usize> {
+        (0..self.entries.len()).filter(|&i| self.entries[i].shown).collect()
+    }
+
+    fn turn_page(&mut self, delta: isize) -> bool {
+        let page = self.page.saturating_add_signed(delta).min(self.pages.starts.len() - 1);
+        let turned = page != self.page;
+        self.page = page;
+        if turned {
+            self.place_page();
+        }
+        turned
+    }
+
+    fn is_paged(&self) -> bool {
+        self.pages.starts.len() > 1
+    }
+
+    // Splits the entries into pages when a flexible button would get thinner than `min_width`
+    fn paginate(&mut self, width: f64, min_width: f64) {
+        self.pages = PageLayout::default();
+        let shown = self.shown_entries();
+        let narrowest = self
+            .place(&shown, width)
+            .into_iter()
+            .zip(shown.iter().map(|&i| &self.entries[i]))
+            .filter(|(_, entry)| entry.button.is_some() && entry.width.is_none())
+            .map(|((_, button_width), _)| button_width)
+            .fold(f64::INFINITY, f64::min);
+        if narrowest >= min_width {
+            self.page = 0;
+            return;
+        }
+
+        let spacing = BUTTON_SPACING_PX as f64;
+        let is_back = |button: &Button| matches!(&button.action, ButtonAction::Command(id) if id == "Back");
+        let pinned = match self.entries[0].button {
+            Some(button) if shown.len() > 1 && shown[0] == 0 && is_back(&self.buttons[button].1) => 1,
+            _ => 0,
+        };
+        let slots_of = |&i: &usize| self.entries[i].slots(min_width);
+        let pinned_slots: usize = shown[..pinned].iter().map(slots_of).sum();
+        let widest = shown[pinned..].iter().map(slots_of).max().unwrap_or(1);
+        let content = width - 2.0 * (PAGE_ARROW_WIDTH_PX + spacing);
+        let fitting = ((content + spacing) / (min_width + spacing)).floor().max(0.0) as usize;
+        let slots = fitting.max(pinned_slots + widest);
+
+        let mut starts = Vec::new();
+        let mut used = slots;
+        for &i in &shown[pinned..] {
+            let entry_slots = slots_of(&i);
+            if used + entry_slots > slots - pinned_slots {
+                starts.push(i);
+                used = 0;
+            }
+            used += entry_slots;
+        }
+        self.page = self.page.min(starts.len() - 1);
+        self.pages = PageLayout { starts, pinned, slots };
+    }
+
+    // Entries on the current page, in order
+    fn visible_entries(&self) -> Vec<usize> {
+        let starts = &self.pages.starts;
+        let end = starts.get(self.page + 1).copied().unwrap_or(self.entries.len());
+        (0..self.pages.pinned).chain(starts[self.page]..end).filter(|&i| self.entries[i].shown).collect()
+    }
+
+    /// Whether a visible clock shows seconds and so needs redrawing every second.
+    fn shows_seconds(&self) -> bool {
+        self.visible_buttons().into_iter().any(|i| self.buttons[i].1.shows_seconds())
+    }
+
+    fn visible_buttons(&self) -> Vec<usize> {
+        self.visible_entries().into_iter().filter_map(|entry| self.entries[entry].button).collect()
+    }
+
+    // Left edges and widths of `entries` on a bar `width` wide. Centered and right-aligned
+    // flexible entries get an even share of the bar and left ones fill what the rest
+    // leave, so the groups can separate; they are then packed left, centered and right.
+    fn place(&self, entries: &[usize], width: f64) -> Vec<(f64, f64)> {
+        let spacing = BUTTON_SPACING_PX as f64;
+        let (origin, content) = if self.is_paged() {
+            let arrow = PAGE_ARROW_WIDTH_PX + spacing;
+            (arrow, width - 2.0 * arrow)
+        } else {
+            (0.0, width)
+        };
+        let entries: Vec<&LayoutEntry> = entries.iter().map(|&i| &self.entries[i]).collect();
+        let flex_slots: usize = entries.iter().filter(|e| e.width.is_none()).map(|e| e.stretch).sum();
+        let fixed: Vec<f64> = entries.iter().filter_map(|e| e.width).collect();
+        let virtual_button_width = if self.is_paged() {
+            // Every page uses the same slot width, however full it is
+            let slots = self.pages.slots;
+            (content - spacing * (slots - 1) as f64) / slots as f64
+        } else {
+            let slots = flex_slots + fixed.len();
+            let gaps = slots.saturating_sub(1) as f64;
+            let fill_width = (content - fixed.iter().sum::<f64>() - spacing * gaps) / flex_slots.max(1) as f64;
+            if entries.iter().all(|e| e.align == Align::Left) {
+                fill_width
+            } else {
+                // With groups to pin, every flexible button gets an even share, as if every
+                // entry were flexible, and the gaps between the groups take what is left
+                let even_width = (content - spacing * gaps) / slots.max(1) as f64;
+                even_width.min(fill_width)
+            }
+        };
+
+        // Exact extent for packing, and the drawn width rounded like the slots
+        let sizes: Vec<(f64, f64)> = entries
+            .iter()
+            .map(|e| match e.width {
+                Some(width) => (width, width),
+                None => {
+                    let extra = (e.stretch - 1) as f64 * (virtual_button_width + spacing);
+                    (virtual_button_width + extra, virtual_button_width + extra.floor())
+                }
+            })
+            .collect();
+        let extent = |align: Align| {
+            let group: Vec<f64> = entries.iter().zip(&sizes).filter(|(e, _)| e.align == align).map(|(_, s)| s.0).collect();
+            group.iter().sum::<f64>() + spacing * group.len().saturating_sub(1) as f64
+        };
+        let with_gap = |extent: f64| if extent > 0.0 { extent + spacing } else { 0.0 };
+        let (left, center, right) = (extent(Align::Left), extent(Align::Center), extent(Align::Right));
+
+        let mut left_x = origin;
+        let mut right_x = origin + content - right;
+        let mut center_x = (origin + (content - center) / 2.0)
+            .min(origin + content - with_gap(right) - center)
+            .max(origin + with_gap(left));
+        entries
+            .iter()
+            .zip(sizes)
+            .map(|(e, (extent, drawn))| {
+                let cursor = match e.align {
+                    Align::Left => &mut left_x,
+                    Align::Center => &mut center_x,
+                    Align::Right => &mut right_x,
+                };
+                let left_edge = cursor.floor();
+                *cursor += extent + spacing;
+                (left_edge, drawn)
+            })
+            .collect()
+    }
+
+    // Left edge and width of button `i` inside the margin, if it is on the current page
+    fn button_rect(&self, i: usize) -> Option<(f64, f64)> {
+        let entry = self.buttons[i].0;
+        self.rects.iter().find(|rect| rect.0 == entry).map(|&(_, left_edge, width)| (left_edge, width))
+    }
+
+    /// Which way a touch at `x` turns the page, if it landed on a page arrow.
+    fn page_arrow(&self, x: f64) -> Option<isize> {
+        if !self.is_paged() {
+            return None;
+        }
+        let x = x - self.bar.margin / 2.0;
+        let arrow = PAGE_ARROW_WIDTH_PX + BUTTON_SPACING_PX as f64 / 2.0;
+        if x < arrow {
+            Some(-1)
+        } else if x > self.bar.usable_width() - arrow {
+            Some(1)
+        } else {
+            None
+        }
+    }
+
+    fn draw_page_arrows(&self, c: &Context, left: f64, width: f64, height: f64) {
+        let last = self.pages.starts.len() - 1;
+        let mid = height * 0.4;
+        c.set_line_width(4.0);
+        for (x, step, enabled) in [
+            (left + PAGE_ARROW_WIDTH_PX / 2.0 + 6.0, -12.0, self.page > 0),
+            (left + width - PAGE_ARROW_WIDTH_PX / 2.0 - 6.0, 12.0, self.page < last),
+        ] {
+            let shade = if enabled { 1.0 } else { BUTTON_COLOR_INACTIVE };
+            c.set_source_rgb(shade, shade, shade);
+            c.move_to(x - step, mid - 12.0);
+            c.line_to(x, mid);
+            c.line_to(x - step, mid + 12.0);
+            c.stroke().unwrap();
+        }
+
+        // Page indicator dots under the next arrow, or "2/7" when they don't fit
+        let center = left + width - PAGE_ARROW_WIDTH_PX / 2.0;
+        let y = height * 0.8;
+        if last < 6 {
+            let first = center - last as f64 * 5.0;
+            for page in 0..=last {
+                let shade = if page == self.page { 1.0 } else { BUTTON_COLOR_ACTIVE };
+                c.set_source_rgb(shade, shade, shade);
+                c.arc(first + page as f64 * 10.0, y, 3.0, 0.0, std::f64::consts::TAU);
+                c.fill().unwrap();
+            }
+        } else {
+            let label = format!("{}/{}", self.page + 1, last + 1);
+            c.set_source_rgb(1.0, 1.0, 1.0);
+            c.set_font_size(16.0);
+            let extents = c.text_extents(&label).unwrap();
+            c.move_to(center - extents.width() / 2.0, y + extents.height() / 2.0);
+            c.show_text(&label).unwrap();
         }
     }
     fn draw(
@@ -774,15 +1538,7 @@ impl FunctionLayer {
         };
         c.translate(height as f64, 0.0);
         c.rotate((90.0f64).to_radians());
-        let pixel_shift_width = if config.enable_pixel_shift {
-            PIXEL_SHIFT_WIDTH_PX
-        } else {
-            0
-        };
-        let virtual_button_width = ((width - pixel_shift_width as i32)
-            - (BUTTON_SPACING_PX * (self.virtual_button_count - 1) as i32))
-            as f64
-            / self.virtual_button_count as f64;
+        let margin = self.bar.margin;
         let radius = 8.0f64;
         let bot = (height as f64) * 0.15;
         let top = (height as f64) * 0.85;
@@ -793,29 +1549,22 @@ impl FunctionLayer {
             c.paint().unwrap();
         }
         c.set_font_face(&config.font_face);
+        if complete_redraw && self.is_paged() {
+            self.draw_page_arrows(&c, pixel_shift_x + margin / 2.0, self.bar.usable_width(), height as f64);
+        }
         c.set_font_size(32.0);
 
-        for i in 0..self.buttons.len() {
-            let end = if i + 1 < self.buttons.len() {
-                self.buttons[i + 1].0
-            } else {
-                self.virtual_button_count
+        for &(entry, left_edge, button_width) in &self.rects {
+            let Some(i) = self.entries[entry].button else {
+                continue;
             };
-            let (start, button) = &mut self.buttons[i];
-            let start = *start;
+            let button = &mut self.buttons[i].1;
 
             if !button.changed && !complete_redraw {
                 continue;
             };
 
-            let left_edge = (start as f64 * (virtual_button_width + BUTTON_SPACING_PX as f64))
-                .floor()
-                + pixel_shift_x
-                + (pixel_shift_width / 2) as f64;
-
-            let button_width = virtual_button_width
-                + ((end - start - 1) as f64 * (virtual_button_width + BUTTON_SPACING_PX as f64))
-                    .floor();
+            let left_edge = left_edge + pixel_shift_x + margin / 2.0;
 
             let show_outline = button.show_outline.unwrap_or(config.show_button_outlines);
             if !complete_redraw {
@@ -831,6 +1580,8 @@ impl FunctionLayer {
 
             if button.active {
                 button.set_backround_color(&c, BUTTON_COLOR_ACTIVE);
+            } else if let Some(color) = button.state_color() {
+                color.set_cairo_source(&c);
             } else if show_outline {
                 if let Some(custom_color) = &button.outline_color {
                     custom_color.set_cairo_source(&c);
@@ -899,35 +1650,17 @@ impl FunctionLayer {
         modified_regions
     }
 
-    fn hit(&self, width: u16, height: u16, x: f64, y: f64, i: Option<usize>) -> Option<usize> {
-        let virtual_button_width =
-            (width as i32 - (BUTTON_SPACING_PX * (self.virtual_button_count - 1) as i32)) as f64
-                / self.virtual_button_count as f64;
-
-        let i = i.unwrap_or_else(|| {
-            let virtual_i = (x / (width as f64 / self.virtual_button_count as f64)) as usize;
-            self.buttons
-                .iter()
-                .position(|(start, _)| *start > virtual_i)
-                .unwrap_or(self.buttons.len())
-                - 1
-        });
-        if i >= self.buttons.len() {
-            return None;
-        }
-
-        let start = self.buttons[i].0;
-        let end = if i + 1 < self.buttons.len() {
-            self.buttons[i + 1].0
-        } else {
-            self.virtual_button_count
+    // Touches arrive in bar coordinates; buttons sit inside the pixel-shift margin
+    fn hit(&self, height: u16, x: f64, y: f64, i: Option<usize>) -> Option<usize> {
+        let x = x - self.bar.margin / 2.0;
+        let i = match i {
+            Some(i) => i,
+            None => self.visible_buttons().into_iter().find(|&i| {
+                self.button_rect(i)
+                    .is_some_and(|(left_edge, button_width)| x >= left_edge && x <= left_edge + button_width)
+            })?,
         };
-
-        let left_edge = (start as f64 * (virtual_button_width + BUTTON_SPACING_PX as f64)).floor();
-
-        let button_width = virtual_button_width
-            + ((end - start - 1) as f64 * (virtual_button_width + BUTTON_SPACING_PX as f64))
-                .floor();
+        let (left_edge, button_width) = self.button_rect(i)?;
 
         if x < left_edge
             || x > (left_edge + button_width)
@@ -997,16 +1730,10 @@ fn update_layer_for_navigation(navigation_state: &NavigationState, config: &Conf
             let back_button = ButtonConfig {
                 icon: Some("back".to_string()),
                 text: Some("Back".to_string()),
-                theme: None,
-                time: None,
-                battery: None,
-                locale: None,
-                action: ButtonAction::Command("Back".to_string()),
-                stretch: None,
+                action: Some(ButtonAction::Command("Back".to_string())),
                 show_button_outlines: Some(config.back_button_show_outlines),
                 button_outlines_color: config.back_button_outline_color.clone(),
-                show_app_icon_alongside_text: None,
-                app_icon: None,
+                ..Default::default()
             };
 
             // Combine back button with expandable buttons
@@ -1014,7 +1741,7 @@ fn update_layer_for_navigation(navigation_state: &NavigationState, config: &Conf
             combined_buttons.extend_from_slice(expandable_buttons);
 
             // Replace the current layer with the expandable
-            layers[*active_layer] = FunctionLayer::with_config(combined_buttons);
+            layers[*active_layer] = FunctionLayer::with_config(combined_buttons, config.bar);
             *needs_complete_redraw = true;
 
             // Clear all active touches to prevent accidental triggering in new layout
@@ -1087,16 +1814,12 @@ fn handle_hyprland_expand(hyprland_expand_name: &str, config: &Config, navigatio
             let window_button_config = ButtonConfig {
                 icon: Some("back".to_string()), // Show back arrow icon
                 text: Some("Back".to_string()), // Fixed: Show "Back" text instead of window title
-                theme: None,
-                time: None,
-                battery: None,
-                locale: None,
-                action: ButtonAction::Command("Back".to_string()),
-                stretch: None,
+                action: Some(ButtonAction::Command("Back".to_string())),
                 show_button_outlines: Some(config.back_button_show_outlines),
                 button_outlines_color: config.back_button_outline_color.clone(),
                 show_app_icon_alongside_text: Some(true), // Show icon alongside text
                 app_icon: Some("back".to_string()), // Use back icon
+                ..Default::default()
             };
 
             // Combine window button with expandable layer keys
@@ -1104,7 +1827,7 @@ fn handle_hyprland_expand(hyprland_expand_name: &str, config: &Config, navigatio
             combined_buttons.extend_from_slice(&matched_config.layer_keys);
 
             // Replace the current layer with the expandable
-            layers[*active_layer] = FunctionLayer::with_config(combined_buttons);
+            layers[*active_layer] = FunctionLayer::with_config(combined_buttons, config.bar);
             *needs_complete_redraw = true;
 
             // Push to navigation state to track this expansion
@@ -1118,7 +1841,7 @@ fn handle_hyprland_expand(hyprland_expand_name: &str, config: &Config, navigatio
     // If no Hyprland expandable configuration found, ignore the button press
 }
 
-fn handle_button_action<F>(uinput: &mut UInputHandle<F>, action: &ButtonAction, config: &Config, active: bool, navigation_state: &mut NavigationState, layers: &mut [FunctionLayer; 2], active_layer: &mut usize, needs_complete_redraw: &mut bool, original_layers: &[FunctionLayer; 2], touches: &mut HashMap<u32, (usize, usize)>, pending_actions: &mut Vec<PendingAction>, button_index: Option<usize>)
+fn hold_keys<F>(uinput: &mut UInputHandle<F>, action: &ButtonAction, active: bool)
 where
     F: AsRawFd,
 {
@@ -1139,66 +1862,80 @@ where
                 }
             }
         }
-        ButtonAction::Command(command_id) => {
-            if active {
-                // Add visual feedback delay for Command actions
-                if let Some(btn_idx) = button_index {
-                    pending_actions.push(PendingAction {
-                        action: action.clone(),
-                        execution_time: std::time::Instant::now() + std::time::Duration::from_millis(150),
-                        button_index: btn_idx,
-                        layer_index: *active_layer,
-                    });
-                } else {
-                    // Fallback to immediate execution if no button index provided
-                    if command_id == "Back" {
-                        if navigation_state.pop_expandable() {
-                            update_layer_for_navigation(navigation_state, config, layers, active_layer, needs_complete_redraw, original_layers, touches);
-                        }
-                    } else {
-                        execute_command(command_id, config);
-                    }
-                }
+        _ => {}
+    }
+}
+
+// Performs a one-shot action. Returns true when it navigated, which replaces the
+// layer and leaves any touch targets stale.
+fn perform_action<F>(
+    action: &ButtonAction,
+    uinput: &mut UInputHandle<F>,
+    config: &Config,
+    navigation_state: &mut NavigationState,
+    layers: &mut [FunctionLayer; 2],
+    active_layer: &mut usize,
+    needs_complete_redraw: &mut bool,
+    original_layers: &[FunctionLayer; 2],
+    touches: &mut HashMap<u32, (usize, usize)>,
+) -> bool
+where
+    F: AsRawFd,
+{
+    match action {
+        ButtonAction::Command(command_id) if command_id == "Back" => {
+            if navigation_state.pop_expandable() {
+                update_layer_for_navigation(navigation_state, config, layers, active_layer, needs_complete_redraw, original_layers, touches);
             }
         }
         ButtonAction::Expand(expandable_name) => {
-            if active {
-                // Add visual feedback delay for Expand actions
-                if let Some(btn_idx) = button_index {
-                    pending_actions.push(PendingAction {
-                        action: action.clone(),
-                        execution_time: std::time::Instant::now() + std::time::Duration::from_millis(150),
-                        button_index: btn_idx,
-                        layer_index: *active_layer,
-                    });
-                } else {
-                    // Fallback to immediate execution if no button index provided
-                    navigation_state.push_expandable(expandable_name.clone());
-                    update_layer_for_navigation(navigation_state, config, layers, active_layer, needs_complete_redraw, original_layers, touches);
-                }
-            }
+            navigation_state.push_expandable(expandable_name.clone());
+            update_layer_for_navigation(navigation_state, config, layers, active_layer, needs_complete_redraw, original_layers, touches);
         }
         ButtonAction::HyprlandExpand(hyprland_expand_name) => {
-            if active {
-                // Add visual feedback delay for HyprlandExpand actions
-                if let Some(btn_idx) = button_index {
-                    pending_actions.push(PendingAction {
-                        action: action.clone(),
-                        execution_time: std::time::Instant::now() + std::time::Duration::from_millis(150),
-                        button_index: btn_idx,
-                        layer_index: *active_layer,
-                    });
-                } else {
-                    // Fallback to immediate execution if no button index provided
-                    handle_hyprland_expand(hyprland_expand_name, config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches);
-                }
-            }
+            handle_hyprland_expand(hyprland_expand_name, config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches);
+        }
+        action => {
+            repeat_button_action(uinput, action, config);
+            return false;
+        }
+    }
+    true
+}
+
+// Performs the actions of recognized gestures, stopping at the first that navigated
+fn fire_gestures<F>(
+    fired: Vec<(gesture::Target, Gesture)>,
+    uinput: &mut UInputHandle<F>,
+    config: &Config,
+    navigation_state: &mut NavigationState,
+    layers: &mut [FunctionLayer; 2],
+    active_layer: &mut usize,
+    needs_complete_redraw: &mut bool,
+    original_layers: &[FunctionLayer; 2],
+    touches: &mut HashMap<u32, (usize, usize)>,
+    recognizer: &mut GestureRecognizer,
+) -> bool
+where
+    F: AsRawFd,
+{
+    for (target, gesture) in fired {
+        let Some((_, button)) = layers.get(target.layer).and_then(|layer| layer.buttons.get(target.button)) else {
+            continue;
+        };
+        let Some(action) = button.gesture_action(gesture).cloned() else {
+            continue;
+        };
+        if perform_action(&action, uinput, config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches) {
+            recognizer.reset();
+            return true;
         }
     }
+    false
 }
 
-fn execute_pending_actions<F>(
-    pending_actions: &mut Vec<PendingAction>,
+fn perform_swipe<F>(
+    action: &SwipeAction,
     uinput: &mut UInputHandle<F>,
     config: &Config,
     navigation_state: &mut NavigationState,
@@ -1207,211 +1944,239 @@ fn execute_pending_actions<F>(
     needs_complete_redraw: &mut bool,
     original_layers: &[FunctionLayer; 2],
     touches: &mut HashMap<u32, (usize, usize)>,
+    recognizer: &mut GestureRecognizer,
 ) where
     F: AsRawFd,
 {
-    let now = std::time::Instant::now();
-    let mut actions_to_remove = Vec::new();
-
-    for (index, pending_action) in pending_actions.iter().enumerate() {
-        if now >= pending_action.execution_time {
-            // Reset button visual state
-            if pending_action.layer_index < layers.len() && pending_action.button_index < layers[pending_action.layer_index].buttons.len() {
-                layers[pending_action.layer_index].buttons[pending_action.button_index].1.active = false;
-                layers[pending_action.layer_index].buttons[pending_action.button_index].1.changed = true;
-            }
-
-            // Execute the action
-            match &pending_action.action {
-                ButtonAction::Command(command_id) => {
-                    if command_id == "Back" {
-                        if navigation_state.pop_expandable() {
-                            update_layer_for_navigation(navigation_state, config, layers, active_layer, needs_complete_redraw, original_layers, touches);
-                        }
-                    } else {
-                        execute_command(command_id, config);
-                    }
-                }
-                ButtonAction::Expand(expandable_name) => {
-                    navigation_state.push_expandable(expandable_name.clone());
-                    update_layer_for_navigation(navigation_state, config, layers, active_layer, needs_complete_redraw, original_layers, touches);
-                }
-                ButtonAction::HyprlandExpand(hyprland_expand_name) => {
-                    handle_hyprland_expand(hyprland_expand_name, config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches);
-                }
-                _ => {} // Other actions are handled immediately
+    match action {
+        SwipeAction::NextLayer | SwipeAction::PreviousLayer => {
+            let step = if *action == SwipeAction::NextLayer { 1 } else { layers.len() - 1 };
+            *active_layer = (*active_layer + step) % layers.len();
+            *needs_complete_redraw = true;
+        }
+        SwipeAction::NextPage | SwipeAction::PreviousPage => {
+            let delta = if *action == SwipeAction::NextPage { 1 } else { -1 };
+            if layers[*active_layer].turn_page(delta) {
+                *needs_complete_redraw = true;
+            }
+        }
+        SwipeAction::Back => {
+            if navigation_state.pop_expandable() {
+                update_layer_for_navigation(navigation_state, config, layers, active_layer, needs_complete_redraw, original_layers, touches);
+                recognizer.reset();
+            }
+        }
+        SwipeAction::Button(action) => {
+            if perform_action(action, uinput, config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches) {
+                recognizer.reset();
             }
-
-            actions_to_remove.push(index);
         }
     }
+}
 
-    // Remove executed actions in reverse order to maintain indices
-    for &index in actions_to_remove.iter().rev() {
-        pending_actions.remove(index);
+// One repetition of a held button's action
+fn repeat_button_action<F>(uinput: &mut UInputHandle<F>, action: &ButtonAction, config: &Config)
+where
+    F: AsRawFd,
+{
+    match action {
+        ButtonAction::Key(key) => {
+            toggle_key(uinput, *key, 1);
+            toggle_key(uinput, *key, 0);
+        }
+        ButtonAction::KeyCombos(keys) => {
+            for key in keys {
+                toggle_key(uinput, *key, 1);
+            }
+            for key in keys.iter().rev() {
+                toggle_key(uinput, *key, 0);
+            }
+        }
+        ButtonAction::Command(command_id) => execute_command(command_id, config),
+        ButtonAction::Run(command) => run_command(command, config),
+        ButtonAction::Macro(steps) => macros::play(steps, config.user_env.clone()),
+        ButtonAction::TypeText(text, fallback) => macros::type_snippet(text, *fallback, config.user_env.clone()),
+        ButtonAction::Toggle(name) => {
+            if let Some(command_id) = toggle::flip(name) {
+                execute_command(&command_id, config);
+            }
+        }
+        ButtonAction::Timer(name) => timer::press(name),
+        ButtonAction::TimerReset(name) => timer::reset(name),
+        ButtonAction::NotificationsDnd => notifications::toggle_dnd(),
+        ButtonAction::NotificationInvoke(slot) => notifications::invoke(*slot),
+        ButtonAction::NotificationDismiss(slot) => notifications::dismiss(*slot),
+        ButtonAction::Expand(_) | ButtonAction::HyprlandExpand(_) => {}
     }
 }
 
 fn execute_command(command_id: &str, config: &Config) {
     if let Some(command) = config.commands.get(command_id) {
-        // Execute command in the background with cached user environment
-        std::thread::spawn({
-            let command = command.clone();
-            let user_env = config.user_env.clone();
-            move || {
-                println!("Executing command: {}", command);
-
-                // Use cached user environment for instant execution
-                if let Some(cached_env) = user_cache::get_cached_user_environment() {
-                    // Use user environment config if available, otherwise use cached detection
-                    let wayland_display = if let Some(user_env) = &user_env {
-                        user_env.wayland_display.clone()
-                    } else {
-                        cached_env.wayland_display.clone()
-                    };
+        run_command(command, config);
+    } else {
+        eprintln!("Command '{}' not found in commands.toml", command_id);
+    }
+}
 
-                    // Attempt to discover Hyprland instance signature for external tools
-                    let hypr_sig = {
-                        // Try /run/user/UID/hypr/*
-                        let hypr_dir = format!("{}/hypr", cached_env.runtime_dir);
-                        let mut found: Option<String> = None;
-                        if let Ok(entries) = std::fs::read_dir(&hypr_dir) {
-                            for entry in entries.flatten() {
-                                if let Ok(ft) = entry.file_type() {
-                                    if ft.is_dir() {
-                                        if let Some(name) = entry.file_name().to_str() {
-                                            if !name.is_empty() { found = Some(name.to_string()); break; }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        // Fallback: /tmp/hypr/*
-                        if found.is_none() {
-                            if let Ok(entries) = std::fs::read_dir("/tmp/hypr") {
-                                for entry in entries.flatten() {
-                                    if let Ok(ft) = entry.file_type() {
-                                        if ft.is_dir() {
-                                            if let Some(name) = entry.file_name().to_str() {
-                                                if !name.is_empty() { found = Some(name.to_string()); break; }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        found
-                    };
+fn run_command(command: &str, config: &Config) {
+    // Execute command in the background with cached user environment
+    std::thread::spawn({
+        let command = command.to_string();
+        let user_env = config.user_env.clone();
+        move || run_as_user(&command, user_env.as_ref())
+    });
+}
 
-                    // Build command with environment variables embedded
-                    let env_command = if let Some(sig) = &hypr_sig {
-                        format!(
-                            "export PATH='{}' XDG_RUNTIME_DIR='{}' WAYLAND_DISPLAY='{}' DISPLAY=':0' DBUS_SESSION_BUS_ADDRESS='unix:path={}/bus' HYPRLAND_INSTANCE_SIGNATURE='{}' XDG_SESSION_TYPE='wayland'; {}",
-                            cached_env.enhanced_path,
-                            cached_env.runtime_dir,
-                            wayland_display,
-                            cached_env.runtime_dir,
-                            sig,
-                            command
-                        )
-                    } else {
-                        format!(
-                            "export PATH='{}' XDG_RUNTIME_DIR='{}' WAYLAND_DISPLAY='{}' DISPLAY=':0' DBUS_SESSION_BUS_ADDRESS='unix:path={}/bus' XDG_SESSION_TYPE='wayland'; {}",
-                            cached_env.enhanced_path,
-                            cached_env.runtime_dir,
-                            wayland_display,
-                            cached_env.runtime_dir,
-                            command
-                        )
-                    };
+/// Launches `command` in the desktop user's session; returns once it is spawned.
+fn run_as_user(command: &str, user_env: Option<&UserEnvironment>) {
+    println!("Executing command: {}", command);
 
-                    // Preferred: launch into the user's systemd --user session so the process
-                    // is outside the system service cgroup/sandbox and inherits the right session
-                    // context. This avoids EPERM/Operation not permitted when TUIs spawn children.
-                    let mut launched = false;
-                    // Best effort: convey session-critical env to systemd-run itself via `env`
-                    // to ensure it can talk to the user manager and compositor.
-                    let mut try_systemd_run = std::process::Command::new("sudo");
-                    // Build argument list dynamically to include HYPRLAND_INSTANCE_SIGNATURE only when known
-                    {
-                        let mut args: Vec<String> = vec![
-                            "-u".into(),
-                            cached_env.username.clone(),
-                            "env".into(),
-                            format!("XDG_RUNTIME_DIR={}", cached_env.runtime_dir),
-                            format!("DBUS_SESSION_BUS_ADDRESS=unix:path={}/bus", cached_env.runtime_dir),
-                            format!("WAYLAND_DISPLAY={}", wayland_display),
-                            "DISPLAY=:0".into(),
-                            format!("PATH={}", cached_env.enhanced_path),
-                        ];
-                        if let Some(sig) = &hypr_sig {
-                            args.push(format!("HYPRLAND_INSTANCE_SIGNATURE={}", sig));
-                        }
-                        args.push("XDG_SESSION_TYPE=wayland".into());
-                        args.push("systemd-run".into());
-                        args.push("--user".into());
-                        args.push("--quiet".into());
-                        args.push("--collect".into());
-                        args.push("--same-dir".into());
-                        args.push("sh".into());
-                        args.push("-lc".into());
-                        args.push(env_command.clone());
-                        for a in args { try_systemd_run.arg(a); }
-                    }
-                    match try_systemd_run.spawn() {
-                        Ok(_) => {
-                            launched = true;
-                        }
-                        Err(e) => {
-                            eprintln!("systemd-run --user failed (sudo path): {}", e);
+    // Use cached user environment for instant execution
+    if let Some(cached_env) = user_cache::get_cached_user_environment() {
+        // Use user environment config if available, otherwise use cached detection
+        let wayland_display = if let Some(user_env) = user_env {
+            user_env.wayland_display.clone()
+        } else {
+            cached_env.wayland_display.clone()
+        };
+
+        // Attempt to discover Hyprland instance signature for external tools
+        let hypr_sig = {
+            // Try /run/user/UID/hypr/*
+            let hypr_dir = format!("{}/hypr", cached_env.runtime_dir);
+            let mut found: Option<String> = None;
+            if let Ok(entries) = std::fs::read_dir(&hypr_dir) {
+                for entry in entries.flatten() {
+                    if let Ok(ft) = entry.file_type() {
+                        if ft.is_dir() {
+                            if let Some(name) = entry.file_name().to_str() {
+                                if !name.is_empty() { found = Some(name.to_string()); break; }
+                            }
                         }
                     }
-
-                    if !launched {
-                        // Fallback: runuser login shell
-                        let runuser_candidates = [
-                            "/usr/bin/runuser",
-                            "/usr/sbin/runuser",
-                            "runuser",
-                        ];
-                        for bin in &runuser_candidates {
-                            let mut cmd = std::process::Command::new(bin);
-                            cmd.args(["-l", &cached_env.username, "-c", &env_command]);
-                            match cmd.spawn() {
-                                Ok(_) => {
-                                    launched = true;
-                                    break;
-                                }
-                                Err(e) => {
-                                    if e.kind() != std::io::ErrorKind::NotFound {
-                                        eprintln!("runuser variant '{}' failed: {}", bin, e);
-                                    }
+                }
+            }
+            // Fallback: /tmp/hypr/*
+            if found.is_none() {
+                if let Ok(entries) = std::fs::read_dir("/tmp/hypr") {
+                    for entry in entries.flatten() {
+                        if let Ok(ft) = entry.file_type() {
+                            if ft.is_dir() {
+                                if let Some(name) = entry.file_name().to_str() {
+                                    if !name.is_empty() { found = Some(name.to_string()); break; }
                                 }
                             }
                         }
                     }
+                }
+            }
+            found
+        };
 
-                    if !launched {
-                        // Last fallback: sudo -u USER sh -lc "..."
-                        let mut sudo = std::process::Command::new("sudo");
-                        sudo.args(["-u", &cached_env.username, "sh", "-lc", &env_command]);
-                        if let Err(e) = sudo.spawn() {
-                            eprintln!(
-                                "Failed to execute command '{}' as user '{}' via sudo: {}",
-                                command, cached_env.username, e
-                            );
-                            fallback_execution(&command);
+        // Build command with environment variables embedded
+        let env_command = if let Some(sig) = &hypr_sig {
+            format!(
+                "export PATH='{}' XDG_RUNTIME_DIR='{}' WAYLAND_DISPLAY='{}' DISPLAY=':0' DBUS_SESSION_BUS_ADDRESS='unix:path={}/bus' HYPRLAND_INSTANCE_SIGNATURE='{}' XDG_SESSION_TYPE='wayland'; {}",
+                cached_env.enhanced_path,
+                cached_env.runtime_dir,
+                wayland_display,
+                cached_env.runtime_dir,
+                sig,
+                command
+            )
+        } else {
+            format!(
+                "export PATH='{}' XDG_RUNTIME_DIR='{}' WAYLAND_DISPLAY='{}' DISPLAY=':0' DBUS_SESSION_BUS_ADDRESS='unix:path={}/bus' XDG_SESSION_TYPE='wayland'; {}",
+                cached_env.enhanced_path,
+                cached_env.runtime_dir,
+                wayland_display,
+                cached_env.runtime_dir,
+                command
+            )
+        };
+
+        // Preferred: launch into the user's systemd --user session so the process
+        // is outside the system service cgroup/sandbox and inherits the right session
+        // context. This avoids EPERM/Operation not permitted when TUIs spawn children.
+        let mut launched = false;
+        // Best effort: convey session-critical env to systemd-run itself via `env`
+        // to ensure it can talk to the user manager and compositor.
+        let mut try_systemd_run = std::process::Command::new("sudo");
+        // Build argument list dynamically to include HYPRLAND_INSTANCE_SIGNATURE only when known
+        {
+            let mut args: Vec<String> = vec![
+                "-u".into(),
+                cached_env.username.clone(),
+                "env".into(),
+                format!("XDG_RUNTIME_DIR={}", cached_env.runtime_dir),
+                format!("DBUS_SESSION_BUS_ADDRESS=unix:path={}/bus", cached_env.runtime_dir),
+                format!("WAYLAND_DISPLAY={}", wayland_display),
+                "DISPLAY=:0".into(),
+                format!("PATH={}", cached_env.enhanced_path),
+            ];
+            if let Some(sig) = &hypr_sig {
+                args.push(format!("HYPRLAND_INSTANCE_SIGNATURE={}", sig));
+            }
+            args.push("XDG_SESSION_TYPE=wayland".into());
+            args.push("systemd-run".into());
+            args.push("--user".into());
+            args.push("--quiet".into());
+            args.push("--collect".into());
+            args.push("--same-dir".into());
+            args.push("sh".into());
+            args.push("-lc".into());
+            args.push(env_command.clone());
+            for a in args { try_systemd_run.arg(a); }
+        }
+        match try_systemd_run.spawn() {
+            Ok(_) => {
+                launched = true;
+            }
+            Err(e) => {
+                eprintln!("systemd-run --user failed (sudo path): {}", e);
+            }
+        }
+
+        if !launched {
+            // Fallback: runuser login shell
+            let runuser_candidates = [
+                "/usr/bin/runuser",
+                "/usr/sbin/runuser",
+                "runuser",
+            ];
+            for bin in &runuser_candidates {
+                let mut cmd = std::process::Command::new(bin);
+                cmd.args(["-l", &cached_env.username, "-c", &env_command]);
+                match cmd.spawn() {
+                    Ok(_) => {
+                        launched = true;
+                        break;
+                    }
+                    Err(e) => {
+                        if e.kind() != std::io::ErrorKind::NotFound {
+                            eprintln!("runuser variant '{}' failed: {}", bin, e);
                         }
                     }
-                } else {
-                    // Fallback if cache is not available
-                    eprintln!("User environment cache not available, using fallback execution");
-                    fallback_execution(&command);
                 }
             }
-        });
+        }
+
+        if !launched {
+            // Last fallback: sudo -u USER sh -lc "..."
+            let mut sudo = std::process::Command::new("sudo");
+            sudo.args(["-u", &cached_env.username, "sh", "-lc", &env_command]);
+            if let Err(e) = sudo.spawn() {
+                eprintln!(
+                    "Failed to execute command '{}' as user '{}' via sudo: {}",
+                    command, cached_env.username, e
+                );
+                fallback_execution(command);
+            }
+        }
     } else {
-        eprintln!("Command '{}' not found in commands.toml", command_id);
+        // Fallback if cache is not available
+        eprintln!("User environment cache not available, using fallback execution");
+        fallback_execution(command);
     }
 }
 
@@ -1467,7 +2232,24 @@ fn expand_user_path(username: &str) -> Result<String, std::io::Error> {
 }
 
 
+// Offline helper commands; without arguments tiny-dfr runs as the daemon.
+fn run_subcommand(args: &[String]) -> Option<i32> {
+    let (command, rest) = args.split_first()?;
+    Some(match command.as_str() {
+        "migrate" => upstream::migrate_main(rest),
+        "schema" => schema::schema_main(rest),
+        _ => {
+            eprintln!("Unknown command '{}'. Available: migrate, schema", command);
+            2
+        }
+    })
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(code) = run_subcommand(&args) {
+        std::process::exit(code);
+    }
     let mut drm = DrmBackend::open_card().unwrap();
     let (height, width) = drm.mode().size();
     let _ = panic::catch_unwind(AssertUnwindSafe(|| real_main(&mut drm)));
@@ -1499,7 +2281,9 @@ fn real_main(drm: &mut DrmBackend) {
     let mut uinput = UInputHandle::new(OpenOptions::new().write(true).open("/dev/uinput").unwrap());
     let mut backlight = BacklightManager::new();
     let mut last_redraw_minute = Local::now().minute();
+    let mut last_clock_second = 0;
     let mut last_battery_update_minute = Local::now().minute();
+    let mut last_visibility_stamp = visibility::stamp();
     let mut cfg_mgr = ConfigManager::new();
     let (mut cfg, mut layers) = cfg_mgr.load_config(width);
     
@@ -1521,7 +2305,6 @@ fn real_main(drm: &mut DrmBackend) {
     // Initialize performance optimizations
     user_cache::initialize_user_environment_cache();
     icon_cache::preload_common_icons();
-    let _battery_monitor = battery_monitor::BatteryMonitor::new("BAT0".to_string());
     let _system_monitor = system_monitor::SystemMonitor::new();
     icon_cache::start_background_preloader();
 
@@ -1538,15 +2321,11 @@ fn real_main(drm: &mut DrmBackend) {
     // Start preloading common icons in background
     icon_cache::preload_common_icons();
 
-    // Start battery monitoring if there's a battery device
-    let _battery_monitor = if let Some(battery_device) = find_battery_device() {
-        Some(battery_monitor::BatteryMonitor::new(battery_device))
-    } else {
-        None
-    };
-
     // Start system state monitoring
     let _system_monitor = system_monitor::SystemMonitor::new();
+    let _visibility_monitor = visibility::VisibilityMonitor::new();
+    let _toggle_monitor = toggle::ToggleMonitor::new();
+    let _resource_monitor = resource_monitor::ResourceMonitor::new();
 
     // Start background icon preloader (after initial setup)
     icon_cache::start_background_preloader();
@@ -1574,19 +2353,37 @@ fn real_main(drm: &mut DrmBackend) {
     epoll
         .add(&udev_monitor, EpollEvent::new(EpollFlags::EPOLLIN, 3))
         .unwrap();
+    let network_monitor = match network::NetworkMonitor::new() {
+        Ok(monitor) => {
+            epoll.add(&monitor, EpollEvent::new(EpollFlags::EPOLLIN, 4)).unwrap();
+            Some(monitor)
+        }
+        Err(e) => {
+            eprintln!("Network status unavailable, cannot listen to netlink: {}", e);
+            None
+        }
+    };
+    let mut last_signal_refresh = std::time::Instant::now();
+    let mut battery_alert = BatteryAlert::default();
+    epoll.add(audio::wake_fd(), EpollEvent::new(EpollFlags::EPOLLIN, 5)).unwrap();
+    epoll.add(notifications::wake_fd(), EpollEvent::new(EpollFlags::EPOLLIN, 6)).unwrap();
+    epoll.add(network::wake_fd(), EpollEvent::new(EpollFlags::EPOLLIN, 7)).unwrap();
+    // Volume sliders being pressed: where the finger landed and the volume then
+    let mut sliders: HashMap<u32, VolumeSlider> = HashMap::new();
     uinput.set_evbit(EventKind::Key).unwrap();
     for layer in &layers {
         for button in &layer.buttons {
-            match &button.1.action {
-                ButtonAction::Key(key) => {
-                    uinput.set_keybit(*key).unwrap();
-                }
-                ButtonAction::KeyCombos(keys) => {
-                    for key in keys {
-                        uinput.set_keybit(*key).unwrap();
-                    }
-                }
-                _ => {}
+            for key in button.1.keys() {
+                uinput.set_keybit(key).unwrap();
+            }
+        }
+    }
+
+    // Also register keys from swipe bindings
+    for action in cfg.swipes.actions() {
+        if let SwipeAction::Button(action) = action {
+            for key in action.keys() {
+                uinput.set_keybit(key).unwrap();
             }
         }
     }
@@ -1594,16 +2391,8 @@ fn real_main(drm: &mut DrmBackend) {
     // Also register keys from expandables
     for expandable_buttons in cfg.expandables.values() {
         for button in expandable_buttons {
-            match &button.action {
-                ButtonAction::Key(key) => {
-                    uinput.set_keybit(*key).unwrap();
-                }
-                ButtonAction::KeyCombos(keys) => {
-                    for key in keys {
-                        uinput.set_keybit(*key).unwrap();
-                    }
-                }
-                _ => {}
+            for key in button.keys() {
+                uinput.set_keybit(key).unwrap();
             }
         }
     }
@@ -1612,16 +2401,8 @@ fn real_main(drm: &mut DrmBackend) {
     for hyprland_expandable_configs in cfg.hyprland_expandables.values() {
         for hyprland_config in hyprland_expandable_configs {
             for button in &hyprland_config.layer_keys {
-                match &button.action {
-                    ButtonAction::Key(key) => {
-                        uinput.set_keybit(*key).unwrap();
-                    }
-                    ButtonAction::KeyCombos(keys) => {
-                        for key in keys {
-                            uinput.set_keybit(*key).unwrap();
-                        }
-                    }
-                    _ => {}
+                for key in button.keys() {
+                    uinput.set_keybit(key).unwrap();
                 }
             }
         }
@@ -1644,16 +2425,43 @@ fn real_main(drm: &mut DrmBackend) {
         })
         .unwrap();
     uinput.dev_create().unwrap();
+    let _macro_player = macros::MacroPlayer::new(uinput.as_inner_mut().try_clone().unwrap());
 
     let mut digitizer: Option<InputDevice> = None;
     let mut touches: HashMap<u32, (usize, usize)> = HashMap::new();
-    let mut pending_actions: Vec<PendingAction> = Vec::new();
+    let gesture_thresholds = |cfg: &Config| {
+        (
+            std::time::Duration::from_millis(cfg.long_press_ms),
+            std::time::Duration::from_millis(cfg.double_tap_ms),
+        )
+    };
+    let (long_press, double_tap) = gesture_thresholds(&cfg);
+    let mut recognizer = GestureRecognizer::new(long_press, double_tap);
+    let mut swipe_tracker = SwipeTracker::default();
+    let mut key_repeats: HashMap<u32, KeyRepeat> = HashMap::new();
     loop {
+        // Keys held down from the current layers, let go if a reload replaces them
+        let held_keys: Vec<ButtonAction> = touches
+            .values()
+            .map(|&(layer, btn)| &layers[layer].buttons[btn].1)
+            .filter(|button| button.active && button.holds_keys_under(&cfg))
+            .map(|button| button.action.clone())
+            .collect();
         if cfg_mgr.update_config(&mut cfg, &mut layers, width) {
+            // Fingers down during the reload point at the old layers' buttons
+            for action in &held_keys {
+                hold_keys(&mut uinput, action, false);
+            }
+            touches.clear();
+            key_repeats.clear();
+            sliders.clear();
             active_layer = 0;
             needs_complete_redraw = true;
             original_layers = layers.clone(); // Update original layers
             navigation_state.reset_to_main(); // Reset navigation on config update
+            let (long_press, double_tap) = gesture_thresholds(&cfg);
+            recognizer.set_thresholds(long_press, double_tap);
+            recognizer.reset();
 
             // Update keyboard backlight step size only (can't recreate manager after privilege drop)
             kbd_backlight.update_brightness_step(cfg.keyboard_brightness_step);
@@ -1667,6 +2475,7 @@ fn real_main(drm: &mut DrmBackend) {
             needs_complete_redraw = true;
             // Clear touches to prevent accidental triggering after timeout
             clear_all_touches(&mut layers, &mut touches);
+            recognizer.reset();
 
             // Force update hyprland buttons with current window state after timeout
             if let Ok(window_info) = hyprland::get_active_window_info() {
@@ -1683,8 +2492,16 @@ fn real_main(drm: &mut DrmBackend) {
         }
 
         let now = Local::now();
-        let ms_left = ((60 - now.second()) * 1000) as i32;
-        let mut next_timeout_ms = min(ms_left, TIMEOUT_MS);
+        let mut next_timeout_ms = min(ms_to_clock_tick(&now, false), TIMEOUT_MS);
+
+        // Clocks showing seconds redraw on each second, and only while visible
+        if layers[active_layer].shows_seconds() {
+            if now.timestamp() != last_clock_second {
+                layers[active_layer].mark_changed(Button::shows_seconds);
+                last_clock_second = now.timestamp();
+            }
+            next_timeout_ms = min(next_timeout_ms, ms_to_clock_tick(&now, true));
+        }
 
         if cfg.enable_pixel_shift {
             let (pixel_shift_needs_redraw, pixel_shift_next_timeout_ms) = pixel_shift.update();
@@ -1704,29 +2521,45 @@ fn real_main(drm: &mut DrmBackend) {
             }
         }
 
-        // Process pending actions (for visual feedback delay)
-        if !pending_actions.is_empty() {
-            execute_pending_actions(
-                &mut pending_actions,
-                &mut uinput,
-                &cfg,
-                &mut navigation_state,
-                &mut layers,
-                &mut active_layer,
-                &mut needs_complete_redraw,
-                &original_layers,
-                &mut touches,
-            );
+        // Fire long presses and taps held back for a possible double tap
+        recognizer.retain_touches(|slot| touches.contains_key(&slot));
+        let fired = recognizer.tick(std::time::Instant::now());
+        fire_gestures(
+            fired,
+            &mut uinput,
+            &cfg,
+            &mut navigation_state,
+            &mut layers,
+            &mut active_layer,
+            &mut needs_complete_redraw,
+            &original_layers,
+            &mut touches,
+            &mut recognizer,
+        );
+        if let Some(deadline) = recognizer.next_deadline() {
+            let remaining_ms = deadline.saturating_duration_since(std::time::Instant::now()).as_millis() as i32;
+            next_timeout_ms = min(next_timeout_ms, remaining_ms.max(1));
         }
 
-        // Calculate next timeout for pending actions
-        if !pending_actions.is_empty() {
+        // Auto-repeat held buttons; repeats end with their touch or a layer change
+        if !key_repeats.is_empty() {
+            key_repeats.retain(|slot, repeat| repeat.held_by(touches.get(slot)));
             let now = std::time::Instant::now();
-            for pending_action in &pending_actions {
-                let remaining_ms = pending_action.execution_time.saturating_duration_since(now).as_millis() as i32;
-                if remaining_ms > 0 {
-                    next_timeout_ms = min(next_timeout_ms, remaining_ms);
+            for repeat in key_repeats.values_mut() {
+                if repeat.due(now) {
+                    let action = &layers[repeat.layer].buttons[repeat.button].1.action;
+                    match action {
+                        ButtonAction::Key(Key::IllumUp) if cfg.keyboard_brightness_enabled => {
+                            kbd_backlight.increase_brightness();
+                        }
+                        ButtonAction::Key(Key::IllumDown) if cfg.keyboard_brightness_enabled => {
+                            kbd_backlight.decrease_brightness();
+                        }
+                        _ => repeat_button_action(&mut uinput, action, &cfg),
+                    }
                 }
+                let remaining_ms = repeat.next.saturating_duration_since(now).as_millis() as i32;
+                next_timeout_ms = min(next_timeout_ms, remaining_ms.max(1));
             }
         }
 
@@ -1736,11 +2569,15 @@ fn real_main(drm: &mut DrmBackend) {
             needs_complete_redraw = true;
             last_redraw_minute = current_minute;
         }
-        if layers[active_layer].displays_battery && (current_minute != last_battery_update_minute) {
-            for button in &mut layers[active_layer].buttons {
-                if let ButtonImage::Battery(_, _, _) = button.1.image {
-                    button.1.changed = true;
-                }
+        // Plugging in, unplugging and status changes arrive as power_supply uevents, but
+        // a discharging or charging battery's capacity and energy_now change without one:
+        // ACPI batteries only notify when the firmware does, which most do not do per
+        // percent. So the charge is re-read from sysfs once a minute, here rather than on
+        // a thread, while a Battery button or the alert needs it.
+        let watches_battery = layers[active_layer].displays_battery || cfg.battery_alert;
+        if watches_battery && (current_minute != last_battery_update_minute) {
+            if battery_monitor::rescan() {
+                layers[active_layer].mark_changed(|button| matches!(button.image, ButtonImage::Battery(..)));
             }
             last_battery_update_minute = current_minute;
         }
@@ -1750,6 +2587,68 @@ fn real_main(drm: &mut DrmBackend) {
             icon_cache::cleanup_cache();
         }
 
+        // Redraw toggles switched by a tap or found switched by their probe
+        let toggles_changed = toggle::take_changed();
+        if toggles_changed {
+            layers[active_layer].mark_changed(|button| matches!(button.image, ButtonImage::Toggle(..)));
+        }
+
+        // Show and hide VisibleWhen buttons as power, focus, devices, toggles or the hour change
+        let visibility_stamp = visibility::stamp();
+        if toggles_changed || visibility_stamp != last_visibility_stamp {
+            let facts = visibility::current_facts();
+            for (i, layer) in layers.iter_mut().enumerate() {
+                if layer.update_visibility(&facts) && i == active_layer {
+                    needs_complete_redraw = true;
+                }
+            }
+            last_visibility_stamp = visibility_stamp;
+        }
+        if layers[active_layer].entries.iter().any(|entry| entry.visible_when.is_some()) {
+            next_timeout_ms = min(next_timeout_ms, STATE_POLL_MS);
+        }
+
+        // Redraw just the resource widgets that got a new sample
+        let sampled = resource_monitor::take_updated();
+        if !sampled.is_empty() {
+            layers[active_layer].mark_changed(|button| matches!(&button.image, ButtonImage::Resource(source, _, _) if sampled.contains(source)));
+        }
+        if layers[active_layer].buttons.iter().any(|(_, button)| matches!(button.image, ButtonImage::Resource(..))) {
+            next_timeout_ms = min(next_timeout_ms, RESOURCE_SAMPLE_MS);
+        }
+
+        // Link and address changes arrive over netlink; signal strength is re-read and
+        // the SSID answered from a worker thread
+        let shows_network = layers[active_layer].buttons.iter().any(|(_, button)| matches!(button.image, ButtonImage::Network(_)));
+        if let Some(monitor) = &network_monitor {
+            monitor.process_events();
+        }
+        if shows_network && last_signal_refresh.elapsed() >= NETWORK_SIGNAL_REFRESH {
+            last_signal_refresh = std::time::Instant::now();
+            network::refresh();
+        }
+        if network::take_changed() {
+            layers[active_layer].mark_changed(|button| matches!(button.image, ButtonImage::Network(_)));
+        }
+        if audio::take_changed() {
+            layers[active_layer].mark_changed(Button::shows_audio);
+        }
+        if notifications::take_changed() {
+            layers[active_layer].mark_changed(Button::shows_notifications);
+        }
+        // Lock LEDs and Hyprland layout events change without a key reaching this loop
+        let shows_indicators = layers[active_layer].buttons.iter().any(|(_, button)| matches!(button.image, ButtonImage::Indicator(..)));
+        if shows_indicators {
+            keyboard_state::sync_leds();
+            next_timeout_ms = min(next_timeout_ms, STATE_POLL_MS);
+        }
+        if keyboard_state::take_changed() {
+            layers[active_layer].mark_changed(|button| matches!(button.image, ButtonImage::Indicator(..)));
+        }
+        if layers[active_layer].buttons.iter().any(|(_, button)| matches!(button.image, ButtonImage::Toggle(..))) {
+            next_timeout_ms = min(next_timeout_ms, STATE_POLL_MS);
+        }
+
         // Check for Hyprland plugin updates and update button content
         // Also periodically retry if Hyprland becomes available
         let hyprland_cache_updated = hyprland::check_and_reset_cache_updated();
@@ -1901,7 +2800,60 @@ fn real_main(drm: &mut DrmBackend) {
             }
         }
 
-        if needs_complete_redraw || layers[active_layer].buttons.iter().any(|b| b.1.changed) {
+        // Timers redraw just their own button, every second while running
+        let (ticked, commands) = timer::tick();
+        for command in commands {
+            execute_command(&command, &cfg);
+        }
+        if !ticked.is_empty() {
+            layers[active_layer].mark_changed(|button| matches!(&button.image, ButtonImage::Timer(name) if ticked.contains(name)));
+        }
+        let shown_timers: Vec<&str> = layers[active_layer]
+            .visible_buttons()
+            .into_iter()
+            .filter_map(|i| match &layers[active_layer].buttons[i].1.image {
+                ButtonImage::Timer(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        if let Some(wait) = timer::next_change(|name| shown_timers.contains(&name)) {
+            next_timeout_ms = min(next_timeout_ms, wait.as_millis() as i32 + 1);
+        }
+
+        // A critical battery takes over the bar until it is tapped away
+        let alert_change = if cfg.battery_alert {
+            battery_alert.update(battery_monitor::get_cached_battery_state().as_ref())
+        } else {
+            battery_alert.update(None)
+        };
+        match alert_change {
+            AlertChange::Raised => {
+                if cfg.battery_alert_wake {
+                    backlight.wake();
+                }
+                if let Some(command) = &cfg.battery_alert_command {
+                    execute_command(command, &cfg);
+                }
+            }
+            AlertChange::Cleared => needs_complete_redraw = true,
+            AlertChange::None => {}
+        }
+        if battery_alert.is_shown() {
+            next_timeout_ms = min(next_timeout_ms, battery_alert::FLASH_INTERVAL.as_millis() as i32);
+            if battery_alert.needs_redraw() {
+                let shift = if cfg.enable_pixel_shift {
+                    pixel_shift.get()
+                } else {
+                    (0.0, 0.0)
+                };
+                let clips = draw_battery_alert(&cfg, width as i32, height as i32, &surface, shift, battery_alert.phase());
+                let data = surface.data().unwrap();
+                drm.map().unwrap().as_mut()[..data.len()].copy_from_slice(&data);
+                drm.dirty(&clips).unwrap();
+                // The layer comes back in full once the alert goes
+                needs_complete_redraw = true;
+            }
+        } else if needs_complete_redraw || layers[active_layer].buttons.iter().any(|b| b.1.changed) {
             let shift = if cfg.enable_pixel_shift {
                 pixel_shift.get()
             } else {
@@ -1929,7 +2881,11 @@ fn real_main(drm: &mut DrmBackend) {
             e => e.unwrap(),
         };
 
-        _ = udev_monitor.iter().last();
+        // Plugging in the charger or a battery update arrives as a power_supply uevent
+        let power_changed = udev_monitor.iter().fold(false, |changed, event| battery_monitor::handle_event(&event) | changed);
+        if power_changed {
+            layers[active_layer].mark_changed(|button| matches!(button.image, ButtonImage::Battery(..)));
+        }
 
         input_tb.dispatch().unwrap();
         input_main.dispatch().unwrap();
@@ -1949,6 +2905,7 @@ fn real_main(drm: &mut DrmBackend) {
                     }
                 }
                 Event::Keyboard(KeyboardEvent::Key(key)) => {
+                    keyboard_state::key_event(key.key(), key.key_state() == KeyState::Pressed);
                     if key.key() == Key::Fn as u32 {
                         let new_layer = match key.key_state() {
                             KeyState::Pressed => 1,
@@ -1966,43 +2923,75 @@ fn real_main(drm: &mut DrmBackend) {
                     }
                     match te {
                         TouchEvent::Down(dn) => {
+                            if battery_alert.is_shown() {
+                                battery_alert.dismiss();
+                                needs_complete_redraw = true;
+                                continue;
+                            }
                             let x = dn.x_transformed(width as u32);
                             let y = dn.y_transformed(height as u32);
-                            if let Some(btn) = layers[active_layer].hit(width, height, x, y, None) {
-                                touches.insert(dn.seat_slot(), (active_layer, btn));
-                                
-                                // Get the button action before borrowing layers mutably
-                                let button_action = &layers[active_layer].buttons[btn].1.action;
-                                
-                                // Handle keyboard backlight actions directly
-                                let handled_by_keyboard_backlight = if cfg.keyboard_brightness_enabled {
-                                    match button_action {
-                                        ButtonAction::Key(Key::IllumUp) => {
-                                            kbd_backlight.increase_brightness()
-                                        }
-                                        ButtonAction::Key(Key::IllumDown) => {
-                                            kbd_backlight.decrease_brightness()
-                                        }
-                                        _ => false
-                                    }
-                                } else {
-                                    false
-                                };
-                                
-                                // Only send key event if we didn't handle it with keyboard backlight
-                                if !handled_by_keyboard_backlight {
-                                    // Extract the button action to avoid borrowing conflict
-                                    let action = layers[active_layer].buttons[btn].1.action.clone();
-                                    let old_active = layers[active_layer].buttons[btn].1.active;
-                                    if old_active != true {
-                                        layers[active_layer].buttons[btn].1.active = true;
-                                        layers[active_layer].buttons[btn].1.changed = true;
-                                        handle_button_action(&mut uinput, &action, &cfg, true, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, Some(btn));
+                            swipe_tracker.down(dn.seat_slot(), x);
+                            if let Some(delta) = layers[active_layer].page_arrow(x) {
+                                if layers[active_layer].turn_page(delta) {
+                                    needs_complete_redraw = true;
+                                }
+                                navigation_state.update_interaction_time();
+                                continue;
+                            }
+                            if let Some(btn) = layers[active_layer].hit(height, x, y, None) {
+                                let slot = dn.seat_slot();
+                                touches.insert(slot, (active_layer, btn));
+
+                                // A new touch may settle a tap held back on another button
+                                let button = &layers[active_layer].buttons[btn].1;
+                                let target = gesture::Target { layer: active_layer, button: btn };
+                                let fired = recognizer.down(slot, target, button.secondary(), std::time::Instant::now());
+                                if !fire_gestures(fired, &mut uinput, &cfg, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut recognizer) {
+                                    let button = &layers[active_layer].buttons[btn].1;
+                                    let is_kbd_backlight_button = button.is_kbd_backlight(&cfg);
+                                    let holds_keys = button.holds_keys_under(&cfg);
+                                    let was_active = button.active;
+                                    let button_is_slider = button.is_volume_slider();
+                                    // Nothing else to wait for: no secondary action, slide or swipe
+                                    let fires_on_press = !button.has_secondary() && !button_is_slider && !cfg.swipes.enabled();
+                                    let action = button.action.clone();
+                                    if let (Some(repeat), true) = (button.repeat, button.repeats() || is_kbd_backlight_button) {
+                                        key_repeats.insert(slot, KeyRepeat::new(active_layer, btn, repeat, std::time::Instant::now()));
                                     }
-                                } else {
-                                    // Show visual feedback for keyboard backlight buttons (without key event)
                                     layers[active_layer].buttons[btn].1.active = true;
                                     layers[active_layer].buttons[btn].1.changed = true;
+                                    if button_is_slider {
+                                        sliders.insert(
+                                            slot,
+                                            VolumeSlider {
+                                                start_x: x,
+                                                start_volume: audio::current().volume,
+                                                button_width: layers[active_layer].button_rect(btn).map_or(width as f64, |(_, w)| w),
+                                                sliding: false,
+                                            },
+                                        );
+                                    }
+
+                                    // Keyboard backlight, held keys, repeating buttons and buttons with
+                                    // only a tap act right away; the rest waits for the recognizer
+                                    if is_kbd_backlight_button {
+                                        recognizer.cancel(slot);
+                                        match action {
+                                            ButtonAction::Key(Key::IllumUp) => kbd_backlight.increase_brightness(),
+                                            _ => kbd_backlight.decrease_brightness(),
+                                        };
+                                    } else if holds_keys {
+                                        recognizer.cancel(slot);
+                                        if !was_active {
+                                            hold_keys(&mut uinput, &action, true);
+                                        }
+                                    } else if key_repeats.contains_key(&slot) {
+                                        recognizer.cancel(slot);
+                                        repeat_button_action(&mut uinput, &action, &cfg);
+                                    } else if fires_on_press {
+                                        recognizer.cancel(slot);
+                                        fire_gestures(vec![(target, Gesture::Tap)], &mut uinput, &cfg, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut recognizer);
+                                    }
                                 }
 
                                 // Update interaction time for any touch
@@ -2010,66 +2999,107 @@ fn real_main(drm: &mut DrmBackend) {
                             }
                         }
                         TouchEvent::Motion(mtn) => {
+                            let x = mtn.x_transformed(width as u32);
+                            let y = mtn.y_transformed(height as u32);
+
+                            // Dragging a volume slider sets the volume instead of tapping
+                            if let Some(slider) = sliders.get_mut(&mtn.seat_slot()) {
+                                let dx = x - slider.start_x;
+                                if !slider.sliding && dx.abs() >= SLIDER_START_PX {
+                                    slider.sliding = true;
+                                    recognizer.cancel(mtn.seat_slot());
+                                }
+                                if slider.sliding {
+                                    let volume = slider.start_volume as f64 + dx / slider.button_width * 100.0;
+                                    audio::set_volume(volume.clamp(0.0, 100.0).round() as u32);
+                                    if let Some(&(layer, btn)) = touches.get(&mtn.seat_slot()) {
+                                        layers[layer].buttons[btn].1.changed = true;
+                                    }
+                                    navigation_state.update_interaction_time();
+                                    continue;
+                                }
+                            }
+
+                            // A swipe takes its fingers away from the buttons they landed on
+                            if cfg.swipes.enabled() {
+                                if let Some((swipe, slots)) = swipe_tracker.motion(mtn.seat_slot(), x, cfg.swipes.distance()) {
+                                    for slot in slots {
+                                        key_repeats.remove(&slot);
+                                        recognizer.cancel(slot);
+                                        if let Some(&(layer, btn)) = touches.get(&slot) {
+                                            let button = &mut layers[layer].buttons[btn].1;
+                                            if button.active && button.holds_keys_under(&cfg) {
+                                                hold_keys(&mut uinput, &button.action, false);
+                                            }
+                                            button.active = false;
+                                            button.changed = true;
+                                        }
+                                    }
+                                    if let Some(action) = cfg.swipes.binding(swipe.left, swipe.fingers).cloned() {
+                                        perform_swipe(&action, &mut uinput, &cfg, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut recognizer);
+                                    }
+                                    navigation_state.update_interaction_time();
+                                }
+                                if swipe_tracker.is_swiping(mtn.seat_slot()) {
+                                    continue;
+                                }
+                            }
+
                             if !touches.contains_key(&mtn.seat_slot()) {
                                 continue;
                             }
-
-                            let x = mtn.x_transformed(width as u32);
-                            let y = mtn.y_transformed(height as u32);
                             let (layer, btn) = *touches.get(&mtn.seat_slot()).unwrap();
                             let hit = layers[active_layer]
-                                .hit(width, height, x, y, Some(btn))
+                                .hit(height, x, y, Some(btn))
                                 .is_some();
                             
-                            // Check if this is a keyboard backlight button
-                            let button_action = &layers[layer].buttons[btn].1.action;
-                            let is_kbd_backlight_button = cfg.keyboard_brightness_enabled &&
-                                matches!(button_action, ButtonAction::Key(Key::IllumUp) | ButtonAction::Key(Key::IllumDown));
-                            
-                            if !is_kbd_backlight_button {
-                                // Extract the button action to avoid borrowing conflict
-                                let action = layers[layer].buttons[btn].1.action.clone();
-                                let old_active = layers[layer].buttons[btn].1.active;
-                                if old_active != hit {
-                                    layers[layer].buttons[btn].1.active = hit;
-                                    layers[layer].buttons[btn].1.changed = true;
-                                    handle_button_action(&mut uinput, &action, &cfg, hit, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, Some(btn));
-                                }
-                            } else {
-                                // Handle visual feedback for keyboard backlight buttons (without key event)
-                                layers[layer].buttons[btn].1.active = hit;
+                            let button = &layers[layer].buttons[btn].1;
+                            let holds_keys = button.holds_keys_under(&cfg);
+                            let action = button.action.clone();
+                            if !hit {
+                                key_repeats.remove(&mtn.seat_slot());
+                                recognizer.cancel(mtn.seat_slot());
+                            }
+
+                            // Held keys go down again when the finger slides back; anything
+                            // else was cancelled when it slid off, so it stays unlit
+                            let active = hit && (holds_keys || layers[layer].buttons[btn].1.active);
+                            if layers[layer].buttons[btn].1.active != active {
+                                layers[layer].buttons[btn].1.active = active;
                                 layers[layer].buttons[btn].1.changed = true;
+                                if holds_keys {
+                                    hold_keys(&mut uinput, &action, active);
+                                }
                             }
 
                             // Update interaction time for motion
                             navigation_state.update_interaction_time();
                         }
                         TouchEvent::Up(up) => {
+                            swipe_tracker.up(up.seat_slot());
+                            sliders.remove(&up.seat_slot());
                             if !touches.contains_key(&up.seat_slot()) {
                                 continue;
                             }
                             let (layer, btn) = *touches.get(&up.seat_slot()).unwrap();
                             
-                            // Check if this was a keyboard backlight button
-                            let button_action = &layers[layer].buttons[btn].1.action;
-                            let is_kbd_backlight_button = cfg.keyboard_brightness_enabled &&
-                                matches!(button_action, ButtonAction::Key(Key::IllumUp) | ButtonAction::Key(Key::IllumDown));
-
-                            if !is_kbd_backlight_button {
-                                // Extract the button action to avoid borrowing conflict
-                                let action = layers[layer].buttons[btn].1.action.clone();
-                                let old_active = layers[layer].buttons[btn].1.active;
-                                if old_active != false {
-                                    layers[layer].buttons[btn].1.active = false;
-                                    layers[layer].buttons[btn].1.changed = true;
-                                    handle_button_action(&mut uinput, &action, &cfg, false, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, Some(btn));
-                                }
-                            } else {
-                                // Reset visual state for keyboard backlight buttons
+                            let button = &layers[layer].buttons[btn].1;
+                            let holds_keys = button.holds_keys_under(&cfg);
+                            let action = button.action.clone();
+                            key_repeats.remove(&up.seat_slot());
+
+                            if layers[layer].buttons[btn].1.active {
                                 layers[layer].buttons[btn].1.active = false;
                                 layers[layer].buttons[btn].1.changed = true;
+                                if holds_keys {
+                                    hold_keys(&mut uinput, &action, false);
+                                }
                             }
 
+                            // A tap fires on release unless the finger slid off or it already long-pressed
+                            let fired = recognizer.up(up.seat_slot(), std::time::Instant::now());
+                            fire_gestures(fired, &mut uinput, &cfg, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut recognizer);
+
                             // Update interaction time for release
                             navigation_state.update_interaction_time();
                         }
@@ -2200,3 +3230,234 @@ fn update_hyprland_button_content(button: &mut (usize, Button), window_info: &hy
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pagination() {
+        let layer = |count: usize| {
+            let buttons: Vec<String> = (0..=count).map(|i| format!(r#"{{ Text = "{}", Action = "F1" }}"#, i)).collect();
+            let table: toml::Table = toml::from_str(&format!("Buttons = [{}]", buttons.join(","))).unwrap();
+            let mut layer = FunctionLayer::with_config(table["Buttons"].clone().try_into().unwrap(), Bar::new(2000, false, 100));
+            layer.buttons[0].1.action = ButtonAction::Command("Back".to_string());
+            layer.relayout();
+            layer
+        };
+
+        let fits = layer(9);
+        assert!(!fits.is_paged());
+        assert_eq!(fits.button_rect(1).unwrap().0, 201.0);
+
+        // Arrows leave 1840px for 16 slots of at least 100px, one taken by Back
+        let mut paged = layer(32);
+        assert_eq!(paged.pages.starts, vec![1, 16, 31]);
+        assert_eq!(paged.visible_buttons()[..2], [0, 1]);
+        let first = paged.button_rect(1);
+        assert!(paged.turn_page(1));
+        assert_eq!(paged.visible_buttons()[..2], [0, 16]);
+        assert_eq!(paged.button_rect(16), first);
+        assert!(paged.turn_page(1));
+        assert!(!paged.turn_page(1));
+        assert_eq!(paged.visible_buttons(), vec![0, 31, 32]);
+        assert_eq!(paged.page_arrow(10.0), Some(-1));
+        assert_eq!(paged.hit(60, 5.0, 30.0, None), None);
+        assert_eq!(paged.hit(60, 100.0, 30.0, None), Some(0));
+        assert_eq!(paged.hit(60, 100.0, 30.0, Some(1)), None);
+    }
+
+    #[test]
+    fn test_alignment() {
+        let table: toml::Table = toml::from_str(
+            r#"Buttons = [
+                { Text = "Play", Action = "PlayPause", Width = 100 },
+                { Text = "Clock", Action = "Time", Width = 200, Align = "Right" },
+                { Spacer = true, Width = 50 },
+                { Text = "Title", Action = "Title", Width = 300, Align = "Center" },
+                { Text = "Note", Width = 100 },
+            ]"#,
+        )
+        .unwrap();
+        let layer = FunctionLayer::with_config(table["Buttons"].clone().try_into().unwrap(), Bar::new(2000, false, 100));
+        // The entry without an Action stays a gap
+        assert_eq!(layer.buttons.len(), 3);
+        let rect = |i| layer.button_rect(i).unwrap();
+        assert_eq!(rect(0), (0.0, 100.0));
+        assert_eq!(rect(1), (1800.0, 200.0));
+        assert_eq!(rect(2), (850.0, 300.0));
+        assert_eq!(layer.hit(60, 120.0, 30.0, None), None);
+        assert_eq!(layer.hit(60, 900.0, 30.0, None), Some(2));
+
+        // Without groups to pin, flexible buttons fill what fixed ones leave
+        let table: toml::Table = toml::from_str(
+            r#"Buttons = [
+                { Text = "A", Action = "F1" },
+                { Spacer = true, Stretch = 2 },
+                { Text = "B", Action = "F2" },
+                { Text = "Clock", Action = "Time", Width = 184 },
+            ]"#,
+        )
+        .unwrap();
+        let layer = FunctionLayer::with_config(table["Buttons"].clone().try_into().unwrap(), Bar::new(2000, false, 100));
+        assert_eq!(rect_of(&layer, 0), (0.0, 438.0));
+        assert_eq!(rect_of(&layer, 1), (1362.0, 438.0));
+        assert_eq!(rect_of(&layer, 2), (1816.0, 184.0));
+
+        // With a right group they take an even share and the gap takes the rest
+        let table: toml::Table = toml::from_str(
+            r#"Buttons = [
+                { Text = "Clock", Action = "Time", Width = 184, Align = "Right" },
+                { Text = "A", Action = "F1" },
+                { Spacer = true, Stretch = 2 },
+                { Text = "B", Action = "F2" },
+            ]"#,
+        )
+        .unwrap();
+        let layer = FunctionLayer::with_config(table["Buttons"].clone().try_into().unwrap(), Bar::new(2000, false, 100));
+        assert_eq!(rect_of(&layer, 0), (1816.0, 184.0));
+        assert_eq!(rect_of(&layer, 1), (0.0, 387.2));
+        assert_eq!(rect_of(&layer, 2), (1209.0, 387.2));
+
+        // A flexible left group leaves the center group in the middle of the bar
+        let table: toml::Table = toml::from_str(
+            r#"Buttons = [
+                { Text = "A", Action = "F1" },
+                { Text = "Title", Action = "Title", Width = 400, Align = "Center" },
+                { Text = "Clock", Action = "Time", Width = 200, Align = "Right" },
+            ]"#,
+        )
+        .unwrap();
+        let layer = FunctionLayer::with_config(table["Buttons"].clone().try_into().unwrap(), Bar::new(2000, false, 100));
+        assert_eq!(rect_of(&layer, 0), (0.0, 656.0));
+        assert_eq!(rect_of(&layer, 1), (800.0, 400.0));
+        assert_eq!(rect_of(&layer, 2), (1800.0, 200.0));
+
+        // Centered and right flexible buttons take an even share instead of filling
+        let table: toml::Table = toml::from_str(
+            r#"Buttons = [
+                { Text = "Play", Action = "PlayPause", Width = 100 },
+                { Text = "Title", Action = "Title", Align = "Center" },
+                { Text = "Clock", Action = "Time", Align = "Right" },
+            ]"#,
+        )
+        .unwrap();
+        let layer = FunctionLayer::with_config(table["Buttons"].clone().try_into().unwrap(), Bar::new(2000, false, 100));
+        assert_eq!(rect_of(&layer, 1).0, 672.0);
+        assert_eq!(rect_of(&layer, 2).0, 1344.0);
+        assert_eq!(rect_of(&layer, 1).1, rect_of(&layer, 2).1);
+
+        // Hidden buttons leave their room to the others
+        let table: toml::Table = toml::from_str(
+            r#"Buttons = [
+                { Text = "A", Action = "F1" },
+                { Text = "B", Action = "F2", VisibleWhen = "class(kitty)" },
+            ]"#,
+        )
+        .unwrap();
+        let mut layer = FunctionLayer::with_config(table["Buttons"].clone().try_into().unwrap(), Bar::new(2000, false, 100));
+        assert_eq!(layer.button_rect(1), None);
+        assert_eq!(rect_of(&layer, 0), (0.0, 2000.0));
+        let facts = visibility::Facts { class: "kitty".into(), ..Default::default() };
+        assert!(layer.update_visibility(&facts));
+        assert!(!layer.update_visibility(&facts));
+        assert!(rect_of(&layer, 0).1 < 1000.0);
+        assert!(rect_of(&layer, 1).0 > 1000.0);
+    }
+
+    #[test]
+    fn test_key_repeat() {
+        let t0 = std::time::Instant::now();
+        let ms = |ms: u64| t0 + std::time::Duration::from_millis(ms);
+        let config = |repeat: &str| toml::from_str::<RepeatConfig>(repeat).unwrap();
+
+        // 400 ms before the first repeat, then 20 a second
+        let mut repeat = KeyRepeat::new(0, 3, config(""), t0);
+        assert!(!repeat.due(ms(399)));
+        assert!(repeat.due(ms(400)) && !repeat.due(ms(449)));
+        assert!(repeat.due(ms(450)));
+        // A late wakeup keeps the cadence
+        assert!(repeat.due(ms(510)) && !repeat.due(ms(549)) && repeat.due(ms(550)));
+        // A stall fires once, not once per missed repeat
+        assert!(repeat.due(ms(2000)));
+        assert!(!repeat.due(ms(2049)) && repeat.due(ms(2050)));
+
+        assert_eq!(config("Rate = 0").interval(), std::time::Duration::from_secs(1));
+        assert_eq!(config("Rate = 5000").interval(), std::time::Duration::from_millis(1));
+        assert_eq!(config("Delay = 100\nRate = 4").delay(), std::time::Duration::from_millis(100));
+
+        // Lifting the finger, moving to another button or another layer ends it
+        assert!(repeat.held_by(Some(&(0, 3))));
+        assert!(!repeat.held_by(None) && !repeat.held_by(Some(&(0, 4))) && !repeat.held_by(Some(&(1, 3))));
+    }
+
+    #[test]
+    fn test_clock_ticks() {
+        let at = |second, milli| chrono::NaiveTime::from_hms_milli_opt(9, 41, second, milli).unwrap();
+        assert_eq!(ms_to_clock_tick(&at(58, 250), true), 750);
+        assert_eq!(ms_to_clock_tick(&at(58, 0), true), 1000);
+        // A leap second reads as a millisecond past 999 and still waits
+        assert_eq!(ms_to_clock_tick(&at(59, 1500), true), 1);
+        assert_eq!(ms_to_clock_tick(&at(58, 250), false), 2000);
+        assert_eq!(ms_to_clock_tick(&at(0, 0), false), 60000);
+
+        let clock = |format: &str| Button::new_time(ButtonAction::Command("Time".into()), format, None, None).shows_seconds();
+        assert!(clock("%H:%M:%S") && clock("%T") && !clock("%H:%M") && !clock("24hr"));
+    }
+
+    #[test]
+    fn test_world_clock() {
+        let now = chrono::NaiveDate::from_ymd_opt(2024, 1, 15).unwrap().and_hms_opt(12, 0, 0).unwrap().and_utc();
+        let text = |zone: Option<&str>| {
+            let ButtonImage::Time(format, locale, zone) = Button::new_time(ButtonAction::Command("Time".into()), "%H:%M %Z", None, zone).image else {
+                panic!("not a clock");
+            };
+            format_time(&format, locale, zone.as_ref(), now)
+        };
+        assert_eq!(text(Some("Asia/Kolkata")), "17:30 IST");
+        assert_eq!(text(Some("America/New_York")), "07:00 EST");
+        // An unknown zone falls back to local time
+        let local = now.with_timezone(&Local).format("%H:%M %Z").to_string();
+        assert_eq!(text(Some("Mars/Olympus_Mons")), local);
+        assert_eq!(text(None), local);
+    }
+
+    #[test]
+    fn test_keys_held_under_swipes() {
+        let table: toml::Table = toml::from_str(
+            r#"Buttons = [
+                { Text = "Shift", Action = "LeftShift" },
+                { Text = "Up", Action = "Up" },
+                { Text = "Select", Action = ["LeftShift", "Right"] },
+                { Text = "Copy", Action = "KeyCombos_CTRL_C" },
+                { Text = "Esc", Action = "Esc" },
+                { Text = "Files", Action = "Command_Files" },
+            ]"#,
+        )
+        .unwrap();
+        let layer = FunctionLayer::with_config(table["Buttons"].clone().try_into().unwrap(), Bar::new(2000, false, 100));
+        let held: Vec<bool> = layer.buttons.iter().map(|(_, button)| button.only_held_keys()).collect();
+        assert_eq!(held, [true, true, true, false, false, false]);
+    }
+
+    #[test]
+    fn test_pixel_shift_margin() {
+        let table: toml::Table = toml::from_str(
+            r#"Buttons = [
+                { Text = "A", Action = "F1" },
+                { Text = "B", Action = "F2" },
+            ]"#,
+        )
+        .unwrap();
+        let layer = FunctionLayer::with_config(table["Buttons"].clone().try_into().unwrap(), Bar::new(2000, true, 100));
+        // Touches are in bar coordinates, drawn buttons start half the margin in
+        assert_eq!(rect_of(&layer, 0), (0.0, 981.0));
+        assert_eq!(layer.hit(60, 5.0, 30.0, None), None);
+        assert_eq!(layer.hit(60, 15.0, 30.0, None), Some(0));
+        assert_eq!(layer.hit(60, 1995.0, 30.0, None), None);
+    }
+
+    fn rect_of(layer: &FunctionLayer, i: usize) -> (f64, f64) {
+        layer.button_rect(i).unwrap()
+    }
+}
@@ -0,0 +1,353 @@
+use crate::shared_state::SharedState;
+use std::fs;
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+// How long an nl80211 reply may take before the SSID is given up on
+const SSID_QUERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WifiState {
+    pub interface: String,
+    pub connected: bool,
+    pub ssid: Option<String>,
+    /// Link quality in percent, from /proc/net/wireless
+    pub quality: Option<u32>,
+    pub signal_dbm: Option<i32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NetworkState {
+    pub wifi: Option<WifiState>,
+    /// Any interface other than loopback, Wi-Fi included, has a link
+    pub online: bool,
+    /// WireGuard and tun interfaces that are up
+    pub vpns: Vec<String>,
+}
+
+impl NetworkState {
+    /// Short status line, e.g. "HomeNet 72%", "Wi-Fi off" or "Wired", with " VPN" appended
+    /// while a tunnel is up.
+    pub fn describe(&self) -> String {
+        let link = match &self.wifi {
+            Some(wifi) if wifi.connected => match (&wifi.ssid, wifi.quality) {
+                (Some(ssid), Some(quality)) => format!("{} {}%", ssid, quality),
+                (Some(ssid), None) => ssid.clone(),
+                (None, Some(quality)) => format!("Wi-Fi {}%", quality),
+                (None, None) => "Wi-Fi".to_string(),
+            },
+            _ if self.online => "Wired".to_string(),
+            Some(_) => "Wi-Fi off".to_string(),
+            None => "Offline".to_string(),
+        };
+        if self.vpns.is_empty() {
+            link
+        } else {
+            format!("{} VPN", link)
+        }
+    }
+
+    /// Signal bars from 0 to 4 for the icon; wired links show full bars.
+    pub fn bars(&self) -> usize {
+        match &self.wifi {
+            Some(wifi) if wifi.connected => match wifi.quality {
+                Some(q) => (q as usize * 4).div_ceil(100).clamp(1, 4),
+                None => 4,
+            },
+            _ if self.online => 4,
+            _ => 0,
+        }
+    }
+}
+
+// Global network state
+static NETWORK_STATE: std::sync::LazyLock<SharedState<NetworkState>> = std::sync::LazyLock::new(SharedState::default);
+
+// Set while a thread is asking nl80211 for the SSID, so slow replies don't pile up threads
+static SSID_QUERY_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Watches rtnetlink for link and address changes. Its fd goes into the main epoll
+/// set, so the widget updates as soon as the kernel reports a change.
+pub struct NetworkMonitor {
+    fd: OwnedFd,
+}
+
+impl NetworkMonitor {
+    pub fn new() -> io::Result<NetworkMonitor> {
+        let groups = libc::RTMGRP_LINK | libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR;
+        let fd = netlink_socket(libc::NETLINK_ROUTE, groups as u32, libc::SOCK_NONBLOCK)?;
+        refresh();
+        Ok(NetworkMonitor { fd })
+    }
+
+    /// Drains pending notifications and re-reads the state if there were any.
+    pub fn process_events(&self) {
+        let mut buf = [0u8; 8192];
+        let mut received = false;
+        while unsafe { libc::recv(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0) } > 0 {
+            received = true;
+        }
+        if received {
+            refresh();
+        }
+    }
+}
+
+impl AsFd for NetworkMonitor {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+/// Re-reads the network state. Signal strength changes raise no netlink event, so
+/// the main loop calls this now and then while the widget shows. The SSID comes from
+/// nl80211 on a worker thread; until it answers the last known one stays.
+pub fn refresh() {
+    let wireless = fs::read_to_string("/proc/net/wireless").unwrap_or_default();
+    let mut state = read_state(Path::new("/sys/class/net"), &wireless);
+    let known = NETWORK_STATE.current().wifi.and_then(|wifi| wifi.ssid.filter(|_| wifi.connected).map(|ssid| (wifi.interface, ssid)));
+    if let Some(wifi) = state.wifi.as_mut().filter(|wifi| wifi.connected) {
+        wifi.ssid = known.filter(|(interface, _)| *interface == wifi.interface).map(|(_, ssid)| ssid);
+        let ifindex = fs::read_to_string(format!("/sys/class/net/{}/ifindex", wifi.interface))
+            .ok()
+            .and_then(|index| index.trim().parse().ok());
+        if let Some(ifindex) = ifindex {
+            request_ssid(wifi.interface.clone(), ifindex);
+        }
+    }
+    NETWORK_STATE.update(|current| *current = state);
+}
+
+fn request_ssid(interface: String, ifindex: u32) {
+    if SSID_QUERY_RUNNING.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    thread::spawn(move || {
+        let ssid = query_ssid(ifindex);
+        NETWORK_STATE.update(|state| {
+            if let Some(wifi) = state.wifi.as_mut().filter(|wifi| wifi.connected && wifi.interface == interface) {
+                wifi.ssid = ssid;
+            }
+        });
+        SSID_QUERY_RUNNING.store(false, Ordering::Release);
+    });
+}
+
+pub fn current() -> NetworkState {
+    NETWORK_STATE.current()
+}
+
+pub fn wake_fd() -> BorrowedFd<'static> {
+    NETWORK_STATE.wake_fd()
+}
+
+/// True if the network state changed since the last call.
+pub fn take_changed() -> bool {
+    NETWORK_STATE.take_changed()
+}
+
+fn read_state(sys_class_net: &Path, proc_wireless: &str) -> NetworkState {
+    let read = |path: &Path| fs::read_to_string(path).map(|s| s.trim().to_string()).unwrap_or_default();
+    let mut state = NetworkState::default();
+    let Ok(entries) = fs::read_dir(sys_class_net) else {
+        return state;
+    };
+    let mut names: Vec<String> = entries.flatten().map(|e| e.file_name().to_string_lossy().to_string()).collect();
+    names.sort();
+    for name in names.into_iter().filter(|name| name != "lo") {
+        let dir = sys_class_net.join(&name);
+        let operstate = read(&dir.join("operstate"));
+        if name.starts_with("wg") || name.starts_with("tun") {
+            // Tunnels report "unknown"; IFF_UP tells whether they are configured up
+            let flags = u32::from_str_radix(read(&dir.join("flags")).trim_start_matches("0x"), 16).unwrap_or(0);
+            if flags & libc::IFF_UP as u32 != 0 {
+                state.vpns.push(name);
+            }
+            continue;
+        }
+        let connected = operstate == "up";
+        state.online |= connected;
+        if state.wifi.is_none() && (dir.join("wireless").exists() || dir.join("phy80211").exists()) {
+            let (quality, signal_dbm) = wireless_signal(proc_wireless, &name).unzip();
+            state.wifi = Some(WifiState {
+                interface: name,
+                connected,
+                ssid: None,
+                quality,
+                signal_dbm,
+            });
+        }
+    }
+    state
+}
+
+// Quality in percent and level in dBm, from a line like
+// "wlan0: 0000   54.  -56.  -256        0      0      0      0      0        0"
+fn wireless_signal(proc_wireless: &str, interface: &str) -> Option<(u32, i32)> {
+    let line = proc_wireless.lines().find(|line| line.trim_start().starts_with(&format!("{}:", interface)))?;
+    let mut fields = line.split(':').nth(1)?.split_whitespace().skip(1);
+    let quality: f64 = fields.next()?.trim_end_matches('.').parse().ok()?;
+    let level: f64 = fields.next()?.trim_end_matches('.').parse().ok()?;
+    // Drivers scale link quality to 70
+    Some(((quality * 100.0 / 70.0).round().min(100.0) as u32, level as i32))
+}
+
+fn netlink_socket(protocol: i32, groups: u32, flags: i32) -> io::Result<OwnedFd> {
+    let raw = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC | flags, protocol) };
+    if raw < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+    let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+    addr.nl_family = libc::AF_NETLINK as u16;
+    addr.nl_groups = groups;
+    let len = std::mem::size_of::<libc::sockaddr_nl>() as u32;
+    if unsafe { libc::bind(fd.as_raw_fd(), (&addr as *const libc::sockaddr_nl).cast(), len) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fd)
+}
+
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+const NL80211_CMD_GET_INTERFACE: u8 = 5;
+const NL80211_ATTR_IFINDEX: u16 = 3;
+const NL80211_ATTR_SSID: u16 = 52;
+
+// A generic netlink request: netlink header, genl header, then attributes
+fn genl_request(family: u16, cmd: u8, attrs: &[(u16, &[u8])]) -> Vec<u8> {
+    let mut msg = vec![0u8; 16];
+    msg.extend_from_slice(&[cmd, 1, 0, 0]);
+    for (kind, payload) in attrs {
+        msg.extend_from_slice(&((4 + payload.len()) as u16).to_ne_bytes());
+        msg.extend_from_slice(&kind.to_ne_bytes());
+        msg.extend_from_slice(payload);
+        msg.resize(msg.len().next_multiple_of(4), 0);
+    }
+    let len = msg.len() as u32;
+    msg[0..4].copy_from_slice(&len.to_ne_bytes());
+    msg[4..6].copy_from_slice(&family.to_ne_bytes());
+    msg[6..8].copy_from_slice(&(libc::NLM_F_REQUEST as u16).to_ne_bytes());
+    msg[8..12].copy_from_slice(&1u32.to_ne_bytes());
+    msg
+}
+
+// Attributes of the first message in a generic netlink reply
+fn genl_reply_attrs(reply: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut attrs = Vec::new();
+    if reply.len() < 20 {
+        return attrs;
+    }
+    let len = (u32::from_ne_bytes(reply[0..4].try_into().unwrap()) as usize).min(reply.len());
+    let kind = u16::from_ne_bytes(reply[4..6].try_into().unwrap());
+    if kind == libc::NLMSG_ERROR as u16 {
+        return attrs;
+    }
+    let mut pos = 20;
+    while pos + 4 <= len {
+        let attr_len = u16::from_ne_bytes(reply[pos..pos + 2].try_into().unwrap()) as usize;
+        let attr_kind = u16::from_ne_bytes(reply[pos + 2..pos + 4].try_into().unwrap());
+        if attr_len < 4 || pos + attr_len > len {
+            break;
+        }
+        attrs.push((attr_kind & 0x3fff, &reply[pos + 4..pos + attr_len]));
+        pos += attr_len.next_multiple_of(4);
+    }
+    attrs
+}
+
+fn genl_call(fd: &OwnedFd, request: &[u8], reply: &mut [u8]) -> Option<usize> {
+    let sent = unsafe { libc::send(fd.as_raw_fd(), request.as_ptr().cast(), request.len(), 0) };
+    if sent < 0 {
+        return None;
+    }
+    let received = unsafe { libc::recv(fd.as_raw_fd(), reply.as_mut_ptr().cast(), reply.len(), 0) };
+    (received > 0).then_some(received as usize)
+}
+
+// SSID of a connected station interface, asked from nl80211
+fn query_ssid(ifindex: u32) -> Option<String> {
+    let fd = netlink_socket(libc::NETLINK_GENERIC, 0, 0).ok()?;
+    let timeout = libc::timeval { tv_sec: SSID_QUERY_TIMEOUT.as_secs() as libc::time_t, tv_usec: 0 };
+    let len = std::mem::size_of::<libc::timeval>() as u32;
+    if unsafe { libc::setsockopt(fd.as_raw_fd(), libc::SOL_SOCKET, libc::SO_RCVTIMEO, (&timeout as *const libc::timeval).cast(), len) } < 0 {
+        return None;
+    }
+    let mut reply = vec![0u8; 8192];
+
+    let request = genl_request(GENL_ID_CTRL, CTRL_CMD_GETFAMILY, &[(CTRL_ATTR_FAMILY_NAME, b"nl80211\0")]);
+    let len = genl_call(&fd, &request, &mut reply)?;
+    let family = genl_reply_attrs(&reply[..len])
+        .into_iter()
+        .find(|(kind, payload)| *kind == CTRL_ATTR_FAMILY_ID && payload.len() >= 2)
+        .map(|(_, payload)| u16::from_ne_bytes([payload[0], payload[1]]))?;
+
+    let request = genl_request(family, NL80211_CMD_GET_INTERFACE, &[(NL80211_ATTR_IFINDEX, &ifindex.to_ne_bytes())]);
+    let len = genl_call(&fd, &request, &mut reply)?;
+    genl_reply_attrs(&reply[..len])
+        .into_iter()
+        .find(|(kind, _)| *kind == NL80211_ATTR_SSID)
+        .map(|(_, ssid)| String::from_utf8_lossy(ssid).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_network_state() {
+        let root = TempDir::new("net");
+        for (name, operstate, flags) in [("lo", "unknown", "0x9"), ("wlan0", "up", "0x1003"), ("wg0", "unknown", "0x91"), ("tun0", "down", "0x1000")] {
+            root.write(format!("{}/operstate", name), operstate);
+            root.write(format!("{}/flags", name), flags);
+        }
+        fs::create_dir_all(root.join("wlan0/wireless")).unwrap();
+        let wireless = "Inter-| sta-|   Quality        |   Discarded packets\n face | tus | link level noise |  nwid  crypt\n wlan0: 0000   54.  -56.  -256        0      0      0      0      0        0\n";
+
+        let mut state = read_state(&root, wireless);
+        let wifi = state.wifi.clone().unwrap();
+        assert_eq!((wifi.interface.as_str(), wifi.connected), ("wlan0", true));
+        assert_eq!((wifi.quality, wifi.signal_dbm), (Some(77), Some(-56)));
+        assert_eq!(state.vpns, ["wg0"]);
+        assert_eq!(state.bars(), 4);
+        state.wifi.as_mut().unwrap().ssid = Some("HomeNet".into());
+        assert_eq!(state.describe(), "HomeNet 77% VPN");
+
+        // Requests and replies share the attribute layout
+        let request = genl_request(GENL_ID_CTRL, CTRL_CMD_GETFAMILY, &[(CTRL_ATTR_FAMILY_NAME, b"nl80211\0"), (NL80211_ATTR_SSID, b"abcde")]);
+        assert_eq!(request.len() % 4, 0);
+        let attrs = genl_reply_attrs(&request);
+        assert_eq!(attrs, [(CTRL_ATTR_FAMILY_NAME, &b"nl80211\0"[..]), (NL80211_ATTR_SSID, &b"abcde"[..])]);
+    }
+
+    #[test]
+    fn test_network_state_errors() {
+        let root = TempDir::new("net-errors");
+        assert_eq!(read_state(&root.join("missing"), ""), NetworkState::default());
+
+        // Missing files and a garbled /proc/net/wireless leave the signal unknown
+        fs::create_dir_all(root.join("wlan0/phy80211")).unwrap();
+        root.write("wg0/flags", "garbage");
+        let state = read_state(&root, " wlan0: 0000   n/a  -56.\n");
+        let wifi = state.wifi.clone().unwrap();
+        assert_eq!((wifi.connected, wifi.quality, wifi.signal_dbm), (false, None, None));
+        assert!(state.vpns.is_empty() && !state.online);
+        assert_eq!(wireless_signal("wlan0: 0000\n", "wlan0"), None);
+        assert_eq!(wireless_signal(" wlan1: 0000   54.  -56.\n", "wlan0"), None);
+
+        // Truncated and error replies carry no attributes
+        let request = genl_request(GENL_ID_CTRL, CTRL_CMD_GETFAMILY, &[(CTRL_ATTR_FAMILY_NAME, b"nl80211\0")]);
+        assert!(genl_reply_attrs(&request[..12]).is_empty());
+        assert!(genl_reply_attrs(&request[..request.len() - 4]).is_empty());
+        let mut error = request.clone();
+        error[4..6].copy_from_slice(&(libc::NLMSG_ERROR as u16).to_ne_bytes());
+        assert!(genl_reply_attrs(&error).is_empty());
+    }
+}
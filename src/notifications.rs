@@ -0,0 +1,707 @@
+use crate::shared_state::{keep_running, SharedState};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::iter;
+use std::mem;
+use std::os::fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::ptr;
+use std::sync::mpsc;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+use zbus::blocking::fdo::{DBusProxy, MonitoringProxy};
+use zbus::blocking::{Connection, MessageIterator, Proxy};
+use zbus::message::Type;
+use zbus::proxy::CacheProperties;
+use zbus::zvariant::OwnedValue;
+use zbus::{MatchRule, Message};
+
+// The freedesktop interface has no unread count, do not disturb or listing, so each
+// daemon is asked through its own interface on the desktop user's session bus
+const NOTIFICATIONS: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+const MAKO_INTERFACE: &str = "fr.emersion.Mako";
+const MAKO_PATH: &str = "/fr/emersion/Mako";
+const MAKO_DND_MODE: &str = "do-not-disturb";
+const DUNST_INTERFACE: &str = "org.dunstproject.cmd0";
+const SWAYNC: &str = "org.erikreider.swaync.cc";
+const SWAYNC_PATH: &str = "/org/erikreider/swaync/cc";
+
+const MAX_RECENT: usize = 10;
+// Daemons that keep notifications until the user acts on them never close some
+const MAX_SEEN: usize = 100;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub id: u32,
+    pub app: String,
+    pub summary: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NotificationState {
+    pub connected: bool,
+    pub unread: u32,
+    pub dnd: bool,
+    /// Newest first, as far as the daemon lists them
+    pub recent: Vec<Notification>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Request {
+    Invoke(u32),
+    Dismiss(u32),
+    ToggleDnd,
+}
+
+enum Event {
+    Request(Request),
+    // Whatever the monitor connection saw, or why it stopped
+    Bus(zbus::Result<Message>),
+}
+
+// Global notification state
+static NOTIFICATION_STATE: std::sync::LazyLock<SharedState<NotificationState>> =
+    std::sync::LazyLock::new(SharedState::default);
+
+// Where taps go while a session is up
+static EVENTS: Mutex<Option<mpsc::Sender<Event>>> = Mutex::new(None);
+
+// Started by the first button that shows notifications; nobody connects before that
+static MONITOR: OnceLock<NotificationMonitor> = OnceLock::new();
+
+/// Follows the desktop user's notification daemon, asking it again whenever something
+/// goes to or comes from it on the session bus.
+pub struct NotificationMonitor {
+    _handle: thread::JoinHandle<()>,
+}
+
+impl NotificationMonitor {
+    pub fn new() -> Self {
+        let handle = thread::spawn(move || {
+            Self::monitor_loop();
+        });
+
+        NotificationMonitor { _handle: handle }
+    }
+
+    fn monitor_loop() {
+        keep_running("Notification daemon", Duration::from_secs(5), session, || {
+            NOTIFICATION_STATE.update(|state| *state = NotificationState::default())
+        });
+    }
+}
+
+// Public API
+pub fn watch() {
+    MONITOR.get_or_init(NotificationMonitor::new);
+}
+
+pub fn current() -> NotificationState {
+    NOTIFICATION_STATE.current()
+}
+
+pub fn wake_fd() -> BorrowedFd<'static> {
+    NOTIFICATION_STATE.wake_fd()
+}
+
+/// Clears the wake fd; true if the state changed since the last call.
+pub fn take_changed() -> bool {
+    NOTIFICATION_STATE.take_changed()
+}
+
+/// Runs the default action of the `slot`th most recent notification, counting from 1.
+pub fn invoke(slot: usize) {
+    request_for(slot, Request::Invoke);
+}
+
+pub fn dismiss(slot: usize) {
+    request_for(slot, Request::Dismiss);
+}
+
+pub fn toggle_dnd() {
+    send(Request::ToggleDnd);
+}
+
+fn request_for(slot: usize, request: impl FnOnce(u32) -> Request) {
+    if let Some(id) = slot.checked_sub(1).and_then(|i| current().recent.get(i).map(|n| n.id)) {
+        send(request(id));
+    }
+}
+
+fn send(request: Request) {
+    if let Some(events) = EVENTS.lock().ok().and_then(|events| events.clone()) {
+        let _ = events.send(Event::Request(request));
+    }
+}
+
+fn session() -> io::Result<()> {
+    let env = crate::user_cache::get_cached_user_environment().ok_or_else(|| io::Error::other("no desktop user"))?;
+    let gid = fs::metadata(&env.runtime_dir)?.gid();
+    follow(&Path::new(&env.runtime_dir).join("bus"), env.uid, gid).map_err(io::Error::other)
+}
+
+fn connect(bus: &Path, uid: u32, gid: u32) -> zbus::Result<Connection> {
+    let stream = connect_as(bus, uid, gid)?;
+    zbus::blocking::connection::Builder::async_io_unix_stream(stream).user_id(uid).build()
+}
+
+// The session bus admits only its owner, judged by the credentials the kernel records
+// when the socket connects. A forked child takes on the user's ids, connects and hands
+// the socket back, so tiny-dfr itself never changes ids.
+fn connect_as(bus: &Path, uid: u32, gid: u32) -> io::Result<UnixStream> {
+    if unsafe { libc::geteuid() } == uid {
+        return UnixStream::connect(bus);
+    }
+    let mut address: libc::sockaddr_un = unsafe { mem::zeroed() };
+    address.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    let path = bus.as_os_str().as_bytes();
+    if path.len() >= address.sun_path.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "session bus path too long"));
+    }
+    for (to, from) in address.sun_path.iter_mut().zip(path) {
+        *to = *from as libc::c_char;
+    }
+    let (parent, child) = UnixStream::pair()?;
+
+    let pid = unsafe { libc::fork() };
+    if pid < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if pid == 0 {
+        // Only async-signal-safe calls until _exit; the child shares the parent's memory
+        // as it was, locks held by other threads included
+        unsafe {
+            let mut fd = -1;
+            let ok = libc::setgroups(0, ptr::null()) == 0
+                && libc::setgid(gid) == 0
+                && libc::setuid(uid) == 0
+                && {
+                    fd = libc::socket(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0);
+                    fd >= 0
+                }
+                && libc::connect(fd, (&raw const address).cast(), mem::size_of_val(&address) as libc::socklen_t) == 0;
+            let errno = if ok { 0 } else { io::Error::last_os_error().raw_os_error().unwrap_or(libc::EIO) };
+            send_fd(child.as_raw_fd(), errno, if ok { fd } else { -1 });
+            libc::_exit(0);
+        }
+    }
+    drop(child);
+    let result = receive_fd(&parent);
+    unsafe { libc::waitpid(pid, ptr::null_mut(), 0) };
+    result.map(UnixStream::from)
+}
+
+// Sends `errno` and, when it is zero, `fd` along with it
+unsafe fn send_fd(socket: RawFd, errno: i32, fd: RawFd) {
+    let mut data = errno;
+    let mut iov = libc::iovec { iov_base: (&raw mut data).cast(), iov_len: mem::size_of::<i32>() };
+    let mut control = [0u64; 4];
+    let mut msg: libc::msghdr = mem::zeroed();
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    if fd >= 0 {
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<RawFd>() as u32) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast(), fd);
+    }
+    libc::sendmsg(socket, &msg, 0);
+}
+
+fn receive_fd(socket: &UnixStream) -> io::Result<OwnedFd> {
+    let mut errno = 0i32;
+    let mut iov = libc::iovec { iov_base: (&raw mut errno).cast(), iov_len: mem::size_of::<i32>() };
+    let mut control = [0u64; 4];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+    let received = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if received != mem::size_of::<i32>() as isize {
+        return Err(io::Error::other("connecting to the session bus as the desktop user failed"));
+    }
+    if errno != 0 {
+        return Err(io::Error::from_raw_os_error(errno));
+    }
+    let cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    if cmsg.is_null() || unsafe { (*cmsg).cmsg_type } != libc::SCM_RIGHTS {
+        return Err(io::Error::other("no socket came back from the session bus connection"));
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast())) })
+}
+
+fn follow(bus: &Path, uid: u32, gid: u32) -> zbus::Result<()> {
+    let conn = connect(bus, uid, gid)?;
+    // A monitor connection sees the calls other clients make, which is the only way to
+    // hear of new notifications, but can send nothing itself
+    let monitor = connect(bus, uid, gid)?;
+    let messages = MessageIterator::from(&monitor);
+    MonitoringProxy::new(&monitor)?.become_monitor(&monitored()?, 0)?;
+
+    let (events, received) = mpsc::channel();
+    let bus_events = events.clone();
+    thread::spawn(move || {
+        for message in messages {
+            if bus_events.send(Event::Bus(message)).is_err() {
+                return;
+            }
+        }
+        let _ = bus_events.send(Event::Bus(Err(zbus::Error::Failure("session bus closed the connection".into()))));
+    });
+    if let Ok(mut sender) = EVENTS.lock() {
+        *sender = Some(events);
+    }
+
+    let result = serve(&conn, &received);
+    // Ends the reader thread along with the connection
+    let _ = monitor.close();
+    result
+}
+
+// Notifications being sent, everything the daemon sends, and its name changing hands.
+// A rule can only name a unique destination, so calls are told apart by interface.
+fn monitored() -> zbus::Result<Vec<MatchRule<'static>>> {
+    Ok(vec![
+        MatchRule::builder().msg_type(Type::MethodCall).interface(NOTIFICATIONS)?.build(),
+        MatchRule::builder().sender(NOTIFICATIONS)?.build(),
+        MatchRule::builder()
+            .msg_type(Type::Signal)
+            .sender("org.freedesktop.DBus")?
+            .member("NameOwnerChanged")?
+            .arg(0, NOTIFICATIONS)?
+            .build(),
+    ])
+}
+
+fn serve(conn: &Connection, events: &mpsc::Receiver<Event>) -> zbus::Result<()> {
+    let own_name = conn.unique_name().map(|name| name.to_string());
+    let mut daemon = None;
+    let mut seen = Seen::default();
+    let mut stale = true;
+    loop {
+        if std::mem::take(&mut stale) {
+            // Asking a name nobody owns would start whichever daemon D-Bus can activate
+            if daemon.is_none() && DBusProxy::new(conn)?.name_has_owner(NOTIFICATIONS.try_into()?)? {
+                let found = Daemon::detect(conn)?;
+                match &found {
+                    Daemon::Other(name) => println!("Notification daemon {} offers no unread count or do not disturb", name),
+                    found => println!("Following notification daemon {:?}", found),
+                }
+                daemon = Some(found);
+            }
+            let state = match &daemon {
+                Some(daemon) => daemon.fetch(conn, &seen)?,
+                None => NotificationState::default(),
+            };
+            NOTIFICATION_STATE.update(|current| *current = state);
+        }
+
+        let first = events.recv().map_err(|_| zbus::Error::Failure("monitor thread gone".into()))?;
+        for event in iter::once(first).chain(events.try_iter()) {
+            match event {
+                Event::Request(request) => {
+                    // A failed request, such as invoking an action a notification lacks, only gets logged
+                    let result = match &daemon {
+                        Some(daemon) => daemon.perform(conn, &seen, request),
+                        None => Err(zbus::Error::Failure("no notification daemon running".into())),
+                    };
+                    if let Err(e) = result {
+                        eprintln!("Notification request {:?} failed: {}", request, e);
+                    }
+                    stale = true;
+                }
+                Event::Bus(message) => {
+                    let message = message?;
+                    let header = message.header();
+                    // The replies to our own questions change nothing
+                    let own = own_name.as_deref();
+                    if header.sender().map(|name| name.as_str()) == own || header.destination().map(|name| name.as_str()) == own {
+                        continue;
+                    }
+                    if header.member().is_some_and(|member| member == "NameOwnerChanged") {
+                        daemon = None;
+                        seen = Seen::default();
+                    }
+                    seen.observe(&message);
+                    stale = true;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Daemon {
+    Mako,
+    Dunst,
+    Swaync,
+    // Shows notifications but offers nothing to follow
+    Other(String),
+}
+
+impl Daemon {
+    fn detect(conn: &Connection) -> zbus::Result<Daemon> {
+        let (name, _, _, _): (String, String, String, String) =
+            proxy(conn, NOTIFICATIONS, NOTIFICATIONS_PATH, NOTIFICATIONS)?.call("GetServerInformation", &())?;
+        Ok(match name.as_str() {
+            "mako" => Daemon::Mako,
+            "dunst" => Daemon::Dunst,
+            "SwayNotificationCenter" => Daemon::Swaync,
+            _ => Daemon::Other(name),
+        })
+    }
+
+    fn fetch(&self, conn: &Connection, seen: &Seen) -> zbus::Result<NotificationState> {
+        let mut state = NotificationState { connected: true, recent: seen.recent(), ..Default::default() };
+        match self {
+            Daemon::Mako => {
+                let mako = proxy(conn, NOTIFICATIONS, MAKO_PATH, MAKO_INTERFACE)?;
+                let list: Vec<HashMap<String, OwnedValue>> = mako.call("ListNotifications", &())?;
+                state.recent = parse_notifications(&list);
+                state.unread = state.recent.len() as u32;
+                state.dnd = mako_modes(conn)?.iter().any(|mode| mode == MAKO_DND_MODE);
+            }
+            Daemon::Dunst => {
+                let dunst = proxy(conn, NOTIFICATIONS, NOTIFICATIONS_PATH, DUNST_INTERFACE)?;
+                state.unread = dunst.get_property::<u32>("displayedLength")? + dunst.get_property::<u32>("waitingLength")?;
+                state.dnd = dunst.get_property("paused")?;
+            }
+            Daemon::Swaync => {
+                let swaync = proxy(conn, SWAYNC, SWAYNC_PATH, SWAYNC)?;
+                state.unread = swaync.call("NotificationCount", &())?;
+                state.dnd = swaync.call("GetDnd", &())?;
+            }
+            Daemon::Other(_) => state.unread = seen.open.len() as u32,
+        }
+        state.recent.truncate(MAX_RECENT);
+        Ok(state)
+    }
+
+    fn perform(&self, conn: &Connection, seen: &Seen, request: Request) -> zbus::Result<()> {
+        match (self, request) {
+            (Daemon::Mako, Request::Invoke(id)) => {
+                proxy(conn, NOTIFICATIONS, MAKO_PATH, MAKO_INTERFACE)?.call_method("InvokeAction", &(id, "default"))?;
+            }
+            (Daemon::Dunst, Request::Invoke(id)) => {
+                let position = seen.dunst_position(id).ok_or_else(|| zbus::Error::Failure(format!("notification {} is not on screen", id)))?;
+                proxy(conn, NOTIFICATIONS, NOTIFICATIONS_PATH, DUNST_INTERFACE)?.call_method("NotificationAction", &(position,))?;
+            }
+            (_, Request::Dismiss(id)) => {
+                proxy(conn, NOTIFICATIONS, NOTIFICATIONS_PATH, NOTIFICATIONS)?.call_method("CloseNotification", &(id,))?;
+            }
+            (Daemon::Mako, Request::ToggleDnd) => {
+                let mut modes = mako_modes(conn)?;
+                if modes.iter().any(|mode| mode == MAKO_DND_MODE) {
+                    modes.retain(|mode| mode != MAKO_DND_MODE);
+                } else {
+                    modes.push(MAKO_DND_MODE.to_string());
+                }
+                proxy(conn, NOTIFICATIONS, MAKO_PATH, MAKO_INTERFACE)?.call_method("SetModes", &(modes,))?;
+            }
+            (Daemon::Dunst, Request::ToggleDnd) => {
+                let dunst = proxy(conn, NOTIFICATIONS, NOTIFICATIONS_PATH, DUNST_INTERFACE)?;
+                let paused: bool = dunst.get_property("paused")?;
+                dunst.set_property("paused", !paused)?;
+            }
+            (Daemon::Swaync, Request::ToggleDnd) => {
+                proxy(conn, SWAYNC, SWAYNC_PATH, SWAYNC)?.call_method("ToggleDnd", &())?;
+            }
+            (daemon, request) => {
+                return Err(zbus::Error::Failure(format!("{:?} cannot do {:?}", daemon, request)));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Properties are asked for each time; a cache would subscribe to changes the monitor sees anyway
+fn proxy<'a>(conn: &Connection, destination: &'a str, path: &'a str, interface: &'a str) -> zbus::Result<Proxy<'a>> {
+    zbus::blocking::proxy::Builder::new(conn)
+        .destination(destination)?
+        .path(path)?
+        .interface(interface)?
+        .cache_properties(CacheProperties::No)
+        .build()
+}
+
+fn mako_modes(conn: &Connection) -> zbus::Result<Vec<String>> {
+    proxy(conn, NOTIFICATIONS, MAKO_PATH, MAKO_INTERFACE)?.call("ListModes", &())
+}
+
+// mako's aa{sv} list of the notifications on screen
+fn parse_notifications(list: &[HashMap<String, OwnedValue>]) -> Vec<Notification> {
+    list.iter()
+        .filter_map(|entry| {
+            let text = |key: &str| entry.get(key).and_then(|v| <&str>::try_from(&**v).ok()).unwrap_or_default().to_string();
+            Some(Notification {
+                id: u32::try_from(&**entry.get("id")?).ok()?,
+                app: text("app-name"),
+                summary: text("summary"),
+            })
+        })
+        .collect()
+}
+
+// A notification and the urgency dunst orders its screen by
+#[derive(Debug, Clone, PartialEq)]
+struct Shown {
+    notification: Notification,
+    urgency: u8,
+}
+
+/// The notifications the monitor saw sent and not yet closed, for daemons that cannot
+/// list their own. Those sent before tiny-dfr started are not known.
+#[derive(Default)]
+struct Seen {
+    // Notify calls waiting for the reply that carries their id, by sender and serial
+    pending: HashMap<(String, u32), Shown>,
+    // Newest first
+    open: Vec<Shown>,
+}
+
+impl Seen {
+    fn observe(&mut self, message: &Message) {
+        let header = message.header();
+        let member = header.member().map(|member| member.as_str());
+        match message.message_type() {
+            Type::MethodCall if member == Some("Notify") => {
+                type Notify = (String, u32, String, String, String, Vec<String>, HashMap<String, OwnedValue>, i32);
+                let (Ok((app, _, _, summary, _, _, hints, _)), Some(sender)) = (message.body().deserialize::<Notify>(), header.sender()) else {
+                    return;
+                };
+                let urgency = hints.get("urgency").and_then(|urgency| u8::try_from(&**urgency).ok()).unwrap_or(1);
+                let shown = Shown { notification: Notification { id: 0, app, summary }, urgency };
+                self.pending.insert((sender.to_string(), header.primary().serial_num().get()), shown);
+            }
+            Type::MethodReturn | Type::Error => {
+                let (Some(destination), Some(serial)) = (header.destination(), header.reply_serial()) else {
+                    return;
+                };
+                let Some(mut shown) = self.pending.remove(&(destination.to_string(), serial.get())) else {
+                    return;
+                };
+                if let Ok(id) = message.body().deserialize::<u32>() {
+                    // A notification replacing another keeps its id
+                    self.close(id);
+                    shown.notification.id = id;
+                    self.open.insert(0, shown);
+                    self.open.truncate(MAX_SEEN);
+                }
+            }
+            Type::Signal if member == Some("NotificationClosed") => {
+                if let Ok((id, _reason)) = message.body().deserialize::<(u32, u32)>() {
+                    self.close(id);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn close(&mut self, id: u32) {
+        self.open.retain(|shown| shown.notification.id != id);
+    }
+
+    fn recent(&self) -> Vec<Notification> {
+        self.open.iter().map(|shown| shown.notification.clone()).collect()
+    }
+
+    // dunst runs actions by position on screen, where its default sort puts the most
+    // urgent first and the oldest first among equals
+    fn dunst_position(&self, id: u32) -> Option<u32> {
+        let key = |shown: &Shown| (Reverse(shown.urgency), shown.notification.id);
+        let target = key(self.open.iter().find(|shown| shown.notification.id == id)?);
+        Some(self.open.iter().filter(|shown| key(shown) < target).count() as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::os::unix::net::UnixListener;
+    use std::sync::Arc;
+    use zbus::zvariant::Value;
+
+    #[derive(Default)]
+    struct Calls {
+        invoked: Vec<(u32, String)>,
+        closed: Vec<u32>,
+    }
+
+    struct FakeNotifications(Arc<Mutex<Calls>>);
+
+    #[zbus::interface(name = "org.freedesktop.Notifications")]
+    impl FakeNotifications {
+        fn get_server_information(&self) -> (String, String, String, String) {
+            ("mako".into(), "emersion".into(), "1.9".into(), "1.2".into())
+        }
+
+        fn close_notification(&self, id: u32) {
+            self.0.lock().unwrap().closed.push(id);
+        }
+    }
+
+    struct FakeMako {
+        calls: Arc<Mutex<Calls>>,
+        modes: Vec<String>,
+    }
+
+    #[zbus::interface(name = "fr.emersion.Mako")]
+    impl FakeMako {
+        fn list_notifications(&self) -> Vec<HashMap<String, OwnedValue>> {
+            let closed = self.calls.lock().unwrap().closed.clone();
+            [(12u32, "mail", "3 new messages"), (9, "chat", "Lunch?")]
+                .into_iter()
+                .filter(|(id, _, _)| !closed.contains(id))
+                .map(|(id, app, summary)| {
+                    HashMap::from([
+                        ("id".to_string(), OwnedValue::from(id)),
+                        ("app-name".to_string(), Value::from(app).try_into().unwrap()),
+                        ("summary".to_string(), Value::from(summary).try_into().unwrap()),
+                    ])
+                })
+                .collect()
+        }
+
+        fn list_modes(&self) -> Vec<String> {
+            self.modes.clone()
+        }
+
+        fn set_modes(&mut self, modes: Vec<String>) {
+            self.modes = modes;
+        }
+
+        fn invoke_action(&self, id: u32, action: String) {
+            self.calls.lock().unwrap().invoked.push((id, action));
+        }
+    }
+
+    // mako on the other end of a socket in `dir`, as peers without a bus in between;
+    // returns the client's connection and the daemon's
+    fn fake_mako(dir: &Path, calls: Arc<Mutex<Calls>>) -> (Connection, Connection) {
+        let path = dir.join("bus");
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            zbus::blocking::connection::Builder::async_io_unix_stream(stream)
+                .server(zbus::Guid::generate())
+                .unwrap()
+                .p2p()
+                .serve_at(NOTIFICATIONS_PATH, FakeNotifications(calls.clone()))
+                .unwrap()
+                .serve_at(MAKO_PATH, FakeMako { calls, modes: vec!["default".into()] })
+                .unwrap()
+                .build()
+                .unwrap()
+        });
+        let address = format!("unix:path={}", path.display());
+        let client = zbus::blocking::connection::Builder::address(address.as_str()).unwrap().p2p().build().unwrap();
+        (client, server.join().unwrap())
+    }
+
+    #[test]
+    fn test_notification_daemon() {
+        let dir = TempDir::new("bus");
+        let calls = Arc::new(Mutex::new(Calls::default()));
+        let (conn, _daemon) = fake_mako(&dir, calls.clone());
+
+        let daemon = Daemon::detect(&conn).unwrap();
+        assert_eq!(daemon, Daemon::Mako);
+        let seen = Seen::default();
+        let state = daemon.fetch(&conn, &seen).unwrap();
+        assert_eq!((state.connected, state.unread, state.dnd), (true, 2, false));
+        assert_eq!(state.recent[0], Notification { id: 12, app: "mail".into(), summary: "3 new messages".into() });
+
+        daemon.perform(&conn, &seen, Request::ToggleDnd).unwrap();
+        assert!(daemon.fetch(&conn, &seen).unwrap().dnd);
+        daemon.perform(&conn, &seen, Request::ToggleDnd).unwrap();
+        assert!(!daemon.fetch(&conn, &seen).unwrap().dnd);
+
+        daemon.perform(&conn, &seen, Request::Invoke(12)).unwrap();
+        daemon.perform(&conn, &seen, Request::Dismiss(12)).unwrap();
+        assert_eq!(calls.lock().unwrap().invoked, [(12, "default".to_string())]);
+        assert_eq!(calls.lock().unwrap().closed, [12]);
+        assert_eq!(daemon.fetch(&conn, &seen).unwrap().recent.len(), 1);
+
+        // The fake is no dunst: asking it for dunst's properties fails rather than
+        // reading as nothing unread
+        assert!(Daemon::Dunst.fetch(&conn, &seen).is_err());
+        let error = Daemon::Swaync.perform(&conn, &seen, Request::Invoke(9)).unwrap_err();
+        assert!(error.to_string().contains("cannot do"));
+    }
+
+    fn notify(sender: &str, app: &str, summary: &str, urgency: u8) -> Message {
+        let hints = HashMap::from([("urgency", Value::U8(urgency))]);
+        Message::method_call(NOTIFICATIONS_PATH, "Notify")
+            .unwrap()
+            .interface(NOTIFICATIONS)
+            .unwrap()
+            .sender(sender)
+            .unwrap()
+            .destination(NOTIFICATIONS)
+            .unwrap()
+            .build(&(app, 0u32, "", summary, "", Vec::<&str>::new(), hints, -1i32))
+            .unwrap()
+    }
+
+    fn reply(call: &Message, id: u32) -> Message {
+        Message::method_return(&call.header()).unwrap().sender(":1.2").unwrap().build(&(id,)).unwrap()
+    }
+
+    #[test]
+    fn test_seen_notifications() {
+        let mut seen = Seen::default();
+        let mail = notify(":1.10", "mail", "3 new messages", 1);
+        let alarm = notify(":1.11", "clock", "Wake up", 2);
+        let chat = notify(":1.12", "chat", "Lunch?", 1);
+        for message in [&mail, &alarm, &chat] {
+            seen.observe(message);
+        }
+        // Nothing is shown before the daemon answers with the id
+        assert!(seen.recent().is_empty());
+        seen.observe(&reply(&chat, 7));
+        seen.observe(&reply(&alarm, 6));
+        seen.observe(&reply(&mail, 5));
+        let ids: Vec<u32> = seen.recent().iter().map(|n| n.id).collect();
+        assert_eq!(ids, [5, 6, 7]);
+        assert_eq!(seen.recent()[0], Notification { id: 5, app: "mail".into(), summary: "3 new messages".into() });
+
+        // The critical alarm tops dunst's screen, then the oldest of the rest
+        assert_eq!([6, 5, 7].map(|id| seen.dunst_position(id)), [Some(0), Some(1), Some(2)]);
+        assert_eq!(seen.dunst_position(99), None);
+
+        let closed = Message::signal(NOTIFICATIONS_PATH, NOTIFICATIONS, "NotificationClosed")
+            .unwrap()
+            .sender(":1.2")
+            .unwrap()
+            .build(&(6u32, 2u32))
+            .unwrap();
+        seen.observe(&closed);
+        assert_eq!(seen.dunst_position(7), Some(1));
+
+        // A replacement keeps its id and moves to the front
+        let update = notify(":1.12", "chat", "Lunch at noon?", 1);
+        seen.observe(&update);
+        seen.observe(&reply(&update, 7));
+        assert_eq!(seen.recent().len(), 2);
+        assert_eq!(seen.recent()[0].summary, "Lunch at noon?");
+
+        // Replies to calls it never saw, and garbled calls, are left alone
+        seen.observe(&reply(&mail, 8));
+        let garbled = Message::method_call(NOTIFICATIONS_PATH, "Notify").unwrap().sender(":1.13").unwrap().build(&("only an app",)).unwrap();
+        seen.observe(&garbled);
+        assert_eq!(seen.recent().len(), 2);
+        assert!(seen.pending.is_empty());
+    }
+}
@@ -0,0 +1,262 @@
+use crate::config::ResourceKind;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Samples kept for the graph style, one per second
+pub const HISTORY_LEN: usize = 60;
+
+/// What a resource widget shows: a kind, and for temperatures an optional sensor pattern.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Source {
+    pub kind: ResourceKind,
+    pub sensor: Option<String>,
+}
+
+impl Source {
+    pub fn label(&self) -> &'static str {
+        match self.kind {
+            ResourceKind::Cpu => "CPU",
+            ResourceKind::Memory => "MEM",
+            ResourceKind::Load => "LOAD",
+            ResourceKind::Temperature => "TEMP",
+        }
+    }
+
+    pub fn format(&self, value: f64) -> String {
+        match self.kind {
+            ResourceKind::Cpu | ResourceKind::Memory => format!("{:.0}%", value),
+            ResourceKind::Load => format!("{:.2}", value),
+            ResourceKind::Temperature => format!("{:.0}°C", value),
+        }
+    }
+
+    /// How full a bar or graph is for `value`, from 0 to 1.
+    pub fn fraction(&self, value: f64) -> f64 {
+        let full = match self.kind {
+            ResourceKind::Cpu | ResourceKind::Memory => 100.0,
+            ResourceKind::Load => thread::available_parallelism().map_or(1, |n| n.get()) as f64,
+            ResourceKind::Temperature => 100.0,
+        };
+        (value / full).clamp(0.0, 1.0)
+    }
+}
+
+#[derive(Default)]
+struct ResourceState {
+    histories: HashMap<Source, VecDeque<f64>>,
+    updated: HashSet<Source>,
+}
+
+// Global resource samples
+static RESOURCE_STATE: std::sync::LazyLock<Arc<Mutex<ResourceState>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(ResourceState::default())));
+
+pub struct ResourceMonitor {
+    _handle: thread::JoinHandle<()>,
+}
+
+impl ResourceMonitor {
+    pub fn new() -> Self {
+        let handle = thread::spawn(move || {
+            Self::monitor_loop();
+        });
+
+        ResourceMonitor { _handle: handle }
+    }
+
+    fn monitor_loop() {
+        let mut last_cpu: Option<(u64, u64)> = None;
+        loop {
+            let sources: Vec<Source> = RESOURCE_STATE
+                .lock()
+                .map(|state| state.histories.keys().cloned().collect())
+                .unwrap_or_default();
+
+            // CPU usage is the busy share of the jiffies since the last sample
+            let cpu_times = fs::read_to_string("/proc/stat").ok().and_then(|s| cpu_times(&s));
+            let cpu = match (last_cpu, cpu_times) {
+                (Some((idle0, total0)), Some((idle1, total1))) if total1 > total0 => {
+                    Some(100.0 * (1.0 - (idle1 - idle0) as f64 / (total1 - total0) as f64))
+                }
+                _ => None,
+            };
+            last_cpu = cpu_times;
+
+            for source in sources {
+                let value = match source.kind {
+                    ResourceKind::Cpu => cpu,
+                    ResourceKind::Memory => fs::read_to_string("/proc/meminfo").ok().and_then(|s| memory_used(&s)),
+                    ResourceKind::Load => fs::read_to_string("/proc/loadavg").ok().and_then(|s| load_average(&s)),
+                    ResourceKind::Temperature => temperature(Path::new("/sys/class"), source.sensor.as_deref()),
+                };
+                let Some(value) = value else {
+                    continue;
+                };
+                if let Ok(mut state) = RESOURCE_STATE.lock() {
+                    if let Some(history) = state.histories.get_mut(&source) {
+                        if history.len() == HISTORY_LEN {
+                            history.pop_front();
+                        }
+                        history.push_back(value);
+                        state.updated.insert(source);
+                    }
+                }
+            }
+
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+}
+
+// Public API
+pub fn watch(source: &Source) {
+    if let Ok(mut state) = RESOURCE_STATE.lock() {
+        state.histories.entry(source.clone()).or_default();
+    }
+}
+
+/// Samples of `source`, oldest first.
+pub fn history(source: &Source) -> Vec<f64> {
+    RESOURCE_STATE
+        .lock()
+        .ok()
+        .and_then(|state| state.histories.get(source).map(|h| h.iter().copied().collect()))
+        .unwrap_or_default()
+}
+
+/// Sources sampled since the last call.
+pub fn take_updated() -> HashSet<Source> {
+    if let Ok(mut state) = RESOURCE_STATE.lock() {
+        std::mem::take(&mut state.updated)
+    } else {
+        HashSet::new()
+    }
+}
+
+// Idle and total jiffies from the aggregate line of /proc/stat
+fn cpu_times(stat: &str) -> Option<(u64, u64)> {
+    let fields: Vec<u64> = stat
+        .lines()
+        .find(|line| line.starts_with("cpu "))?
+        .split_whitespace()
+        .skip(1)
+        .filter_map(|f| f.parse().ok())
+        .collect();
+    // user nice system idle iowait irq softirq steal; guest time is already in user
+    let idle = fields.get(3)? + fields.get(4).unwrap_or(&0);
+    let total = fields.iter().take(8).sum();
+    Some((idle, total))
+}
+
+// Used memory in percent, counting reclaimable cache as free
+fn memory_used(meminfo: &str) -> Option<f64> {
+    let field = |name: &str| -> Option<f64> {
+        let line = meminfo.lines().find(|line| line.starts_with(name))?;
+        line[name.len()..].split_whitespace().next()?.parse().ok()
+    };
+    let total = field("MemTotal:")?;
+    let available = field("MemAvailable:")?;
+    (total > 0.0).then(|| 100.0 * (total - available) / total)
+}
+
+fn load_average(loadavg: &str) -> Option<f64> {
+    loadavg.split_whitespace().next()?.parse().ok()
+}
+
+// Sensor readings in °C named "<hwmon name>/<label>" (e.g. "applesmc/TC0P" or
+// "coretemp/Package id 0") and "thermal/<zone type>"
+fn temperatures(sys_class: &Path) -> Vec<(String, f64)> {
+    let read = |path: &Path| fs::read_to_string(path).ok().map(|s| s.trim().to_string());
+    let millidegrees = |path: &Path| read(path).and_then(|s| s.parse::<f64>().ok()).map(|m| m / 1000.0);
+    let mut readings = Vec::new();
+    if let Ok(entries) = fs::read_dir(sys_class.join("hwmon")) {
+        for entry in entries.flatten() {
+            let dir = entry.path();
+            let chip = read(&dir.join("name")).unwrap_or_default();
+            let Ok(files) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for file in files.flatten() {
+                let name = file.file_name().to_string_lossy().to_string();
+                let Some(sensor) = name.strip_suffix("_input").filter(|s| s.starts_with("temp")) else {
+                    continue;
+                };
+                let label = read(&dir.join(format!("{}_label", sensor))).unwrap_or_else(|| sensor.to_string());
+                if let Some(value) = millidegrees(&file.path()) {
+                    readings.push((format!("{}/{}", chip, label), value));
+                }
+            }
+        }
+    }
+    if let Ok(entries) = fs::read_dir(sys_class.join("thermal")) {
+        for entry in entries.flatten() {
+            let dir = entry.path();
+            if !entry.file_name().to_string_lossy().starts_with("thermal_zone") {
+                continue;
+            }
+            if let (Some(zone), Some(value)) = (read(&dir.join("type")), millidegrees(&dir.join("temp"))) {
+                readings.push((format!("thermal/{}", zone), value));
+            }
+        }
+    }
+    readings
+}
+
+// The hottest sensor matching `sensor`, or the hottest of all
+fn temperature(sys_class: &Path, sensor: Option<&str>) -> Option<f64> {
+    temperatures(sys_class)
+        .into_iter()
+        .filter(|(name, _)| sensor.is_none_or(|pattern| crate::config::wildcard_match(pattern, name)))
+        .map(|(_, value)| value)
+        .reduce(f64::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_resource_parsing() {
+        let stat = "cpu  100 0 50 800 50 0 0 0 0 0\ncpu0 50 0 25 400 25 0 0 0 0 0\n";
+        assert_eq!(cpu_times(stat), Some((850, 1000)));
+        let meminfo = "MemTotal:       16000000 kB\nMemFree:         1000000 kB\nMemAvailable:    4000000 kB\n";
+        assert_eq!(memory_used(meminfo), Some(75.0));
+        assert_eq!(load_average("1.25 0.80 0.50 2/512 1234\n"), Some(1.25));
+
+        let root = TempDir::new("sysfs");
+        root.write("hwmon/hwmon0/name", "applesmc\n");
+        root.write("hwmon/hwmon0/temp1_input", "61250\n");
+        root.write("hwmon/hwmon0/temp1_label", "TC0P\n");
+        root.write("hwmon/hwmon0/temp2_input", "48000\n");
+        root.write("thermal/thermal_zone0/type", "x86_pkg_temp\n");
+        root.write("thermal/thermal_zone0/temp", "70000\n");
+        assert_eq!(temperature(&root, None), Some(70.0));
+        assert_eq!(temperature(&root, Some("applesmc/TC0P")), Some(61.25));
+        assert_eq!(temperature(&root, Some("applesmc/temp2")), Some(48.0));
+        assert_eq!(temperature(&root, Some("nvme/*")), None);
+    }
+
+    #[test]
+    fn test_resource_parsing_errors() {
+        assert_eq!(cpu_times(""), None);
+        assert_eq!(cpu_times("cpu0 50 0 25 400\n"), None);
+        assert_eq!(cpu_times("cpu  100 0 50\n"), None);
+        assert_eq!(memory_used("MemTotal:       16000000 kB\n"), None);
+        assert_eq!(memory_used("MemTotal: 0 kB\nMemAvailable: 0 kB\n"), None);
+        assert_eq!(load_average("n/a"), None);
+
+        // Unreadable or garbled sensors are skipped rather than read as 0 °C
+        let root = TempDir::new("sysfs-errors");
+        assert_eq!(temperature(&root, None), None);
+        root.write("hwmon/hwmon0/temp1_input", "hot\n");
+        root.write("hwmon/hwmon1/temp1_input", "-5000\n");
+        root.write("thermal/thermal_zone0/temp", "70000\n");
+        root.write("thermal/cooling_device0/temp", "90000\n");
+        assert_eq!(temperatures(&root), [("/temp1".to_string(), -5.0)]);
+    }
+}
@@ -0,0 +1,521 @@
+// JSON Schemas for the config files. Every type that can appear in them implements
+// ConfigSchema: structs list their keys here, unit enums take their variants from
+// serde, and the types with hand-written Deserialize impls supply their own. The
+// tests compare the key lists with the ones serde reads, so a key added in
+// config.rs without a schema fails them.
+use crate::config::{
+    Align, ButtonAction, ButtonColor, ButtonConfig, ConfigProxy, GaugeMode, HyprlandExpandConfig,
+    Indicator, NetworkMode, ProfileMatch, RepeatConfig, ResourceKind, ResourceStyle, SwipeAction,
+    SwipeConfig, TimerConfig, TimerKind, ToggleConfig,
+};
+use anyhow::{anyhow, Result};
+use input_linux::Key;
+use serde::de::{self, DeserializeOwned, Visitor};
+use serde_json::{json, Map, Value};
+use std::{collections::HashMap, fmt, fs, path::Path};
+
+pub const SCHEMA_FILES: [&str; 5] = ["config", "commands", "expandables", "hyprland", "dropin"];
+
+pub type Definitions = Map<String, Value>;
+
+/// A type that can appear in the config files.
+pub trait ConfigSchema {
+    /// Whether a struct key of this type may be left out
+    const OPTIONAL: bool = false;
+
+    /// The type's schema, adding any definitions it refers to.
+    fn schema(defs: &mut Definitions) -> Value;
+}
+
+// Adds the definition `name` unless it exists, and refers to it
+fn define(defs: &mut Definitions, name: &str, build: impl FnOnce(&mut Definitions) -> Value) -> Value {
+    if !defs.contains_key(name) {
+        // Reserve the name first so a type can refer to itself
+        defs.insert(name.into(), Value::Null);
+        let schema = build(defs);
+        defs.insert(name.into(), schema);
+    }
+    json!({ "$ref": format!("#/definitions/{name}") })
+}
+
+macro_rules! primitive_schemas {
+    ($($ty:ty => $schema:tt),* $(,)?) => {
+        $(impl ConfigSchema for $ty {
+            fn schema(_: &mut Definitions) -> Value {
+                json!($schema)
+            }
+        })*
+    };
+}
+
+primitive_schemas! {
+    bool => { "type": "boolean" },
+    u16 => { "type": "integer", "minimum": 0 },
+    u32 => { "type": "integer", "minimum": 0 },
+    u64 => { "type": "integer", "minimum": 0 },
+    usize => { "type": "integer", "minimum": 0 },
+    f64 => { "type": "number" },
+    String => { "type": "string" },
+}
+
+impl<T: ConfigSchema> ConfigSchema for Option<T> {
+    const OPTIONAL: bool = true;
+
+    fn schema(defs: &mut Definitions) -> Value {
+        T::schema(defs)
+    }
+}
+
+impl<T: ConfigSchema> ConfigSchema for Vec<T> {
+    fn schema(defs: &mut Definitions) -> Value {
+        json!({ "type": "array", "items": T::schema(defs) })
+    }
+}
+
+impl<T: ConfigSchema> ConfigSchema for HashMap<String, T> {
+    fn schema(defs: &mut Definitions) -> Value {
+        json!({ "type": "object", "additionalProperties": T::schema(defs) })
+    }
+}
+
+macro_rules! unit_enum_schemas {
+    ($($ty:ident),* $(,)?) => {
+        $(impl ConfigSchema for $ty {
+            fn schema(defs: &mut Definitions) -> Value {
+                define(defs, stringify!($ty), |_| json!({ "type": "string", "enum": serde_names::<$ty>() }))
+            }
+        })*
+    };
+}
+
+unit_enum_schemas!(Key, TimerKind, ResourceKind, ResourceStyle, Indicator, NetworkMode, GaugeMode, Align);
+
+impl ConfigSchema for ButtonAction {
+    fn schema(defs: &mut Definitions) -> Value {
+        Key::schema(defs);
+        define(defs, "ButtonAction", |_| ButtonAction::json_schema())
+    }
+}
+
+impl ConfigSchema for SwipeAction {
+    fn schema(defs: &mut Definitions) -> Value {
+        ButtonAction::schema(defs);
+        define(defs, "SwipeAction", |_| SwipeAction::json_schema())
+    }
+}
+
+impl ConfigSchema for ButtonColor {
+    fn schema(defs: &mut Definitions) -> Value {
+        define(defs, "ButtonColor", |_| ButtonColor::json_schema())
+    }
+}
+
+// The keys of a struct, all rejected but the listed ones
+#[derive(Default)]
+struct Object {
+    properties: Map<String, Value>,
+    required: Vec<Value>,
+}
+
+impl Object {
+    fn key<T: ConfigSchema>(mut self, defs: &mut Definitions, key: &str) -> Object {
+        self.properties.insert(key.into(), T::schema(defs));
+        if !T::OPTIONAL {
+            self.required.push(key.into());
+        }
+        self
+    }
+
+    fn build(self) -> Value {
+        let mut schema = json!({
+            "type": "object",
+            "properties": self.properties,
+            "additionalProperties": false,
+        });
+        if !self.required.is_empty() {
+            schema["required"] = Value::Array(self.required);
+        }
+        schema
+    }
+}
+
+// config.toml itself. `[[Profiles]]` entries take the same keys plus a Match table.
+impl ConfigSchema for ConfigProxy {
+    fn schema(defs: &mut Definitions) -> Value {
+        define(defs, "ConfigProxy", |defs| {
+            let mut config = Object::default()
+                .key::<Option<bool>>(defs, "MediaLayerDefault")
+                .key::<Option<bool>>(defs, "ShowButtonOutlines")
+                .key::<Option<bool>>(defs, "EnablePixelShift")
+                .key::<Option<String>>(defs, "FontTemplate")
+                .key::<Option<bool>>(defs, "AdaptiveBrightness")
+                .key::<Option<u32>>(defs, "ActiveBrightness")
+                .key::<Option<Vec<ButtonConfig>>>(defs, "PrimaryLayerKeys")
+                .key::<Option<Vec<ButtonConfig>>>(defs, "MediaLayerKeys")
+                .key::<Option<u32>>(defs, "KeyboardBrightnessStep")
+                .key::<Option<bool>>(defs, "KeyboardBrightnessEnabled")
+                .key::<Option<bool>>(defs, "BackButtonShowOutlines")
+                .key::<Option<ButtonColor>>(defs, "BackButtonOutlineColor")
+                .key::<Option<u32>>(defs, "ExpandableTimeoutSeconds")
+                .key::<Option<u64>>(defs, "LongPressMs")
+                .key::<Option<u64>>(defs, "DoubleTapMs")
+                .key::<Option<SwipeConfig>>(defs, "Swipes")
+                .key::<Option<u32>>(defs, "MinButtonWidth")
+                .key::<Option<u32>>(defs, "BatteryLowPercent")
+                .key::<Option<u32>>(defs, "BatteryCriticalPercent")
+                .key::<Option<bool>>(defs, "BatteryAlert")
+                .key::<Option<bool>>(defs, "BatteryAlertWake")
+                .key::<Option<String>>(defs, "BatteryAlertCommand")
+                .key::<Option<u32>>(defs, "ProbeIntervalSeconds")
+                .key::<Option<bool>>(defs, "AutoEsc")
+                .build();
+            let mut profile = config.clone();
+            profile["properties"]["Match"] = ProfileMatch::schema(defs);
+            defs.insert("Profile".into(), profile);
+            config["properties"]["Profiles"] = json!({
+                "type": "array",
+                "items": { "$ref": "#/definitions/Profile" },
+            });
+            config
+        })
+    }
+}
+
+impl ConfigSchema for ProfileMatch {
+    fn schema(defs: &mut Definitions) -> Value {
+        define(defs, "ProfileMatch", |defs| {
+            Object::default()
+                .key::<Option<String>>(defs, "ProductName")
+                .key::<Option<u16>>(defs, "Width")
+                .key::<Option<String>>(defs, "Hostname")
+                .build()
+        })
+    }
+}
+
+impl ConfigSchema for ButtonConfig {
+    fn schema(defs: &mut Definitions) -> Value {
+        define(defs, "ButtonConfig", |defs| {
+            let mut button = Object::default()
+                .key::<Option<String>>(defs, "Icon")
+                .key::<Option<String>>(defs, "Svg")
+                .key::<Option<String>>(defs, "Text")
+                .key::<Option<String>>(defs, "Theme")
+                .key::<Option<String>>(defs, "Time")
+                .key::<Option<GaugeMode>>(defs, "Battery")
+                .key::<Option<bool>>(defs, "BatteryTime")
+                .key::<Option<String>>(defs, "Locale")
+                .key::<Option<String>>(defs, "TimeZone")
+                .key::<Option<ButtonAction>>(defs, "Action")
+                .key::<Option<ButtonAction>>(defs, "LongPressAction")
+                .key::<Option<ButtonAction>>(defs, "DoubleTapAction")
+                .key::<Option<usize>>(defs, "Stretch")
+                .key::<Option<bool>>(defs, "ShowButtonOutlines")
+                .key::<Option<ButtonColor>>(defs, "ButtonOutlinesColor")
+                .key::<Option<bool>>(defs, "ShowAppIconAlongsideText")
+                .key::<Option<String>>(defs, "AppIcon")
+                .key::<Option<RepeatConfig>>(defs, "Repeat")
+                .key::<Option<bool>>(defs, "Spacer")
+                .key::<Option<u32>>(defs, "Width")
+                .key::<Option<Align>>(defs, "Align")
+                .key::<Option<String>>(defs, "VisibleWhen")
+                .key::<Option<ToggleConfig>>(defs, "Toggle")
+                .key::<Option<ResourceKind>>(defs, "Resource")
+                .key::<Option<ResourceStyle>>(defs, "ResourceStyle")
+                .key::<Option<String>>(defs, "Sensor")
+                .key::<Option<NetworkMode>>(defs, "Network")
+                .key::<Option<GaugeMode>>(defs, "Volume")
+                .key::<Option<bool>>(defs, "VolumeSlider")
+                .key::<Option<TimerConfig>>(defs, "Timer")
+                .key::<Option<Indicator>>(defs, "Indicator")
+                .key::<Option<bool>>(defs, "Notifications")
+                .key::<Option<usize>>(defs, "Notification")
+                .build();
+            // Spacers are the one kind of layer entry without an Action
+            button["anyOf"] = json!([
+                { "required": ["Action"] },
+                { "required": ["Spacer"], "properties": { "Spacer": { "const": true } } },
+            ]);
+            button
+        })
+    }
+}
+
+impl ConfigSchema for HyprlandExpandConfig {
+    fn schema(defs: &mut Definitions) -> Value {
+        define(defs, "HyprlandExpandConfig", |defs| {
+            Object::default()
+                .key::<String>(defs, "Class")
+                .key::<Option<String>>(defs, "ButtonTitle")
+                .key::<Option<bool>>(defs, "ShowAppIconAlongsideText")
+                .key::<Option<String>>(defs, "AppIcon")
+                .key::<Vec<ButtonConfig>>(defs, "LayerKeys")
+                .build()
+        })
+    }
+}
+
+impl ConfigSchema for ToggleConfig {
+    fn schema(defs: &mut Definitions) -> Value {
+        define(defs, "ToggleConfig", |defs| {
+            Object::default()
+                .key::<Option<String>>(defs, "Name")
+                .key::<String>(defs, "OnCommand")
+                .key::<String>(defs, "OffCommand")
+                .key::<Option<String>>(defs, "Probe")
+                .key::<Option<String>>(defs, "OnIcon")
+                .key::<Option<String>>(defs, "OnText")
+                .key::<Option<ButtonColor>>(defs, "OnColor")
+                .key::<Option<ButtonColor>>(defs, "OffColor")
+                .build()
+        })
+    }
+}
+
+impl ConfigSchema for TimerConfig {
+    fn schema(defs: &mut Definitions) -> Value {
+        define(defs, "TimerConfig", |defs| {
+            Object::default()
+                .key::<TimerKind>(defs, "Kind")
+                .key::<Option<String>>(defs, "Name")
+                .key::<Option<f64>>(defs, "Minutes")
+                .key::<Option<f64>>(defs, "BreakMinutes")
+                .key::<Option<String>>(defs, "Command")
+                .build()
+        })
+    }
+}
+
+impl ConfigSchema for RepeatConfig {
+    fn schema(defs: &mut Definitions) -> Value {
+        define(defs, "RepeatConfig", |defs| {
+            Object::default()
+                .key::<Option<u64>>(defs, "Delay")
+                .key::<Option<u32>>(defs, "Rate")
+                .build()
+        })
+    }
+}
+
+impl ConfigSchema for SwipeConfig {
+    fn schema(defs: &mut Definitions) -> Value {
+        define(defs, "SwipeConfig", |defs| {
+            Object::default()
+                .key::<Option<SwipeAction>>(defs, "Left")
+                .key::<Option<SwipeAction>>(defs, "Right")
+                .key::<Option<SwipeAction>>(defs, "TwoFingerLeft")
+                .key::<Option<SwipeAction>>(defs, "TwoFingerRight")
+                .key::<Option<u32>>(defs, "Distance")
+                .build()
+        })
+    }
+}
+
+// Serde hands a derived impl's struct keys or enum variants to the deserializer
+// before reading anything; this one stops right there with them.
+#[derive(Debug)]
+enum NameProbe {
+    Names(&'static [&'static str]),
+    Other(String),
+}
+
+impl fmt::Display for NameProbe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NameProbe::Names(names) => write!(f, "{}", names.join(", ")),
+            NameProbe::Other(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for NameProbe {}
+
+impl de::Error for NameProbe {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        NameProbe::Other(msg.to_string())
+    }
+}
+
+struct NameProbeDeserializer;
+
+impl<'de> de::Deserializer<'de> for NameProbeDeserializer {
+    type Error = NameProbe;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, NameProbe> {
+        Err(NameProbe::Other("not a struct or enum".into()))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, NameProbe> {
+        Err(NameProbe::Names(fields))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        variants: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, NameProbe> {
+        Err(NameProbe::Names(variants))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map identifier ignored_any
+    }
+}
+
+/// The keys of a derived struct, aliases included, or the variants of a derived enum.
+fn serde_names<T: DeserializeOwned>() -> &'static [&'static str] {
+    match T::deserialize(NameProbeDeserializer) {
+        Err(NameProbe::Names(names)) => names,
+        _ => &[],
+    }
+}
+
+fn include_schema() -> Value {
+    json!({
+        "description": "Other TOML files to merge first, relative to this file; `*` and `?` allowed in the file name",
+        "anyOf": [
+            { "type": "string" },
+            { "type": "array", "items": { "type": "string" } },
+        ],
+    })
+}
+
+// config.toml is ConfigProxy itself, plus Include
+fn config_root(defs: &mut Definitions) -> Value {
+    ConfigProxy::schema(defs);
+    let mut root = defs.remove("ConfigProxy").unwrap_or_default();
+    root["properties"]["Include"] = include_schema();
+    root
+}
+
+fn keyed_root<T: ConfigSchema>(defs: &mut Definitions) -> Value {
+    let mut root = HashMap::<String, T>::schema(defs);
+    root["properties"] = json!({ "Include": include_schema() });
+    root
+}
+
+/// Builds the schema for one of `SCHEMA_FILES`.
+pub fn generate(file: &str) -> Result<Value> {
+    let mut defs = Definitions::new();
+    let (title, mut root) = match file {
+        "config" => ("tiny-dfr config.toml", config_root(&mut defs)),
+        "commands" => ("tiny-dfr commands.toml", keyed_root::<String>(&mut defs)),
+        "expandables" => ("tiny-dfr expandables.toml", keyed_root::<Vec<ButtonConfig>>(&mut defs)),
+        "hyprland" => ("tiny-dfr hyprland.toml", keyed_root::<Vec<HyprlandExpandConfig>>(&mut defs)),
+        "dropin" => {
+            let mut root = config_root(&mut defs);
+            root["properties"]["Commands"] = keyed_root::<String>(&mut defs);
+            root["properties"]["Expandables"] = keyed_root::<Vec<ButtonConfig>>(&mut defs);
+            root["properties"]["Hyprland"] = keyed_root::<Vec<HyprlandExpandConfig>>(&mut defs);
+            ("tiny-dfr config.d drop-in", root)
+        }
+        _ => return Err(anyhow!("unknown schema '{}', expected one of {}", file, SCHEMA_FILES.join(", "))),
+    };
+    root["$schema"] = json!("http://json-schema.org/draft-07/schema#");
+    root["title"] = json!(title);
+    root["definitions"] = Value::Object(defs);
+    Ok(root)
+}
+
+/// `tiny-dfr schema [config|commands|expandables|hyprland|dropin]` prints one schema,
+/// `tiny-dfr schema --out DIR` writes all of them as DIR/<name>.schema.json.
+pub fn schema_main(args: &[String]) -> i32 {
+    let result = match args {
+        [flag, dir] if flag == "--out" => SCHEMA_FILES.iter().try_for_each(|file| {
+            let path = Path::new(dir).join(format!("{file}.schema.json"));
+            fs::create_dir_all(dir)?;
+            fs::write(&path, serde_json::to_string_pretty(&generate(file)?)?)?;
+            println!("Wrote {}", path.display());
+            Ok(())
+        }),
+        [file] => generate(file).map(|schema| println!("{}", serde_json::to_string_pretty(&schema).unwrap())),
+        _ => {
+            eprintln!("usage: tiny-dfr schema <{}> | --out <dir>", SCHEMA_FILES.join("|"));
+            return 2;
+        }
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Schema generation failed: {e}");
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schemas_follow_definitions() {
+        for file in SCHEMA_FILES {
+            generate(file).unwrap();
+        }
+        let config = generate("config").unwrap();
+        let button = &config["definitions"]["ButtonConfig"];
+        assert!(button["properties"]["Icon"].is_object());
+        assert_eq!(button["anyOf"][0]["required"], json!(["Action"]));
+        assert!(config["definitions"]["Key"]["enum"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("VolumeUp")));
+        assert!(config["properties"]["Profiles"].is_object());
+        assert!(config["definitions"]["Profile"]["properties"]["Match"].is_object());
+
+        let hyprland = generate("hyprland").unwrap();
+        assert_eq!(
+            hyprland["additionalProperties"]["items"],
+            json!({ "$ref": "#/definitions/HyprlandExpandConfig" })
+        );
+    }
+
+    #[test]
+    fn test_schemas_cover_every_key() {
+        fn check<T: ConfigSchema + DeserializeOwned>(name: &str) {
+            let mut defs = Definitions::new();
+            T::schema(&mut defs);
+            let mut listed: Vec<&str> = defs[name]["properties"].as_object().unwrap().keys().map(String::as_str).collect();
+            let mut read = serde_names::<T>().to_vec();
+            listed.sort_unstable();
+            read.sort_unstable();
+            assert_eq!(listed, read, "keys of {name}");
+        }
+        check::<ConfigProxy>("ConfigProxy");
+        check::<ProfileMatch>("ProfileMatch");
+        check::<ButtonConfig>("ButtonConfig");
+        check::<HyprlandExpandConfig>("HyprlandExpandConfig");
+        check::<ToggleConfig>("ToggleConfig");
+        check::<TimerConfig>("TimerConfig");
+        check::<RepeatConfig>("RepeatConfig");
+        check::<SwipeConfig>("SwipeConfig");
+    }
+
+    #[test]
+    fn test_swipe_action_schema() {
+        let config = generate("config").unwrap();
+        let names = config["definitions"]["SwipeAction"]["anyOf"][0]["enum"].as_array().unwrap().clone();
+        let mut seen = [false; 5];
+        for name in names {
+            let swipes: SwipeConfig = toml::from_str(&format!("Left = {name}")).unwrap();
+            // No wildcard: a new variant has to be added here, and then to the schema
+            match swipes.left.unwrap() {
+                SwipeAction::NextLayer => seen[0] = true,
+                SwipeAction::PreviousLayer => seen[1] = true,
+                SwipeAction::NextPage => seen[2] = true,
+                SwipeAction::PreviousPage => seen[3] = true,
+                SwipeAction::Back => seen[4] = true,
+                SwipeAction::Button(action) => panic!("{name} is the button action {action:?}"),
+            }
+        }
+        assert_eq!(seen, [true; 5], "swipe actions missing from the schema");
+        assert_eq!(config["definitions"]["SwipeAction"]["anyOf"][1], json!({ "$ref": "#/definitions/ButtonAction" }));
+    }
+}
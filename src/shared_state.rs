@@ -0,0 +1,86 @@
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// State a monitor thread keeps current, with an eventfd signalled on every change
+/// that the main loop polls with its other fds.
+pub struct SharedState<T> {
+    state: Mutex<T>,
+    wake_fd: OwnedFd,
+}
+
+impl<T: Clone + Default + PartialEq> Default for SharedState<T> {
+    fn default() -> Self {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        assert!(fd >= 0, "eventfd failed: {}", io::Error::last_os_error());
+        SharedState {
+            state: Mutex::new(T::default()),
+            wake_fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        }
+    }
+}
+
+impl<T: Clone + Default + PartialEq> SharedState<T> {
+    pub fn current(&self) -> T {
+        self.state.lock().map(|state| state.clone()).unwrap_or_default()
+    }
+
+    pub fn wake_fd(&self) -> BorrowedFd<'_> {
+        self.wake_fd.as_fd()
+    }
+
+    /// Clears the wake fd; true if the state changed since the last call.
+    pub fn take_changed(&self) -> bool {
+        let mut count = [0u8; 8];
+        unsafe { libc::read(self.wake_fd.as_raw_fd(), count.as_mut_ptr().cast(), count.len()) == 8 }
+    }
+
+    /// Changes the state, waking the main loop if that made a difference.
+    pub fn update(&self, f: impl FnOnce(&mut T)) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let before = state.clone();
+        f(&mut state);
+        if *state != before {
+            let one = 1u64.to_ne_bytes();
+            unsafe { libc::write(self.wake_fd.as_raw_fd(), one.as_ptr().cast(), one.len()) };
+        }
+    }
+}
+
+/// Runs `session` again and again, `retry` apart, calling `lost` after each failure.
+/// Failures are logged once each until a different one comes along; the other side
+/// may simply not be up yet.
+pub fn keep_running(what: &str, retry: Duration, mut session: impl FnMut() -> io::Result<()>, mut lost: impl FnMut()) -> ! {
+    let mut last_error = String::new();
+    loop {
+        if let Err(e) = session() {
+            if e.to_string() != last_error {
+                eprintln!("{} unavailable: {}", what, e);
+                last_error = e.to_string();
+            }
+            lost();
+        }
+        thread::sleep(retry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_state() {
+        let shared = SharedState::<u32>::default();
+        assert!(!shared.take_changed());
+        shared.update(|n| *n = 0);
+        assert!(!shared.take_changed());
+        shared.update(|n| *n = 3);
+        shared.update(|n| *n += 1);
+        assert_eq!(shared.current(), 4);
+        assert!(shared.take_changed() && !shared.take_changed());
+    }
+}
@@ -0,0 +1,289 @@
+use crate::config::{TimerConfig, TimerKind};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const SECOND: Duration = Duration::from_secs(1);
+// A finished period flashes its button this long, or until tapped
+const FLASH_DURATION: Duration = Duration::from_secs(10);
+const FLASH_INTERVAL: Duration = Duration::from_millis(500);
+const DEFAULT_COUNTDOWN_MINUTES: f64 = 5.0;
+const DEFAULT_WORK_MINUTES: f64 = 25.0;
+const DEFAULT_BREAK_MINUTES: f64 = 5.0;
+
+struct Timer {
+    config: TimerConfig,
+    // Time counted before the current run
+    banked: Duration,
+    started: Option<Instant>,
+    on_break: bool,
+    expired_at: Option<Instant>,
+    // Text and highlight the bar last showed
+    shown: Option<(String, bool)>,
+    // Registered by the latest config load
+    registered: bool,
+}
+
+impl Timer {
+    fn new(config: TimerConfig) -> Timer {
+        Timer {
+            config,
+            banked: Duration::ZERO,
+            started: None,
+            on_break: false,
+            expired_at: None,
+            shown: None,
+            registered: true,
+        }
+    }
+
+    fn elapsed(&self, now: Instant) -> Duration {
+        self.banked + self.started.map_or(Duration::ZERO, |started| now.saturating_duration_since(started))
+    }
+
+    // Length of the current period; stopwatches have none
+    fn period(&self) -> Option<Duration> {
+        let minutes = match self.config.kind {
+            TimerKind::Stopwatch => return None,
+            TimerKind::Countdown => self.config.minutes.unwrap_or(DEFAULT_COUNTDOWN_MINUTES),
+            TimerKind::Pomodoro if self.on_break => self.config.break_minutes.unwrap_or(DEFAULT_BREAK_MINUTES),
+            TimerKind::Pomodoro => self.config.minutes.unwrap_or(DEFAULT_WORK_MINUTES),
+        };
+        Some(Duration::from_secs_f64(minutes.max(0.0) * 60.0))
+    }
+
+    fn flashing(&self, now: Instant) -> bool {
+        self.expired_at.is_some_and(|at| now.saturating_duration_since(at) < FLASH_DURATION)
+    }
+
+    // Starts or pauses; a tap on a flashing timer only silences it
+    fn press(&mut self, now: Instant) {
+        if self.flashing(now) {
+            self.expired_at = None;
+            return;
+        }
+        if let Some(started) = self.started.take() {
+            self.banked += now.saturating_duration_since(started);
+        } else {
+            if self.period().is_some_and(|period| self.banked >= period) {
+                self.banked = Duration::ZERO;
+            }
+            self.started = Some(now);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.banked = Duration::ZERO;
+        self.started = None;
+        self.on_break = false;
+        self.expired_at = None;
+    }
+
+    // Ends a finished period; true if one just ended. Pomodoros carry on with the next.
+    fn advance(&mut self, now: Instant) -> bool {
+        let Some(period) = self.period() else {
+            return false;
+        };
+        let elapsed = self.elapsed(now);
+        if self.started.is_none() || elapsed < period {
+            return false;
+        }
+        if self.config.kind == TimerKind::Pomodoro {
+            self.on_break = !self.on_break;
+            self.banked = Duration::ZERO;
+            self.started = Some(now - (elapsed - period));
+        } else {
+            self.banked = period;
+            self.started = None;
+        }
+        self.expired_at = Some(now);
+        true
+    }
+
+    // Remaining time rounded up for countdowns, elapsed time rounded down otherwise
+    fn text(&self, now: Instant) -> String {
+        let elapsed = self.elapsed(now);
+        let seconds = match self.period() {
+            Some(period) => period.saturating_sub(elapsed).as_secs_f64().ceil() as u64,
+            None => elapsed.as_secs(),
+        };
+        let clock = if seconds >= 3600 {
+            format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+        } else {
+            format!("{:02}:{:02}", seconds / 60, seconds % 60)
+        };
+        if self.on_break {
+            format!("Break {}", clock)
+        } else {
+            clock
+        }
+    }
+
+    fn highlighted(&self, now: Instant) -> bool {
+        self.expired_at.is_some_and(|at| {
+            let since = now.saturating_duration_since(at);
+            since < FLASH_DURATION && (since.as_millis() / FLASH_INTERVAL.as_millis()).is_multiple_of(2)
+        })
+    }
+
+    // How long until the text or highlight changes. A timer that is not on screen
+    // only needs attention when its period ends.
+    fn next_change(&self, now: Instant, shown: bool) -> Option<Duration> {
+        if !shown {
+            let period = self.period()?;
+            return self.started.map(|_| period.saturating_sub(self.elapsed(now)));
+        }
+        let flash = self.expired_at.and_then(|at| {
+            let since = now.saturating_duration_since(at);
+            (since < FLASH_DURATION).then(|| FLASH_INTERVAL - Duration::from_nanos((since.as_nanos() % FLASH_INTERVAL.as_nanos()) as u64))
+        });
+        let tick = self.started.map(|_| {
+            let fraction = Duration::from_nanos((self.elapsed(now).as_nanos() % SECOND.as_nanos()) as u64);
+            SECOND - fraction
+        });
+        flash.into_iter().chain(tick).min()
+    }
+}
+
+// Global timer state by name
+static TIMERS: std::sync::LazyLock<Arc<Mutex<HashMap<String, Timer>>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+// Public API
+/// Adds a timer, keeping the state of one with the same name across config reloads.
+pub fn register(config: &TimerConfig) {
+    if let Ok(mut timers) = TIMERS.lock() {
+        timers
+            .entry(config.name())
+            .and_modify(|timer| {
+                timer.config = config.clone();
+                timer.registered = true;
+            })
+            .or_insert_with(|| Timer::new(config.clone()));
+    }
+}
+
+/// Marks every timer unregistered before a config reload registers the current ones.
+pub fn clear_registered() {
+    if let Ok(mut timers) = TIMERS.lock() {
+        timers.values_mut().for_each(|timer| timer.registered = false);
+    }
+}
+
+/// Drops the timers the reload didn't register, so a deleted or renamed timer stops
+/// running its command and waking the main loop.
+pub fn prune_unregistered() {
+    if let Ok(mut timers) = TIMERS.lock() {
+        timers.retain(|_, timer| timer.registered);
+    }
+}
+
+pub fn press(name: &str) {
+    if let Some(timer) = TIMERS.lock().ok().as_mut().and_then(|timers| timers.get_mut(name)) {
+        timer.press(Instant::now());
+    }
+}
+
+pub fn reset(name: &str) {
+    if let Some(timer) = TIMERS.lock().ok().as_mut().and_then(|timers| timers.get_mut(name)) {
+        timer.reset();
+    }
+}
+
+pub fn text(name: &str) -> String {
+    let now = Instant::now();
+    TIMERS.lock().ok().and_then(|timers| Some(timers.get(name)?.text(now))).unwrap_or_default()
+}
+
+pub fn highlighted(name: &str) -> bool {
+    let now = Instant::now();
+    TIMERS.lock().is_ok_and(|timers| timers.get(name).is_some_and(|timer| timer.highlighted(now)))
+}
+
+/// Ends finished periods. Returns the timers whose button needs a redraw and the
+/// commands of the periods that ended.
+pub fn tick() -> (HashSet<String>, Vec<String>) {
+    let now = Instant::now();
+    let mut changed = HashSet::new();
+    let mut commands = Vec::new();
+    if let Ok(mut timers) = TIMERS.lock() {
+        for (name, timer) in timers.iter_mut() {
+            if timer.advance(now) {
+                commands.extend(timer.config.command.clone());
+            }
+            let shown = Some((timer.text(now), timer.highlighted(now)));
+            if timer.shown != shown {
+                timer.shown = shown;
+                changed.insert(name.clone());
+            }
+        }
+    }
+    (changed, commands)
+}
+
+/// How long the main loop may sleep before a timer needs attention; `shown` tells
+/// which timers have a button on screen.
+pub fn next_change(shown: impl Fn(&str) -> bool) -> Option<Duration> {
+    let now = Instant::now();
+    TIMERS.lock().ok()?.iter().filter_map(|(name, timer)| timer.next_change(now, shown(name))).min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timers() {
+        let t0 = Instant::now();
+        let at = |secs: u64| t0 + Duration::from_secs(secs);
+
+        let config: TimerConfig = toml::from_str("Kind = \"Countdown\"\nMinutes = 1").unwrap();
+        let mut countdown = Timer::new(config);
+        assert_eq!(countdown.text(t0), "01:00");
+        countdown.press(t0);
+        assert_eq!(countdown.text(t0 + Duration::from_millis(1500)), "00:59");
+        countdown.press(at(20));
+        assert_eq!((countdown.text(at(100)), countdown.next_change(at(100), true)), ("00:40".to_string(), None));
+        countdown.press(at(100));
+        assert_eq!(countdown.next_change(at(130), false), Some(Duration::from_secs(10)));
+        assert!(!countdown.advance(at(139)));
+        assert!(countdown.advance(at(140)));
+        assert_eq!(countdown.text(at(141)), "00:00");
+        assert!(countdown.highlighted(at(140)) && !countdown.highlighted(at(140) + FLASH_INTERVAL));
+        countdown.press(at(141));
+        assert!(!countdown.flashing(at(141)));
+        countdown.press(at(142));
+        assert_eq!(countdown.text(at(142)), "01:00");
+
+        let config: TimerConfig = toml::from_str("Kind = \"Pomodoro\"\nMinutes = 25\nBreakMinutes = 5").unwrap();
+        let mut pomodoro = Timer::new(config);
+        pomodoro.press(t0);
+        assert!(pomodoro.advance(at(25 * 60 + 2)));
+        assert_eq!(pomodoro.text(at(25 * 60 + 2)), "Break 04:58");
+        pomodoro.reset();
+        assert_eq!(pomodoro.text(at(2000)), "25:00");
+
+        let mut stopwatch = Timer::new(toml::from_str("Kind = \"Stopwatch\"").unwrap());
+        stopwatch.press(t0);
+        assert_eq!(stopwatch.text(at(3725)), "1:02:05");
+        assert_eq!(stopwatch.next_change(t0 + Duration::from_millis(300), true), Some(Duration::from_millis(700)));
+        assert_eq!(stopwatch.next_change(t0 + Duration::from_millis(300), false), None);
+    }
+
+    #[test]
+    fn test_reload_drops_removed_timers() {
+        let kept: TimerConfig = toml::from_str("Kind = \"Stopwatch\"\nName = \"kept\"").unwrap();
+        let removed: TimerConfig = toml::from_str("Kind = \"Countdown\"\nMinutes = 1\nName = \"removed\"").unwrap();
+        register(&kept);
+        register(&removed);
+        press("kept");
+        press("removed");
+
+        clear_registered();
+        register(&kept);
+        prune_unregistered();
+        assert!(TIMERS.lock().unwrap().get("kept").is_some_and(|timer| timer.started.is_some()));
+        assert_eq!(text("removed"), "");
+        assert!(!tick().0.contains("removed"));
+    }
+}
@@ -0,0 +1,219 @@
+use crate::config::ToggleConfig;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Remembered states of toggles without a probe, one `name=on|off` per line
+const STATE_PATH: &str = "/var/lib/tiny-dfr/toggles";
+const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(10);
+
+struct ToggleStore {
+    path: PathBuf,
+    states: HashMap<String, bool>,
+    // Toggles by name, for their commands and probes
+    toggles: HashMap<String, ToggleConfig>,
+    probe_interval: Duration,
+    changed: bool,
+}
+
+impl ToggleStore {
+    fn load(path: &Path) -> ToggleStore {
+        let states = fs::read_to_string(path).map(|s| parse_states(&s)).unwrap_or_default();
+        ToggleStore {
+            path: path.to_path_buf(),
+            states,
+            toggles: HashMap::new(),
+            probe_interval: DEFAULT_PROBE_INTERVAL,
+            changed: false,
+        }
+    }
+
+    // Writes out the toggles the daemon owns the state of
+    fn save(&self) {
+        let owned: HashMap<&String, bool> = self
+            .states
+            .iter()
+            .filter(|(name, _)| self.toggles.get(*name).is_none_or(|t| t.probe.is_none()))
+            .map(|(name, on)| (name, *on))
+            .collect();
+        let mut names: Vec<_> = owned.keys().collect();
+        names.sort();
+        let contents: String = names
+            .into_iter()
+            .map(|name| format!("{}={}\n", name, if owned[name] { "on" } else { "off" }))
+            .collect();
+        if let Some(dir) = self.path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        if let Err(e) = fs::write(&self.path, contents) {
+            eprintln!("Failed to save toggle states to {}: {}", self.path.display(), e);
+        }
+    }
+
+    // Toggle names by probe command, so toggles sharing a probe run it once
+    fn probes(&self) -> HashMap<String, Vec<String>> {
+        let mut probes: HashMap<String, Vec<String>> = HashMap::new();
+        for (name, toggle) in &self.toggles {
+            if let Some(probe) = &toggle.probe {
+                probes.entry(probe.clone()).or_default().push(name.clone());
+            }
+        }
+        probes
+    }
+
+    // Flips the toggle and returns the command to run
+    fn flip(&mut self, name: &str) -> Option<String> {
+        let toggle = self.toggles.get(name)?;
+        let on = !self.states.get(name).copied().unwrap_or(false);
+        let command = if on { &toggle.on_command } else { &toggle.off_command }.clone();
+        self.states.insert(name.to_string(), on);
+        self.changed = true;
+        self.save();
+        Some(command)
+    }
+}
+
+fn parse_states(contents: &str) -> HashMap<String, bool> {
+    contents
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(name, state)| (name.trim().to_string(), state.trim() == "on"))
+        .collect()
+}
+
+// Global toggle state
+static TOGGLES: std::sync::LazyLock<Arc<Mutex<ToggleStore>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(ToggleStore::load(Path::new(STATE_PATH)))));
+
+/// Runs the probes of probed toggles every `ProbeIntervalSeconds`. This is the only
+/// place commands are polled as the desktop user.
+pub struct ToggleMonitor {
+    _handle: thread::JoinHandle<()>,
+}
+
+impl ToggleMonitor {
+    pub fn new() -> Self {
+        let handle = thread::spawn(move || {
+            Self::monitor_loop();
+        });
+
+        ToggleMonitor { _handle: handle }
+    }
+
+    fn monitor_loop() {
+        loop {
+            let (probes, interval) = TOGGLES
+                .lock()
+                .map(|store| (store.probes(), store.probe_interval))
+                .unwrap_or_default();
+            for (probe, names) in probes {
+                let Some(output) = crate::visibility::probe(&probe) else {
+                    continue;
+                };
+                let on = output.status.success();
+                if let Ok(mut store) = TOGGLES.lock() {
+                    for name in names {
+                        if store.states.insert(name, on) != Some(on) {
+                            store.changed = true;
+                        }
+                    }
+                }
+            }
+            thread::sleep(interval.max(Duration::from_secs(1)));
+        }
+    }
+}
+
+// Public API
+pub fn register(toggle: &ToggleConfig) {
+    if let Ok(mut store) = TOGGLES.lock() {
+        store.toggles.insert(toggle.name().to_string(), toggle.clone());
+    }
+}
+
+/// Forgets the registered toggles before a config reload registers the current ones,
+/// so removed toggles stop probing. Their states are kept.
+pub fn clear_registered() {
+    if let Ok(mut store) = TOGGLES.lock() {
+        store.toggles.clear();
+    }
+}
+
+/// How long the monitor waits between rounds of probes.
+pub fn set_probe_interval(interval: Duration) {
+    if let Ok(mut store) = TOGGLES.lock() {
+        store.probe_interval = interval;
+    }
+}
+
+pub fn is_on(name: &str) -> bool {
+    TOGGLES.lock().is_ok_and(|store| store.states.get(name).copied().unwrap_or(false))
+}
+
+/// Names of the toggles that are on, for `toggle(...)` visibility conditions.
+pub fn on_names() -> HashSet<String> {
+    TOGGLES
+        .lock()
+        .map(|store| store.states.iter().filter(|(_, on)| **on).map(|(name, _)| name.clone()).collect())
+        .unwrap_or_default()
+}
+
+/// Switches the toggle over and returns the command it should run.
+pub fn flip(name: &str) -> Option<String> {
+    TOGGLES.lock().ok()?.flip(name)
+}
+
+pub fn take_changed() -> bool {
+    if let Ok(mut store) = TOGGLES.lock() {
+        std::mem::take(&mut store.changed)
+    } else {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_toggle_persistence() {
+        let dir = TempDir::new("toggles");
+        let path = dir.join("toggles");
+        let toggle: ToggleConfig = toml::from_str("Name = \"dnd\"\nOnCommand = \"DndOn\"\nOffCommand = \"DndOff\"").unwrap();
+        let probed: ToggleConfig = toml::from_str("OnCommand = \"MuteMic\"\nOffCommand = \"UnmuteMic\"\nProbe = \"true\"").unwrap();
+
+        let mut store = ToggleStore::load(&path);
+        store.toggles.insert(toggle.name().into(), toggle.clone());
+        store.toggles.insert(probed.name().into(), probed);
+        assert_eq!(store.probes(), HashMap::from([("true".to_string(), vec!["MuteMic".to_string()])]));
+        assert_eq!(store.flip("dnd").as_deref(), Some("DndOn"));
+        assert_eq!(store.flip("MuteMic").as_deref(), Some("MuteMic"));
+        assert_eq!(store.flip("unknown"), None);
+
+        // Only the state the daemon owns comes back
+        let store = ToggleStore::load(&path);
+        assert_eq!(store.states, HashMap::from([("dnd".to_string(), true)]));
+    }
+
+    #[test]
+    fn test_toggle_state_errors() {
+        let dir = TempDir::new("toggles-errors");
+        let path = dir.write("toggles", "dnd=on\ngarbage\nwifi = yes\n");
+        let store = ToggleStore::load(&path);
+        assert_eq!(store.states, HashMap::from([("dnd".to_string(), true), ("wifi".to_string(), false)]));
+
+        // An unwritable state file still flips the toggle for this session
+        let blocked = dir.write("file", "").join("toggles");
+        let mut store = ToggleStore::load(&blocked);
+        assert!(store.states.is_empty());
+        let toggle: ToggleConfig = toml::from_str("Name = \"dnd\"\nOnCommand = \"DndOn\"\nOffCommand = \"DndOff\"").unwrap();
+        store.toggles.insert(toggle.name().into(), toggle);
+        assert_eq!(store.flip("dnd").as_deref(), Some("DndOn"));
+        assert_eq!(store.states.get("dnd"), Some(&true));
+        assert!(!blocked.exists());
+    }
+}
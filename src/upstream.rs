@@ -0,0 +1,319 @@
+// Compatibility with config.toml files written for upstream AsahiLinux tiny-dfr.
+//
+// Upstream has a single config.toml whose buttons only send keys. This fork reads
+// that format directly (see `config::load_config`); this module detects such files,
+// reports what behaves differently here, and backs the `tiny-dfr migrate` command.
+use crate::config::{parse_key_name, ButtonAction};
+use anyhow::{anyhow, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use toml::{Table, Value};
+
+const UPSTREAM_SETTINGS: [&str; 8] = [
+    "MediaLayerDefault",
+    "ShowButtonOutlines",
+    "EnablePixelShift",
+    "FontTemplate",
+    "AdaptiveBrightness",
+    "ActiveBrightness",
+    "PrimaryLayerKeys",
+    "MediaLayerKeys",
+];
+const UPSTREAM_LAYERS: [&str; 2] = ["PrimaryLayerKeys", "MediaLayerKeys"];
+const UPSTREAM_BUTTON_FIELDS: [&str; 9] = [
+    "Icon", "Svg", "Text", "Theme", "Time", "Battery", "Locale", "Action", "Stretch",
+];
+const FORK_ACTION_PREFIXES: [&str; 4] = ["Hyprland_Expand_", "KeyCombos_", "Expand_", "Command_"];
+const ICON_DIRS: [&str; 2] = ["/etc/tiny-dfr", "/usr/share/tiny-dfr"];
+
+fn buttons<'a>(table: &'a Table, layer: &str) -> impl Iterator<Item = (usize, &'a Table)> {
+    table
+        .get(layer)
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_table)
+        .enumerate()
+}
+
+fn uses_fork_action(button: &Table) -> bool {
+    button
+        .get("Action")
+        .and_then(Value::as_str)
+        .is_some_and(|a| FORK_ACTION_PREFIXES.iter().any(|p| a.starts_with(p)))
+}
+
+// Upstream's spellings: `Svg` for `Icon`, and keys pressed together as an array
+fn uses_upstream_form(button: &Table) -> bool {
+    button.contains_key("Svg") || matches!(button.get("Action"), Some(Value::Array(_)))
+}
+
+/// True when a config.toml uses upstream tiny-dfr's own forms and nothing that only
+/// this fork understands. Files setting just the keys both share, like an /etc
+/// override or a drop-in, don't count.
+pub fn is_upstream_format(table: &Table) -> bool {
+    let all_buttons = || UPSTREAM_LAYERS.iter().flat_map(|layer| buttons(table, layer)).map(|(_, button)| button);
+    table.keys().all(|k| UPSTREAM_SETTINGS.contains(&k.as_str()))
+        && all_buttons().all(|button| {
+            button.keys().all(|k| UPSTREAM_BUTTON_FIELDS.contains(&k.as_str())) && !uses_fork_action(button)
+        })
+        && all_buttons().any(uses_upstream_form)
+}
+
+fn icon_installed(name: &str) -> bool {
+    ICON_DIRS.iter().any(|dir| {
+        ["svg", "png"]
+            .iter()
+            .any(|ext| Path::new(&format!("{dir}/{name}.{ext}")).exists())
+    })
+}
+
+fn check_button(layer: &str, index: usize, button: &Table, notes: &mut Vec<String>) {
+    let at = format!("{layer}[{index}]");
+    for key in button.keys() {
+        if !UPSTREAM_BUTTON_FIELDS.contains(&key.as_str()) {
+            notes.push(format!("{at}: unknown button field '{key}' dropped"));
+        }
+    }
+    match button.get("Action") {
+        Some(Value::String(name)) => {
+            if let Ok(ButtonAction::Command(_)) = ButtonAction::from_name(name) {
+                notes.push(format!(
+                    "{at}: '{name}' is not a key name; upstream rejects it, here it runs the command '{name}' from commands.toml"
+                ));
+            }
+        }
+        Some(Value::Array(keys)) => {
+            for key in keys {
+                if key.as_str().and_then(parse_key_name).is_none() {
+                    notes.push(format!("{at}: {key} is not a key name"));
+                }
+            }
+        }
+        Some(other) => notes.push(format!("{at}: unsupported Action {other}")),
+        None => notes.push(format!("{at}: button has no Action")),
+    }
+    let icon = button.get("Icon").or_else(|| button.get("Svg")).and_then(Value::as_str);
+    if let (Some(icon), None) = (icon, button.get("Theme")) {
+        if !icon_installed(icon) {
+            notes.push(format!(
+                "{at}: icon '{icon}' is not installed in {}; copy it from your old setup",
+                ICON_DIRS.join(" or ")
+            ));
+        }
+    }
+}
+
+/// Lists everything in an upstream config that is dropped or behaves differently.
+pub fn check(table: &Table) -> Vec<String> {
+    let mut notes = Vec::new();
+    for key in table.keys() {
+        if !UPSTREAM_SETTINGS.contains(&key.as_str()) {
+            notes.push(format!("unknown setting '{key}' dropped"));
+        }
+    }
+    for layer in UPSTREAM_LAYERS {
+        match table.get(layer) {
+            Some(Value::Array(_)) => {
+                for (i, button) in buttons(table, layer) {
+                    check_button(layer, i, button, &mut notes);
+                }
+            }
+            Some(_) => notes.push(format!("{layer} is not an array of buttons")),
+            None => notes.push(format!("{layer} not set; this fork's default layer is used")),
+        }
+    }
+    notes
+}
+
+/// Converts an upstream config.toml into this fork's config.toml, keeping only the
+/// settings and button fields both understand.
+pub fn translate(table: &Table) -> Table {
+    let mut out = Table::new();
+    for (key, value) in table {
+        if !UPSTREAM_SETTINGS.contains(&key.as_str()) {
+            continue;
+        }
+        if !UPSTREAM_LAYERS.contains(&key.as_str()) {
+            out.insert(key.clone(), value.clone());
+            continue;
+        }
+        let layer = buttons(table, key)
+            .map(|(_, button)| {
+                let mut converted = Table::new();
+                for (field, value) in button {
+                    match field.as_str() {
+                        "Svg" => converted.insert("Icon".into(), value.clone()),
+                        f if UPSTREAM_BUTTON_FIELDS.contains(&f) => {
+                            converted.insert(field.clone(), value.clone())
+                        }
+                        _ => None,
+                    };
+                }
+                Value::Table(converted)
+            })
+            .collect();
+        out.insert(key.clone(), Value::Array(layer));
+    }
+    out
+}
+
+fn default_output_dir() -> Option<PathBuf> {
+    std::env::var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| std::env::var("HOME").map(|h| Path::new(&h).join(".config")))
+        .ok()
+        .map(|base| base.join("tiny-dfr"))
+}
+
+// Actions upstream rejects and this fork looks up in commands.toml
+fn command_actions(table: &Table) -> Vec<String> {
+    let mut names: Vec<String> = UPSTREAM_LAYERS
+        .iter()
+        .flat_map(|layer| buttons(table, layer))
+        .filter_map(|(_, button)| button.get("Action").and_then(Value::as_str))
+        .filter(|name| matches!(ButtonAction::from_name(name), Ok(ButtonAction::Command(_))))
+        .map(String::from)
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// The files of this fork's layout for an upstream config, as (file name, contents).
+/// Upstream has no commands, expandables or Hyprland layers, so those start out
+/// empty but for a placeholder per action that names a command.
+fn migrated_files(input: &Path, table: &Table) -> Result<Vec<(&'static str, String)>> {
+    let config = format!(
+        "# tiny-dfr config, migrated from upstream {}\n# Settings missing here fall back to /usr/share/tiny-dfr/config.toml\n\n{}",
+        input.display(),
+        toml::to_string(&translate(table))?
+    );
+    let mut commands = String::from("# tiny-dfr commands: button actions that aren't key names run these shell commands\n");
+    for name in command_actions(table) {
+        commands.push_str(&format!("# {} = \"...\"\n", Value::String(name)));
+    }
+    let expandables = "# tiny-dfr expandables: `Expand_<name>` actions open these layers\n# See /usr/share/tiny-dfr/expandables.toml for examples\n".to_string();
+    let hyprland = "# tiny-dfr Hyprland layers, chosen by the focused window's class\n# See /usr/share/tiny-dfr/hyprland.toml for examples\n".to_string();
+    Ok(vec![
+        ("config.toml", config),
+        ("commands.toml", commands),
+        ("expandables.toml", expandables),
+        ("hyprland.toml", hyprland),
+    ])
+}
+
+fn run_migrate(input: &Path, output_dir: &Path, force: bool) -> Result<Vec<String>> {
+    let table = toml::from_str::<Table>(&fs::read_to_string(input)?)
+        .map_err(|e| anyhow!("{} is not valid TOML: {}", input.display(), e))?;
+    let notes = check(&table);
+    let files = migrated_files(input, &table)?;
+    if !force {
+        if let Some((name, _)) = files.iter().find(|(name, _)| output_dir.join(name).exists()) {
+            return Err(anyhow!("{} already exists, pass --force to overwrite it", output_dir.join(name).display()));
+        }
+    }
+    fs::create_dir_all(output_dir)?;
+    for (name, contents) in files {
+        let output = output_dir.join(name);
+        fs::write(&output, contents)?;
+        println!("Wrote {}", output.display());
+    }
+    Ok(notes)
+}
+
+/// `tiny-dfr migrate <upstream config.toml> [output dir] [--force]`
+pub fn migrate_main(args: &[String]) -> i32 {
+    let force = args.iter().any(|a| a == "--force");
+    let positional: Vec<&String> = args.iter().filter(|a| !a.starts_with("--")).collect();
+    let Some(input) = positional.first() else {
+        eprintln!("usage: tiny-dfr migrate <upstream config.toml> [output dir] [--force]");
+        return 2;
+    };
+    let Some(output_dir) = positional.get(1).map(PathBuf::from).or_else(default_output_dir) else {
+        eprintln!("Cannot determine the output directory, pass it explicitly");
+        return 2;
+    };
+    match run_migrate(Path::new(input), &output_dir, force) {
+        Ok(notes) if notes.is_empty() => {
+            println!("Everything was translated");
+            0
+        }
+        Ok(notes) => {
+            println!("Could not translate everything:");
+            for note in notes {
+                println!("  - {note}");
+            }
+            0
+        }
+        Err(e) => {
+            eprintln!("Migration failed: {e}");
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    const UPSTREAM: &str = r#"
+        MediaLayerDefault = false
+        FontTemplate = ":bold"
+        ShowBatteryWarning = true
+        PrimaryLayerKeys = [
+            { Svg = "search", Action = "F1" },
+            { Text = "Copy", Action = ["LeftCtrl", "C"] },
+            { Text = "Oops", Action = "Screenshot", Color = 0.5 },
+        ]
+    "#;
+
+    #[test]
+    fn test_detects_upstream_format() {
+        let upstream = toml::from_str::<Table>(UPSTREAM).unwrap();
+        assert!(!is_upstream_format(&upstream));
+        let parse = |config: &str| toml::from_str::<Table>(config).unwrap();
+        assert!(is_upstream_format(&parse("FontTemplate = \":bold\"\nPrimaryLayerKeys = [{ Svg = \"search\", Action = \"F1\" }]")));
+        assert!(is_upstream_format(&parse("MediaLayerKeys = [{ Text = \"Copy\", Action = [\"LeftCtrl\", \"C\"] }]")));
+        // Keys both forks share are no sign of an upstream file
+        assert!(!is_upstream_format(&parse("ActiveBrightness = 200")));
+        assert!(!is_upstream_format(&parse("PrimaryLayerKeys = [{ Icon = \"search\", Action = \"F1\" }]")));
+        assert!(!is_upstream_format(&parse("MediaLayerKeys = [{ Text = \"A\", Action = [\"F1\"] }, { Text = \"B\", Action = \"Expand_Apps\" }]")));
+    }
+
+    #[test]
+    fn test_migrate_writes_layout() {
+        let dir = TempDir::new("migrate");
+        let input = dir.write("upstream.toml", UPSTREAM);
+        let output = dir.join("out");
+        let notes = run_migrate(&input, &output, false).unwrap();
+        assert!(!notes.is_empty());
+        for file in ["config.toml", "commands.toml", "expandables.toml", "hyprland.toml"] {
+            let contents = fs::read_to_string(output.join(file)).unwrap();
+            toml::from_str::<Table>(&contents).unwrap();
+        }
+        let commands = fs::read_to_string(output.join("commands.toml")).unwrap();
+        assert!(commands.contains("# \"Screenshot\" = "));
+        let error = run_migrate(&input, &output, false).unwrap_err().to_string();
+        assert!(error.contains("already exists"));
+        run_migrate(&input, &output, true).unwrap();
+    }
+
+    #[test]
+    fn test_translate_and_report() {
+        let upstream = toml::from_str::<Table>(UPSTREAM).unwrap();
+        let notes = check(&upstream);
+        assert!(notes.iter().any(|n| n.contains("ShowBatteryWarning")));
+        assert!(notes.iter().any(|n| n.contains("'Screenshot' is not a key name")));
+        assert!(notes.iter().any(|n| n.contains("'Color'")));
+
+        let fork = translate(&upstream);
+        assert!(fork.get("ShowBatteryWarning").is_none());
+        let layer = fork["PrimaryLayerKeys"].as_array().unwrap();
+        assert_eq!(layer[0].get("Icon").and_then(Value::as_str), Some("search"));
+        assert!(layer[2].get("Color").is_none());
+    }
+}
@@ -0,0 +1,386 @@
+use anyhow::{anyhow, Result};
+use crate::config::wildcard_match;
+use chrono::{Local, Timelike};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// A parsed `VisibleWhen` expression, e.g. `ac && class(firefox*)` or
+/// `!device(HDMI-*) || hours(9-17)`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Ac,
+    Battery,
+    /// Focused Hyprland window class, `*` and `?` wildcards allowed
+    Class(String),
+    File(String),
+    /// A toggle by name, true while it is on; probed toggles follow their script
+    Toggle(String),
+    /// Minutes since midnight, start inclusive and end exclusive; may wrap past midnight
+    Hours(u32, u32),
+    /// Connected display connector or input device name, wildcards allowed
+    Device(String),
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+/// Everything a condition can depend on.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Facts {
+    pub on_ac: bool,
+    pub class: String,
+    pub minute_of_day: u32,
+    pub devices: Vec<String>,
+    /// Watched files that exist
+    pub files: HashSet<String>,
+    /// Toggles that are on
+    pub toggles: HashSet<String>,
+}
+
+impl Condition {
+    pub fn parse(expr: &str) -> Result<Condition> {
+        let mut parser = Parser { s: expr.as_bytes(), pos: 0 };
+        let cond = parser.or()?;
+        parser.skip_ws();
+        if parser.pos < parser.s.len() {
+            return Err(anyhow!("unexpected '{}' at {}", &expr[parser.pos..], parser.pos + 1));
+        }
+        Ok(cond)
+    }
+
+    pub fn eval(&self, facts: &Facts) -> bool {
+        match self {
+            Condition::Ac => facts.on_ac,
+            Condition::Battery => !facts.on_ac,
+            Condition::Class(pattern) => wildcard_match(pattern, &facts.class),
+            Condition::File(path) => facts.files.contains(path),
+            Condition::Toggle(name) => facts.toggles.contains(name),
+            Condition::Hours(start, end) => {
+                let m = facts.minute_of_day;
+                if start <= end {
+                    *start <= m && m < *end
+                } else {
+                    m >= *start || m < *end
+                }
+            }
+            Condition::Device(pattern) => facts.devices.iter().any(|d| wildcard_match(pattern, d)),
+            Condition::Not(c) => !c.eval(facts),
+            Condition::And(a, b) => a.eval(facts) && b.eval(facts),
+            Condition::Or(a, b) => a.eval(facts) || b.eval(facts),
+        }
+    }
+
+    fn collect(&self, files: &mut HashSet<String>) {
+        match self {
+            Condition::File(path) => {
+                files.insert(path.clone());
+            }
+            Condition::Not(c) => c.collect(files),
+            Condition::And(a, b) | Condition::Or(a, b) => {
+                a.collect(files);
+                b.collect(files);
+            }
+            _ => {}
+        }
+    }
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_ws(&mut self) {
+        while self.s.get(self.pos).is_some_and(u8::is_ascii_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_ws();
+        if self.s[self.pos..].starts_with(token.as_bytes()) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> Result<Condition> {
+        let mut cond = self.and()?;
+        while self.eat("||") {
+            cond = Condition::Or(Box::new(cond), Box::new(self.and()?));
+        }
+        Ok(cond)
+    }
+
+    fn and(&mut self) -> Result<Condition> {
+        let mut cond = self.unary()?;
+        while self.eat("&&") {
+            cond = Condition::And(Box::new(cond), Box::new(self.unary()?));
+        }
+        Ok(cond)
+    }
+
+    fn unary(&mut self) -> Result<Condition> {
+        if self.eat("!") {
+            return Ok(Condition::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let cond = self.or()?;
+            if !self.eat(")") {
+                return Err(anyhow!("missing ')' at {}", self.pos + 1));
+            }
+            return Ok(cond);
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Condition> {
+        self.skip_ws();
+        let start = self.pos;
+        while self.s.get(self.pos).is_some_and(|c| c.is_ascii_alphabetic()) {
+            self.pos += 1;
+        }
+        let name = String::from_utf8_lossy(&self.s[start..self.pos]).to_string();
+        if name.is_empty() {
+            return Err(anyhow!("expected a condition at {}", start + 1));
+        }
+        match name.as_str() {
+            "ac" => return Ok(Condition::Ac),
+            "battery" => return Ok(Condition::Battery),
+            _ => {}
+        }
+        if !self.eat("(") {
+            return Err(anyhow!("unknown condition '{}'", name));
+        }
+        let arg = self.argument()?;
+        match name.as_str() {
+            "class" => Ok(Condition::Class(arg)),
+            "file" => Ok(Condition::File(arg)),
+            "toggle" => Ok(Condition::Toggle(arg)),
+            "device" => Ok(Condition::Device(arg)),
+            "hours" => parse_hours(&arg),
+            _ => Err(anyhow!("unknown condition '{}'", name)),
+        }
+    }
+
+    // A quoted string or anything up to the closing parenthesis, which is consumed
+    fn argument(&mut self) -> Result<String> {
+        self.skip_ws();
+        let arg = if self.s.get(self.pos) == Some(&b'"') {
+            let start = self.pos + 1;
+            let len = self.s[start..].iter().position(|&c| c == b'"').ok_or_else(|| anyhow!("unterminated string"))?;
+            self.pos = start + len + 1;
+            String::from_utf8_lossy(&self.s[start..start + len]).to_string()
+        } else {
+            let start = self.pos;
+            let len = self.s[start..].iter().position(|&c| c == b')').ok_or_else(|| anyhow!("missing ')'"))?;
+            self.pos = start + len;
+            String::from_utf8_lossy(&self.s[start..start + len]).trim().to_string()
+        };
+        if !self.eat(")") {
+            return Err(anyhow!("missing ')' at {}", self.pos + 1));
+        }
+        Ok(arg)
+    }
+}
+
+// "9-17" or "22:30-6:00"
+fn parse_hours(arg: &str) -> Result<Condition> {
+    let time = |s: &str| -> Result<u32> {
+        let (h, m) = s.trim().split_once(':').unwrap_or((s.trim(), "0"));
+        let (h, m): (u32, u32) = (h.parse()?, m.parse()?);
+        if h > 24 || m > 59 || h * 60 + m > 24 * 60 {
+            return Err(anyhow!("{} is not a time of day", s.trim()));
+        }
+        Ok(h * 60 + m)
+    };
+    let (start, end) = arg.split_once('-').ok_or_else(|| anyhow!("hours need a range like 9-17"))?;
+    Ok(Condition::Hours(time(start)?, time(end)?))
+}
+
+#[derive(Default)]
+struct WatchState {
+    files: HashSet<String>,
+    active: bool,
+    facts: Facts,
+    // Bumped whenever the polled facts change
+    generation: u64,
+}
+
+/// What conditions were last evaluated against, cheap to compare on every pass of
+/// the main loop so the facts are only gathered when something moved.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stamp {
+    generation: u64,
+    class: String,
+    minute_of_day: u32,
+}
+
+// Facts polled in the background, and what to poll for
+static WATCH_STATE: std::sync::LazyLock<Arc<Mutex<WatchState>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(WatchState::default())));
+
+/// Polls power, devices and the files conditions refer to.
+pub struct VisibilityMonitor {
+    _handle: thread::JoinHandle<()>,
+}
+
+impl VisibilityMonitor {
+    pub fn new() -> Self {
+        let handle = thread::spawn(move || {
+            Self::monitor_loop();
+        });
+
+        VisibilityMonitor { _handle: handle }
+    }
+
+    fn monitor_loop() {
+        loop {
+            let watched = WATCH_STATE.lock().ok().filter(|state| state.active).map(|state| state.files.clone());
+            if let Some(files) = watched {
+                let facts = Facts {
+                    on_ac: crate::battery_monitor::on_ac(),
+                    devices: connected_devices(),
+                    files: files.into_iter().filter(|f| Path::new(f).exists()).collect(),
+                    ..Default::default()
+                };
+                if let Ok(mut state) = WATCH_STATE.lock() {
+                    if state.facts != facts {
+                        state.facts = facts;
+                        state.generation += 1;
+                    }
+                }
+            }
+            thread::sleep(Duration::from_secs(2));
+        }
+    }
+}
+
+/// Starts polling for what `cond` depends on.
+pub fn watch(cond: &Condition) {
+    if let Ok(mut state) = WATCH_STATE.lock() {
+        let state = &mut *state;
+        cond.collect(&mut state.files);
+        if !state.active {
+            state.active = true;
+            state.facts.on_ac = crate::battery_monitor::on_ac();
+            state.facts.devices = connected_devices();
+        }
+    }
+}
+
+/// Forgets watched files, before the config is reloaded.
+pub fn clear_watches() {
+    if let Ok(mut state) = WATCH_STATE.lock() {
+        state.files.clear();
+    }
+}
+
+/// Changes whenever `current_facts` would.
+pub fn stamp() -> Stamp {
+    Stamp {
+        generation: WATCH_STATE.lock().map(|state| state.generation).unwrap_or_default(),
+        class: crate::hyprland::cached_window_class().unwrap_or_default(),
+        minute_of_day: minute_of_day(),
+    }
+}
+
+/// The latest polled facts with the focused window, time of day and toggles filled in.
+pub fn current_facts() -> Facts {
+    let mut facts = WATCH_STATE.lock().map(|state| state.facts.clone()).unwrap_or_default();
+    facts.class = crate::hyprland::cached_window_class().unwrap_or_default();
+    facts.minute_of_day = minute_of_day();
+    facts.toggles = crate::toggle::on_names();
+    facts
+}
+
+fn minute_of_day() -> u32 {
+    let now = Local::now();
+    now.hour() * 60 + now.minute()
+}
+
+// Connected DRM connectors ("HDMI-A-1") and input device names, which include
+// Bluetooth headsets through their AVRCP controls
+fn connected_devices() -> Vec<String> {
+    let mut devices = Vec::new();
+    if let Ok(entries) = fs::read_dir("/sys/class/drm") {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some((_, connector)) = name.split_once('-') else {
+                continue;
+            };
+            if fs::read_to_string(entry.path().join("status")).is_ok_and(|s| s.trim() == "connected") {
+                devices.push(connector.to_string());
+            }
+        }
+    }
+    if let Ok(input) = fs::read_to_string("/proc/bus/input/devices") {
+        for line in input.lines() {
+            if let Some(name) = line.strip_prefix("N: Name=") {
+                devices.push(name.trim_matches('"').to_string());
+            }
+        }
+    }
+    devices
+}
+
+/// Runs `command` as the desktop user and waits for it.
+pub fn probe(command: &str) -> Option<Output> {
+    let mut cmd = match crate::user_cache::get_cached_user_environment() {
+        Some(env) => {
+            let mut cmd = Command::new("sudo");
+            cmd.args(["-u", &env.username, "env"])
+                .arg(format!("XDG_RUNTIME_DIR={}", env.runtime_dir))
+                .arg(format!("DBUS_SESSION_BUS_ADDRESS=unix:path={}/bus", env.runtime_dir))
+                .arg(format!("PATH={}", env.enhanced_path))
+                .args(["sh", "-c", command]);
+            cmd
+        }
+        None => {
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", command]);
+            cmd
+        }
+    };
+    cmd.stdin(Stdio::null()).stderr(Stdio::null()).output().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conditions() {
+        let cond = Condition::parse("ac && !class(firefox*) || hours(22-6)").unwrap();
+        let mut facts = Facts { on_ac: true, class: "kitty".into(), minute_of_day: 12 * 60, ..Default::default() };
+        assert!(cond.eval(&facts));
+        facts.class = "firefox-esr".into();
+        assert!(!cond.eval(&facts));
+        facts.minute_of_day = 23 * 60;
+        assert!(cond.eval(&facts));
+
+        let cond = Condition::parse("battery && (device(HDMI-*) || file(\"/tmp/a b\"))").unwrap();
+        facts.on_ac = false;
+        assert!(!cond.eval(&facts));
+        facts.devices.push("HDMI-A-1".into());
+        assert!(cond.eval(&facts));
+        let cond = Condition::parse("toggle(dnd)").unwrap();
+        assert!(!cond.eval(&facts));
+        facts.toggles.insert("dnd".into());
+        assert!(cond.eval(&facts));
+        assert_eq!(Condition::parse("hours(9:30-17)").unwrap(), Condition::Hours(570, 1020));
+
+        assert!(Condition::parse("ac &&").is_err());
+        assert!(Condition::parse("charging").is_err());
+        assert!(Condition::parse("(ac").is_err());
+        assert!(Condition::parse("hours(25-3)").is_err());
+    }
+}